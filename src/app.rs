@@ -1,14 +1,40 @@
+use crate::layout::contacts::actions::edit_peer::PeerDraft;
 use crate::layout::menu_bar::NavigationItems;
+use crate::layout::rooms::actions::create_room::RoomDraft;
+use crate::layout::rooms::actions::export_messages::ExportDraft;
 use crate::layout::rooms::message_settings_bar::RoomView;
+use crate::layout::rooms::search_bar::SearchState;
+use crate::layout::rooms::views::message_table::MessageTableState;
 use crate::layout::ui::display;
-use crate::utils::config::{Peer, Room};
+use crate::utils::ack::{create_ack_message, send_ack_batch, send_read_receipt, AckBatcher};
+use crate::utils::config::{
+    AppConfigManager, BundleOverhead, Peer, RetransmissionPolicy, Room, RoutingConfig,
+};
+use crate::utils::export::{export_records, ExportFilter, ExportRecord};
+use crate::utils::file_transfer::{
+    chunk_envelope, chunk_size, file_label, mime_type, ChunkRejection, FileChunk, FileTransfers,
+    OutgoingChunk, TransferState, LONG_TEXT_NAME, TEXT_MIME,
+};
+use crate::utils::history::HistoryStore;
 use crate::utils::message::{ChatMessage, MessageStatus};
-use crate::utils::prediction_config::PredictionConfig;
-use crate::utils::socket::SocketObserver;
-use chrono::{DateTime, Utc};
+use crate::utils::outbox::{Outbox, OutboxControl, OutboxEntry};
+use crate::utils::prediction_config::{
+    ContactPlanSource, FragmentPlan, PredictionConfig, Reachability, MIN_FRAGMENT_SIZE,
+};
+use crate::utils::presence::Presence;
+use crate::utils::proto::{encoded_size, generate_uuid};
+use crate::utils::rooms::{RoomAction, RoomStore};
+use crate::utils::search::{SearchHit, SearchIndex, SearchQuery};
+use crate::utils::socket::{
+    DedupCache, DefaultSocketController, Endpoint, PathArrival, SocketObserver,
+};
+use chrono::{DateTime, Duration, Utc};
 use eframe::egui;
 use std::cmp::Ordering;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
@@ -18,33 +44,44 @@ pub enum AppEvent {
     Received(String),
 }
 
+/// Exchanges with a peer, over every room
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PeerStats {
+    /// Latest message sent to or received from the peer
+    pub last_message: Option<DateTime<Utc>>,
+    /// Messages handed to the transport for the peer
+    pub sent: usize,
+    /// Sent messages the peer acknowledged
+    pub acked: usize,
+}
+
+impl PeerStats {
+    pub fn ack_rate(&self) -> Option<f32> {
+        (self.sent > 0).then(|| self.acked as f32 / self.sent as f32)
+    }
+}
+
 #[derive(PartialEq, Eq, Clone)]
 pub enum SortStrategy {
     Standard,
     Relative(Peer),
 }
 
+/// Transmission time and reception time (or transmission time while unknown) of a message
+fn sort_times(message: &ChatMessage) -> (DateTime<Utc>, DateTime<Utc>) {
+    let tx = message.shipment_status.tx();
+    (tx, message.shipment_status.rx().unwrap_or(tx))
+}
+
 fn standard_cmp(a: &ChatMessage, b: &ChatMessage) -> Ordering {
-    let (tx_a, rx_a) = match &a.shipment_status {
-        MessageStatus::Sent(tx, _rx) => (tx, tx),
-        MessageStatus::Received(tx, rx) => (tx, rx),
-    };
-    let (tx_b, rx_b) = match &b.shipment_status {
-        MessageStatus::Sent(tx, _rx) => (tx, tx),
-        MessageStatus::Received(tx, rx) => (tx, rx),
-    };
-    tx_a.cmp(tx_b).then(rx_a.cmp(rx_b))
+    let (tx_a, rx_a) = sort_times(a);
+    let (tx_b, rx_b) = sort_times(b);
+    tx_a.cmp(&tx_b).then(rx_a.cmp(&rx_b))
 }
 
 fn relative_cmp(a: &ChatMessage, b: &ChatMessage, ctx_peer_uuid: &str) -> Ordering {
-    let (tx_a, rx_a) = match &a.shipment_status {
-        MessageStatus::Sent(tx, _rx) => (tx, tx),
-        MessageStatus::Received(tx, rx) => (tx, rx),
-    };
-    let (tx_b, rx_b) = match &b.shipment_status {
-        MessageStatus::Sent(tx, _rx) => (tx, tx),
-        MessageStatus::Received(tx, rx) => (tx, rx),
-    };
+    let (tx_a, rx_a) = sort_times(a);
+    let (tx_b, rx_b) = sort_times(b);
     let anchor_a = if a.sender.uuid == ctx_peer_uuid {
         rx_a
     } else {
@@ -55,7 +92,7 @@ fn relative_cmp(a: &ChatMessage, b: &ChatMessage, ctx_peer_uuid: &str) -> Orderi
     } else {
         tx_b
     };
-    anchor_a.cmp(anchor_b)
+    anchor_a.cmp(&anchor_b)
 }
 
 pub struct ChatModel {
@@ -63,11 +100,53 @@ pub struct ChatModel {
     pub localpeer: Peer,
     pub peers: Vec<Peer>,
     pub rooms: Vec<Room>,
-    pub messages: Vec<ChatMessage>,
+    // Persists the rooms created, joined or left at runtime
+    room_store: RoomStore,
+    /// Messages of each room, by room UUID
+    pub messages: HashMap<String, Vec<ChatMessage>>,
+    /// Every copy received per message UUID, first arrival first, for the latest messages
+    pub path_arrivals: DedupCache,
     observers: Vec<Arc<Mutex<dyn ModelObserver>>>,
     pub prediction_config: Option<PredictionConfig>,
+    pub outbox: Outbox,
+    /// Used for the rooms without their own policy
+    pub retransmission: RetransmissionPolicy,
+    /// Used for the peers without their own setting
+    pub read_receipts: bool,
+    // Received messages already displayed to the local user
+    displayed: HashSet<String>,
+    /// Aggregates the read receipts when ACK batching is enabled
+    pub ack_batcher: Option<Arc<Mutex<AckBatcher>>>,
+    /// Files sent and received, shown as messages of their room
+    pub transfers: FileTransfers,
+    /// Presence announced to the peers
+    pub presence: Presence,
+    // Last presence announced by each peer, with the time it was announced
+    peer_presence: HashMap<String, (Presence, DateTime<Utc>)>,
+    // Reachability of each peer, with the time it was computed
+    reachability: HashMap<String, (DateTime<Utc>, Option<Reachability>)>,
+    /// Configuration file receiving the peers added, edited or deleted
+    pub config_path: PathBuf,
+    /// Keeps the messages across restarts
+    pub history: Option<HistoryStore>,
+    /// Reloaded when the file changes or on demand
+    pub contact_plan: Option<ContactPlanSource>,
+    /// Outcome of the last contact plan reload
+    pub contact_plan_status: Option<String>,
+    /// Bytes added around each serialized message, counted in the predictions
+    pub bundle_overhead: BundleOverhead,
+    // Messages changed since the last history flush
+    unsaved: HashSet<String>,
+    search_index: SearchIndex,
+    // Messages changed since they were last indexed
+    unindexed: HashSet<String>,
+    // Bumped on every change of the messages or the rooms
+    revision: u64,
 }
 
+// How long a computed reachability is shown before routing again
+const REACHABILITY_TTL: Duration = Duration::seconds(10);
+
 pub enum MessageDirection {
     Sent,
     Received,
@@ -77,17 +156,40 @@ impl ChatModel {
     pub fn new(
         peers: Vec<Peer>,
         localpeer: Peer,
-        rooms: Vec<Room>,
+        room_store: RoomStore,
         prediction_config: Option<PredictionConfig>,
+        outbox: Outbox,
+        retransmission: RetransmissionPolicy,
+        read_receipts: bool,
     ) -> Self {
         Self {
             sort_strategy: SortStrategy::Standard,
             localpeer,
             peers,
-            rooms,
-            messages: Vec::new(),
+            rooms: room_store.rooms(),
+            room_store,
+            messages: HashMap::new(),
+            path_arrivals: DedupCache::default(),
             observers: Vec::new(),
             prediction_config,
+            outbox,
+            retransmission,
+            read_receipts,
+            displayed: HashSet::new(),
+            ack_batcher: None,
+            transfers: FileTransfers::default(),
+            presence: Presence::default(),
+            peer_presence: HashMap::new(),
+            reachability: HashMap::new(),
+            config_path: PathBuf::new(),
+            history: None,
+            contact_plan: None,
+            contact_plan_status: None,
+            bundle_overhead: BundleOverhead::default(),
+            unsaved: HashSet::new(),
+            search_index: SearchIndex::default(),
+            unindexed: HashSet::new(),
+            revision: 0,
         }
     }
 
@@ -101,20 +203,368 @@ impl ChatModel {
         }
     }
 
-    pub fn add_message(&mut self, new_msg: ChatMessage, direction: MessageDirection) {
+    /// Add or replace `room`, and persist it
+    fn store_room(&mut self, room: Room) {
+        self.revision += 1;
+        self.room_store.save_room(&room);
+        match self.rooms.iter_mut().find(|r| r.uuid == room.uuid) {
+            Some(existing) => *existing = room,
+            None => self.rooms.push(room),
+        }
+    }
+
+    fn participant_peers(&self, room: &Room) -> Vec<Peer> {
+        self.peers
+            .iter()
+            .filter(|peer| room.participants.contains(&peer.uuid))
+            .cloned()
+            .collect()
+    }
+
+    /// Queue a room change for each of `receivers`, sent once their contact is open
+    fn queue_room_control(&mut self, action: RoomAction, room: &Room, receivers: &[Peer]) {
+        for receiver in receivers {
+            let control = OutboxControl::Room {
+                action,
+                room: room.clone(),
+            };
+            self.outbox.push(OutboxEntry::control(
+                receiver,
+                Some(room.uuid.clone()),
+                control,
+            ));
+        }
+    }
+
+    /// Create a room and invite its participants, returns its UUID
+    pub fn create_room(&mut self, name: &str, participants: Vec<String>) -> String {
+        let room = Room {
+            uuid: generate_uuid(),
+            name: name.to_string(),
+            participants,
+            retransmission: None,
+        };
+        let receivers = self.participant_peers(&room);
+        self.queue_room_control(RoomAction::Create, &room, &receivers);
+        let uuid = room.uuid.clone();
+        self.store_room(room);
+        uuid
+    }
+
+    /// Rename a room and change its participants, telling the ones removed from it
+    pub fn update_room(&mut self, room_uuid: &str, name: &str, participants: Vec<String>) {
+        let Some(mut room) = self.rooms.iter().find(|r| r.uuid == room_uuid).cloned() else {
+            return;
+        };
+        let removed = Room {
+            participants: room
+                .participants
+                .iter()
+                .filter(|uuid| !participants.contains(uuid))
+                .cloned()
+                .collect(),
+            ..room.clone()
+        };
+        let members_changed =
+            !removed.participants.is_empty() || participants.len() != room.participants.len();
+        let renamed = room.name != name;
+        room.name = name.to_string();
+        room.participants = participants;
+
+        // An invitation also carries the name, every participant learns the new list
+        let action = if members_changed {
+            Some(RoomAction::Invite)
+        } else if renamed {
+            Some(RoomAction::Rename)
+        } else {
+            None
+        };
+        if let Some(action) = action {
+            let receivers = self.participant_peers(&room);
+            self.queue_room_control(action, &room, &receivers);
+        }
+        let removed_peers = self.participant_peers(&removed);
+        self.queue_room_control(RoomAction::Remove, &room, &removed_peers);
+        self.store_room(room);
+    }
+
+    /// Tell the participants we leave the room, and forget it
+    pub fn leave_room(&mut self, room_uuid: &str) {
+        let Some(room) = self.rooms.iter().find(|r| r.uuid == room_uuid).cloned() else {
+            return;
+        };
+        let receivers = self.participant_peers(&room);
+        self.queue_room_control(RoomAction::Leave, &room, &receivers);
+        self.rooms.retain(|r| r.uuid != room_uuid);
+        self.revision += 1;
+        self.room_store.leave_room(room_uuid);
+    }
+
+    /// Apply a room change sent by a participant
+    pub fn apply_room_control(&mut self, sender_uuid: &str, action: RoomAction, room: Room) {
+        let Some(sender) = self.peers.iter().find(|p| p.uuid == sender_uuid).cloned() else {
+            println!("Room control from unknown peer {sender_uuid}");
+            return;
+        };
+        let existing = self.rooms.iter().find(|r| r.uuid == room.uuid).cloned();
+        // Rooms created at runtime always have a UUID, anything else must already be known
+        if existing.is_none() && uuid::Uuid::parse_str(&room.uuid).is_err() {
+            println!(
+                "Room control from {} with invalid room {}",
+                sender.name, room.uuid
+            );
+            return;
+        }
+        // Our participants are the other peers
+        let participants: Vec<String> = room
+            .participants
+            .iter()
+            .filter(|uuid| **uuid != self.localpeer.uuid)
+            .cloned()
+            .collect();
+
+        let event = match (action, existing) {
+            (RoomAction::Create | RoomAction::Invite, existing) => {
+                let joined = existing.is_none();
+                self.store_room(Room {
+                    participants,
+                    retransmission: existing.and_then(|r| r.retransmission),
+                    ..room.clone()
+                });
+                if joined {
+                    format!("{} added you to room {}", sender.name, room.name)
+                } else {
+                    format!("{} updated room {}", sender.name, room.name)
+                }
+            }
+            (RoomAction::Rename, Some(mut existing)) => {
+                let event = format!(
+                    "{} renamed room {} to {}",
+                    sender.name, existing.name, room.name
+                );
+                existing.name = room.name;
+                self.store_room(existing);
+                event
+            }
+            (RoomAction::Leave, Some(mut existing)) => {
+                existing.participants.retain(|uuid| *uuid != sender.uuid);
+                let event = format!("{} left room {}", sender.name, existing.name);
+                self.store_room(existing);
+                event
+            }
+            (RoomAction::Remove, Some(existing)) => {
+                self.rooms.retain(|r| r.uuid != existing.uuid);
+                self.revision += 1;
+                self.room_store.leave_room(&existing.uuid);
+                format!("{} removed you from room {}", sender.name, existing.name)
+            }
+            (_, None) => {
+                println!("{action:?} for unknown room {}", room.uuid);
+                return;
+            }
+        };
+        self.notify_observers(AppEvent::Received(event));
+    }
+
+    /// Change the local presence and announce it to every other peer through the outbox,
+    /// in place of an announce still waiting for the contact
+    pub fn set_presence(&mut self, presence: Presence) {
+        self.presence = presence;
+        let at = Utc::now().timestamp_millis();
+        let peers: Vec<Peer> = self
+            .peers
+            .iter()
+            .filter(|peer| peer.uuid != self.localpeer.uuid)
+            .cloned()
+            .collect();
+        for peer in &peers {
+            self.outbox.remove_controls(&peer.uuid, |control| {
+                matches!(control, OutboxControl::Presence { .. })
+            });
+            let control = OutboxControl::Presence { presence, at };
+            self.outbox.push(OutboxEntry::control(peer, None, control));
+        }
+    }
+
+    pub fn peer_presence(&self, peer_uuid: &str) -> Presence {
+        self.peer_presence
+            .get(peer_uuid)
+            .map(|(presence, _)| *presence)
+            .unwrap_or_default()
+    }
+
+    /// Apply a presence announced by a peer, ignoring the outdated ones
+    pub fn apply_presence(&mut self, sender_uuid: &str, presence: Presence, at: DateTime<Utc>) {
+        let Some(sender) = self.peers.iter().find(|p| p.uuid == sender_uuid).cloned() else {
+            println!("Presence from unknown peer {sender_uuid}");
+            return;
+        };
+        if let Some((_, known_at)) = self.peer_presence.get(sender_uuid) {
+            if *known_at > at {
+                return;
+            }
+        }
+        self.peer_presence
+            .insert(sender_uuid.to_string(), (presence, at));
+        self.notify_observers(AppEvent::Received(format!(
+            "{} is now {}",
+            sender.name,
+            presence.label()
+        )));
+    }
+
+    /// State of the contact to the first hop towards `peer`,
+    /// None when the peers are outside the contact plan
+    pub fn reachability(&mut self, peer: &Peer) -> Option<Reachability> {
+        let now = Utc::now();
+        if let Some((computed_at, reachability)) = self.reachability.get(&peer.uuid) {
+            if now - *computed_at < REACHABILITY_TTL {
+                return *reachability;
+            }
+        }
+        let config = self.prediction_config.as_ref()?;
+        let sender_ion_id = config.ion_node_for_peer(&self.localpeer)?;
+        let receiver_ion_id = config.ion_node_for_peer(peer)?;
+        let reachability = config.reachability(&sender_ion_id, &receiver_ion_id).ok();
+        self.reachability
+            .insert(peer.uuid.clone(), (now, reachability));
+        reachability
+    }
+
+    /// Add `peer`, or replace the one with the same UUID, and write the peers to the
+    /// configuration file
+    pub fn save_peer(&mut self, peer: Peer) -> io::Result<()> {
+        self.reachability.remove(&peer.uuid);
+        match self.peers.iter_mut().find(|p| p.uuid == peer.uuid) {
+            Some(existing) => *existing = peer,
+            None => self.peers.push(peer),
+        }
+        AppConfigManager::save_peer_list(&self.config_path, &self.peers)
+    }
+
+    /// Forget a peer and write the peers to the configuration file,
+    /// its messages and rooms are kept
+    pub fn delete_peer(&mut self, peer_uuid: &str) -> io::Result<()> {
+        self.peers.retain(|p| p.uuid != peer_uuid);
+        self.peer_presence.remove(peer_uuid);
+        self.reachability.remove(peer_uuid);
+        AppConfigManager::save_peer_list(&self.config_path, &self.peers)
+    }
+
+    pub fn peer_stats(&self, peer_uuid: &str) -> PeerStats {
+        let mut stats = PeerStats::default();
+        for message in self.messages.values().flatten() {
+            let time = if message.sender.uuid == peer_uuid {
+                Some(sort_times(message).1)
+            } else if let Some(recipient) =
+                message.recipients.iter().find(|r| r.peer.uuid == peer_uuid)
+            {
+                if recipient.attempts > 0 {
+                    stats.sent += 1;
+                    if recipient.status.rx().is_some() {
+                        stats.acked += 1;
+                    }
+                }
+                Some(message.shipment_status.tx())
+            } else {
+                None
+            };
+            stats.last_message = stats.last_message.max(time);
+        }
+        stats
+    }
+
+    /// The room holding only `peer`, created if needed, returns its UUID
+    pub fn direct_room(&mut self, peer: &Peer) -> String {
+        let existing = self
+            .rooms
+            .iter()
+            .find(|room| room.participants == [peer.uuid.clone()]);
+        match existing {
+            Some(room) => room.uuid.clone(),
+            None => self.create_room(&peer.name, vec![peer.uuid.clone()]),
+        }
+    }
+
+    /// Room used for the messages of an unknown room
+    pub fn default_room_uuid(&self) -> String {
+        self.rooms
+            .first()
+            .map_or("default".to_string(), |room| room.uuid.clone())
+    }
+
+    /// Peers taking part in `room_uuid`, every peer for a room without participant list
+    pub fn room_participants(&self, room_uuid: &str) -> Vec<Peer> {
+        match self.rooms.iter().find(|room| room.uuid == room_uuid) {
+            Some(room) if !room.participants.is_empty() => self
+                .peers
+                .iter()
+                .filter(|peer| room.participants.contains(&peer.uuid))
+                .cloned()
+                .collect(),
+            _ => self.peers.clone(),
+        }
+    }
+
+    /// Messages of `room_uuid`, sorted with the current strategy
+    pub fn room_messages(&self, room_uuid: &str) -> &[ChatMessage] {
+        self.messages
+            .get(room_uuid)
+            .map_or(&[], |messages| messages)
+    }
+
+    pub fn message(&self, uuid: &str) -> Option<&ChatMessage> {
+        self.messages.values().flatten().find(|m| m.uuid == uuid)
+    }
+
+    /// Revision of the messages and the rooms, views built from them are rebuilt when it changes
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn message_mut(&mut self, uuid: &str) -> Option<&mut ChatMessage> {
+        self.revision += 1;
+        self.unsaved.insert(uuid.to_string());
+        self.unindexed.insert(uuid.to_string());
+        self.messages
+            .values_mut()
+            .flatten()
+            .find(|m| m.uuid == uuid)
+    }
+
+    pub fn add_message(&mut self, mut new_msg: ChatMessage, direction: MessageDirection) {
+        // A retransmission the socket no longer remembers, e.g. received before a restart
+        if self.message(&new_msg.uuid).is_some() {
+            println!("Message {} already shown, dropping the copy", new_msg.uuid);
+            return;
+        }
+        if !self.rooms.iter().any(|room| room.uuid == new_msg.room_uuid) {
+            let room_uuid = self.default_room_uuid();
+            println!(
+                "Unknown room {} for message {}, using room {room_uuid}",
+                new_msg.room_uuid, new_msg.uuid
+            );
+            new_msg.room_uuid = room_uuid;
+        }
+
+        let messages = self.messages.entry(new_msg.room_uuid.clone()).or_default();
         let idx = match &self.sort_strategy {
-            SortStrategy::Standard => self
-                .messages
+            SortStrategy::Standard => messages
                 .binary_search_by(|msg| standard_cmp(msg, &new_msg))
                 .unwrap_or_else(|i| i),
-            SortStrategy::Relative(peer) => self
-                .messages
+            SortStrategy::Relative(peer) => messages
                 .binary_search_by(|msg| relative_cmp(msg, &new_msg, peer.uuid.as_str()))
                 .unwrap_or_else(|i| i),
         };
-        self.messages.insert(idx, new_msg.clone());
+        messages.insert(idx, new_msg.clone());
+        self.revision += 1;
+        self.unsaved.insert(new_msg.uuid.clone());
+        self.search_index.update(&new_msg);
 
         let event = match direction {
+            MessageDirection::Sent if new_msg.is_queued() => {
+                AppEvent::Sent("Message queued.".to_string())
+            }
             MessageDirection::Sent => AppEvent::Sent("Message sent.".to_string()),
             MessageDirection::Received => {
                 AppEvent::Received(format!("New message from {}", new_msg.sender.name))
@@ -123,14 +573,794 @@ impl ChatModel {
         self.notify_observers(event);
     }
 
+    /// Show messages of a previous run, or fixtures, without saving them again
+    pub fn load_messages(&mut self, messages: Vec<ChatMessage>) {
+        for mut message in messages {
+            if !self.rooms.iter().any(|room| room.uuid == message.room_uuid) {
+                message.room_uuid = self.default_room_uuid();
+            }
+            if message.sender.uuid != self.localpeer.uuid {
+                // Shown by the previous run, its read receipt is already sent
+                self.displayed.insert(message.uuid.clone());
+            }
+            self.search_index.update(&message);
+            self.messages
+                .entry(message.room_uuid.clone())
+                .or_default()
+                .push(message);
+        }
+        self.revision += 1;
+        self.sort_messages(self.sort_strategy.clone());
+    }
+
+    /// Append the messages changed since the last call to the history
+    pub fn flush_history(&mut self) {
+        if self.unsaved.is_empty() {
+            return;
+        }
+        let unsaved = std::mem::take(&mut self.unsaved);
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let changed: Vec<&ChatMessage> = self
+            .messages
+            .values()
+            .flatten()
+            .filter(|message| unsaved.contains(&message.uuid))
+            .collect();
+        history.append(&changed);
+    }
+
+    /// Timing records of the messages kept by `filter`, one per recipient
+    pub fn export_records(&self, filter: &ExportFilter) -> Vec<ExportRecord> {
+        let messages: Vec<&ChatMessage> = self.messages.values().flatten().collect();
+        export_records(&messages, &self.rooms, &self.localpeer, filter)
+    }
+
+    /// Messages matching `query`, latest first, at most `limit` of them,
+    /// with the number of matches
+    pub fn search(&mut self, query: &SearchQuery, limit: usize) -> (Vec<SearchHit>, usize) {
+        for uuid in std::mem::take(&mut self.unindexed) {
+            // Only the room of the message is scanned
+            let message = match self.search_index.room_of(&uuid) {
+                Some(room_uuid) => self
+                    .messages
+                    .get(room_uuid)
+                    .and_then(|messages| messages.iter().find(|m| m.uuid == uuid)),
+                None => self.message(&uuid),
+            };
+            if let Some(message) = message.cloned() {
+                self.search_index.update(&message);
+            }
+        }
+        self.search_index.search(query, limit)
+    }
+
+    /// Revision of the index used by the last search
+    pub fn search_revision(&self) -> u64 {
+        self.search_index.revision()
+    }
+
+    /// Whether messages changed since the search made at `revision`
+    pub fn search_outdated(&self, revision: u64) -> bool {
+        !self.unindexed.is_empty() || self.search_index.revision() != revision
+    }
+
     pub fn sort_messages(&mut self, strat: SortStrategy) {
         self.sort_strategy = strat;
 
-        match &self.sort_strategy {
-            SortStrategy::Standard => self.messages.sort_by(standard_cmp),
-            SortStrategy::Relative(for_peer) => self
-                .messages
-                .sort_by(|a, b| relative_cmp(a, b, for_peer.uuid.as_str())),
+        for messages in self.messages.values_mut() {
+            match &self.sort_strategy {
+                SortStrategy::Standard => messages.sort_by(standard_cmp),
+                SortStrategy::Relative(for_peer) => {
+                    messages.sort_by(|a, b| relative_cmp(a, b, for_peer.uuid.as_str()))
+                }
+            }
+        }
+    }
+
+    /// Add a message to the list and to the outbox, one entry per receiver,
+    /// it is sent by the next flush
+    pub fn queue_message(
+        &mut self,
+        mut msg: ChatMessage,
+        receivers: &[Peer],
+        pbat: bool,
+        redundant: bool,
+    ) {
+        let queued = MessageStatus::Queued(msg.shipment_status.tx());
+        msg.transition(queued.clone());
+        for receiver in receivers {
+            msg.add_recipient(receiver, queued.clone(), 0);
+            self.outbox
+                .push(OutboxEntry::new(&msg, receiver, pbat, redundant));
+        }
+        self.add_message(msg, MessageDirection::Sent);
+    }
+
+    /// Fragments of a `size` bytes transfer to `receiver`, sized to the contact A-SABR plans,
+    /// None for the peers outside the contact plan.
+    /// Each fragment is serialized with `envelope` bytes of other fields.
+    pub fn fragment_plan(
+        &self,
+        receiver: &Peer,
+        size: usize,
+        envelope: usize,
+    ) -> Option<FragmentPlan> {
+        let config = self.prediction_config.as_ref()?;
+        let sender_ion_id = config.ion_node_for_peer(&self.localpeer)?;
+        let receiver_ion_id = config.ion_node_for_peer(receiver)?;
+        match config.plan_fragments(
+            &sender_ion_id,
+            &receiver_ion_id,
+            size,
+            chunk_size(),
+            envelope,
+            &self.bundle_overhead,
+        ) {
+            Ok(plan) => Some(plan),
+            Err(e) => {
+                self.notify_observers(AppEvent::Error(format!(
+                    "No fragment plan for {}: {e}",
+                    receiver.name
+                )));
+                None
+            }
+        }
+    }
+
+    /// Hand `msg` to the file transfers, to be sent in chunks like a file, when it is too big
+    /// for a single bundle to `receiver`. The outbox entry is kept, for a restart, until every
+    /// chunk is acknowledged; returns false if a single bundle carries the message.
+    pub fn fragment_outbox_entry(
+        &mut self,
+        entry: &OutboxEntry,
+        msg: &ChatMessage,
+        receiver: &Peer,
+    ) -> bool {
+        // A retransmission keeps the form of the first transmission
+        if entry.transmissions > 0 {
+            return false;
+        }
+        let size = encoded_size(msg);
+        let data = msg.text.clone().into_bytes();
+        let envelope = chunk_envelope(msg, LONG_TEXT_NAME, TEXT_MIME, data.len());
+        let plan = self.fragment_plan(receiver, data.len(), envelope);
+        if size <= chunk_size() && plan.is_none_or(|plan| plan.size + envelope >= size) {
+            return false;
+        }
+
+        let fragment_size = plan.map_or(chunk_size(), |plan| plan.size);
+        self.outbox
+            .fragment(&entry.message_uuid, &entry.receiver_uuid, fragment_size);
+        self.transfers.start_transfer(
+            &msg.uuid,
+            &msg.room_uuid,
+            LONG_TEXT_NAME,
+            TEXT_MIME,
+            data,
+            vec![(
+                receiver.clone(),
+                fragment_size,
+                plan.map(|plan| plan.completion),
+            )],
+        );
+        true
+    }
+
+    /// Send the file at `path` to `receivers` in chunks, shown as a message of the room
+    pub fn send_file(
+        &mut self,
+        path: &Path,
+        room_uuid: &str,
+        receivers: &[Peer],
+    ) -> io::Result<()> {
+        let data = fs::read(path)?;
+        let filename = path.file_name().map_or("file".to_string(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+        let mime_type = mime_type(&filename);
+        let msg = ChatMessage {
+            uuid: generate_uuid(),
+            response: None,
+            sender: self.localpeer.clone(),
+            text: file_label(&filename, mime_type, data.len() as u64),
+            shipment_status: MessageStatus::Draft(Utc::now()),
+            endpoint: None,
+            attempts: 0,
+            history: Vec::new(),
+            room_uuid: room_uuid.to_string(),
+            recipients: Vec::new(),
+            routing: None,
+            predicted_ack: None,
+        };
+        self.start_transfer(msg, &filename, mime_type, data, receivers);
+        Ok(())
+    }
+
+    fn start_transfer(
+        &mut self,
+        mut msg: ChatMessage,
+        filename: &str,
+        mime_type: &str,
+        data: Vec<u8>,
+        receivers: &[Peer],
+    ) {
+        let envelope = chunk_envelope(&msg, filename, mime_type, data.len());
+        let recipients = receivers
+            .iter()
+            .map(|peer| {
+                let plan = self.fragment_plan(peer, data.len(), envelope);
+                let fragment_size = plan.map_or(chunk_size(), |plan| plan.size);
+                (
+                    peer.clone(),
+                    fragment_size,
+                    plan.map(|plan| plan.completion),
+                )
+            })
+            .collect();
+        self.transfers.start_transfer(
+            &msg.uuid,
+            &msg.room_uuid,
+            filename,
+            mime_type,
+            data,
+            recipients,
+        );
+
+        let queued = MessageStatus::Queued(msg.shipment_status.tx());
+        msg.transition(queued.clone());
+        for receiver in receivers {
+            msg.add_recipient(receiver, queued.clone(), 0);
+        }
+        self.add_message(msg, MessageDirection::Sent);
+    }
+
+    /// File chunks to send now, as many as the open contact to the next hop of their
+    /// recipient takes
+    pub fn release_file_chunks(&mut self, now: DateTime<Utc>) -> Vec<OutgoingChunk> {
+        let overhead = self.bundle_overhead;
+        let bundle_size = |payload: usize| overhead.bundle_size(payload);
+        let link_volume = self
+            .transfers
+            .pending_volumes(bundle_size)
+            .into_iter()
+            .filter_map(|(peer, pending)| {
+                let volume = self.next_hop_volume(&peer, bundle_size(MIN_FRAGMENT_SIZE), pending);
+                volume.map(|volume| (peer.uuid, volume))
+            })
+            .collect();
+        let (chunks, expired) =
+            self.transfers
+                .take_due(now, &self.localpeer.uuid, &link_volume, bundle_size);
+
+        for chunk in &chunks {
+            let Some(message) = self.message_mut(&chunk.transfer_uuid) else {
+                continue;
+            };
+            let queued = message
+                .recipient_mut(&chunk.peer.uuid)
+                .is_some_and(|r| matches!(r.status, MessageStatus::Queued(_)));
+            if queued {
+                // The predicted arrival is the one of the last chunk
+                let sent = MessageStatus::Sent(now, chunk.completion);
+                message.transition_for(&chunk.peer.uuid, sent);
+            }
+        }
+        for (transfer_uuid, peer) in expired {
+            self.outbox.remove(&transfer_uuid, &peer.uuid);
+            if let Some(message) = self.message_mut(&transfer_uuid) {
+                let tx = message.tx_for(&peer.uuid);
+                message.transition_for(&peer.uuid, MessageStatus::Expired(tx, now));
+            }
+            self.notify_observers(AppEvent::Error(format!(
+                "File transfer to {} abandoned, chunks left without ACK",
+                peer.name
+            )));
+        }
+        chunks
+    }
+
+    /// Store a chunk of a file received from a peer, the file shows up as a message of its room.
+    /// Returns false if the chunk must not be ACKed.
+    pub fn receive_file_chunk(&mut self, message: ChatMessage, chunk: FileChunk) -> bool {
+        let filename = chunk.filename.clone();
+        let transfer_uuid = chunk.transfer_uuid.clone();
+        let index = chunk.index;
+        let received = match self.transfers.receive_chunk(chunk, Utc::now()) {
+            Ok(received) => received,
+            Err(ChunkRejection::Invalid(reason)) => {
+                eprintln!(
+                    "Dropping chunk {index} of file {filename} from {}: {reason}",
+                    message.sender.name
+                );
+                return false;
+            }
+            Err(ChunkRejection::Corrupted) => {
+                let control = OutboxControl::Nack {
+                    transfer_uuid: transfer_uuid.clone(),
+                };
+                self.outbox.push(OutboxEntry::control(
+                    &message.sender,
+                    Some(message.room_uuid.clone()),
+                    control,
+                ));
+                self.notify_observers(AppEvent::Error(format!(
+                    "File {filename} from {} is corrupted, asking for it again",
+                    message.sender.name
+                )));
+                return false;
+            }
+        };
+
+        if self.message(&transfer_uuid).is_none() {
+            let message = ChatMessage {
+                uuid: transfer_uuid.clone(),
+                ..message
+            };
+            self.add_message(message, MessageDirection::Received);
+        }
+        match received {
+            // A long text message replaces its placeholder
+            Some(TransferState::Complete) => {
+                let text = self.transfers.take_text(&transfer_uuid);
+                if let (Some(text), Some(message)) = (text, self.message_mut(&transfer_uuid)) {
+                    message.text = text;
+                }
+            }
+            Some(TransferState::Saved(path)) => self.notify_observers(AppEvent::Received(format!(
+                "File {filename} saved to {}",
+                path.display()
+            ))),
+            Some(TransferState::Failed(reason)) => self.notify_observers(AppEvent::Error(format!(
+                "File {filename} not saved: {reason}"
+            ))),
+            _ => {}
+        }
+        true
+    }
+
+    /// `peer_uuid` could not put the file `transfer_uuid` back together, send it again
+    pub fn restart_file_transfer(&mut self, transfer_uuid: &str, peer_uuid: &str) {
+        let Some(restarted) = self.transfers.restart_delivery(transfer_uuid, peer_uuid) else {
+            println!("NACK received for unknown file: {transfer_uuid}");
+            return;
+        };
+        let name = self
+            .peers
+            .iter()
+            .find(|p| p.uuid == peer_uuid)
+            .map_or(peer_uuid.to_string(), |p| p.name.clone());
+        if restarted {
+            self.notify_observers(AppEvent::Error(format!(
+                "File corrupted at {name}, sending it again"
+            )));
+            return;
+        }
+        self.outbox.remove(transfer_uuid, peer_uuid);
+        if let Some(message) = self.message_mut(transfer_uuid) {
+            let tx = message.tx_for(peer_uuid);
+            let failed = MessageStatus::Failed(tx, Utc::now(), "corrupted at the recipient".into());
+            message.transition_for(peer_uuid, failed);
+        }
+        self.notify_observers(AppEvent::Error(format!(
+            "File transfer to {name} abandoned, corrupted every time"
+        )));
+    }
+
+    /// Show the messages left in the outbox by a previous run
+    pub fn restore_outbox(&mut self) {
+        let entries = self.outbox.entries().to_vec();
+        // Room changes and other controls have no message in the rooms
+        for entry in entries.into_iter().filter(|e| e.control.is_none()) {
+            // Already transmitted entries are only waiting for their ACK
+            let shipment_status = if entry.transmissions > 0 {
+                MessageStatus::Sent(entry.created_at(), None)
+            } else {
+                MessageStatus::Queued(entry.created_at())
+            };
+            if self.message(&entry.message_uuid).is_none() {
+                let msg = ChatMessage {
+                    uuid: entry.message_uuid.clone(),
+                    response: entry.response.clone(),
+                    sender: self.localpeer.clone(),
+                    text: entry.text.clone(),
+                    shipment_status: shipment_status.clone(),
+                    endpoint: None,
+                    attempts: entry.transmissions,
+                    history: Vec::new(),
+                    room_uuid: entry
+                        .room_uuid
+                        .clone()
+                        .unwrap_or_else(|| self.default_room_uuid()),
+                    recipients: Vec::new(),
+                    routing: None,
+                    predicted_ack: None,
+                };
+                self.add_message(msg, MessageDirection::Sent);
+            }
+
+            let receiver = self
+                .peers
+                .iter()
+                .find(|p| p.uuid == entry.receiver_uuid)
+                .cloned();
+            let Some(receiver) = receiver else {
+                continue;
+            };
+            if let Some(message) = self.message_mut(&entry.message_uuid) {
+                message.add_recipient(&receiver, shipment_status, entry.transmissions);
+            }
+            if let Some(fragment_size) = entry.fragment_size {
+                self.resume_long_text(&entry, &receiver, fragment_size);
+            }
+        }
+    }
+
+    /// Send again in chunks of `fragment_size` the long text of `entry`, the chunks
+    /// acknowledged before the restart included
+    fn resume_long_text(&mut self, entry: &OutboxEntry, receiver: &Peer, fragment_size: usize) {
+        let Some(msg) = self.message(&entry.message_uuid).cloned() else {
+            return;
+        };
+        let data = entry.text.clone().into_bytes();
+        let envelope = chunk_envelope(&msg, LONG_TEXT_NAME, TEXT_MIME, data.len());
+        let completion = self
+            .fragment_plan(receiver, data.len(), envelope)
+            .map(|plan| plan.completion);
+        self.transfers.start_transfer(
+            &msg.uuid,
+            &msg.room_uuid,
+            LONG_TEXT_NAME,
+            TEXT_MIME,
+            data,
+            vec![(receiver.clone(), fragment_size, completion)],
+        );
+    }
+
+    /// Retransmission policy of `room_uuid`, or the global one
+    pub fn retransmission_policy(&self, room_uuid: Option<&str>) -> RetransmissionPolicy {
+        room_uuid
+            .and_then(|uuid| self.rooms.iter().find(|room| room.uuid == uuid))
+            .and_then(|room| room.retransmission)
+            .unwrap_or(self.retransmission)
+    }
+
+    /// Drop `entry` if it was transmitted as many times as its policy allows, without ACK
+    pub fn retransmissions_exhausted(&mut self, entry: &OutboxEntry) -> bool {
+        let policy = self.retransmission_policy(entry.room_uuid.as_deref());
+        if entry.transmissions == 0 || entry.transmissions < policy.max_attempts {
+            return false;
+        }
+
+        self.outbox
+            .remove(&entry.message_uuid, &entry.receiver_uuid);
+        if let Some(message) = self.message_mut(&entry.message_uuid) {
+            let tx = message.tx_for(&entry.receiver_uuid);
+            message.transition_for(&entry.receiver_uuid, MessageStatus::Expired(tx, Utc::now()));
+        }
+        let receiver = self
+            .peers
+            .iter()
+            .find(|p| p.uuid == entry.receiver_uuid)
+            .map_or(entry.receiver_uuid.as_str(), |p| p.name.as_str());
+        self.notify_observers(AppEvent::Error(format!(
+            "No ACK from {receiver} after {} attempts.",
+            entry.transmissions
+        )));
+        true
+    }
+
+    /// Predicted arrival time of `size` bytes sent now to `receiver`
+    pub fn predict_arrival(&self, receiver: &Peer, size: f64) -> Option<DateTime<Utc>> {
+        let config = self.prediction_config.as_ref()?;
+        // Use the ION node of the BP endpoints, or fallback to the UUID
+        let sender_ion_id = config
+            .ion_node_for_peer(&self.localpeer)
+            .unwrap_or_else(|| self.localpeer.uuid.clone());
+        let receiver_ion_id = config
+            .ion_node_for_peer(receiver)
+            .unwrap_or_else(|| receiver.uuid.clone());
+
+        config.predict(&sender_ion_id, &receiver_ion_id, size).ok()
+    }
+
+    /// Predicted arrival of the ACK of `msg`, sent back by `receiver` once it gets the
+    /// message at `arrival`. The return route may differ from the forward one.
+    pub fn predict_ack(
+        &self,
+        receiver: &Peer,
+        msg: &ChatMessage,
+        arrival: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        use prost::Message;
+
+        let config = self.prediction_config.as_ref()?;
+        let sender_ion_id = config
+            .ion_node_for_peer(receiver)
+            .unwrap_or_else(|| receiver.uuid.clone());
+        let receiver_ion_id = config
+            .ion_node_for_peer(&self.localpeer)
+            .unwrap_or_else(|| self.localpeer.uuid.clone());
+        let ack = create_ack_message(msg, &receiver.uuid, false);
+        let size = self.bundle_overhead.bundle_size(ack.encoded_len());
+
+        config
+            .predict_at(&sender_ion_id, &receiver_ion_id, size, arrival)
+            .ok()
+    }
+
+    /// Warning when now falls outside the time span of the contact plan
+    pub fn contact_plan_warning(&self) -> Option<String> {
+        self.prediction_config.as_ref()?.time_warning(Utc::now())
+    }
+
+    /// Algorithm of the predictions, None without contact plan
+    pub fn routing_label(&self) -> Option<String> {
+        Some(self.prediction_config.as_ref()?.routing().label())
+    }
+
+    /// Rebuild the router with another algorithm, the next predictions use it
+    pub fn set_routing(&mut self, routing: RoutingConfig) -> io::Result<()> {
+        let Some(config) = self.prediction_config.as_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No contact plan loaded",
+            ));
+        };
+        config.set_routing(routing.clone())?;
+        // Kept by the next reloads of the contact plan
+        if let Some(source) = &mut self.contact_plan {
+            source.routing = routing;
+        }
+        // Reachability depends on the routes
+        self.reachability.clear();
+        Ok(())
+    }
+
+    /// Contact plan to load, with the epoch to keep when the plan sets none
+    pub fn contact_plan_to_load(&self) -> Option<(ContactPlanSource, DateTime<Utc>)> {
+        let source = self.contact_plan.clone()?;
+        let fallback_epoch = self
+            .prediction_config
+            .as_ref()
+            .map_or_else(Utc::now, |config| config.epoch().0);
+        Some((source, fallback_epoch))
+    }
+
+    /// Replace the contact plan by a freshly loaded one, the current one is kept on error
+    pub fn apply_contact_plan(&mut self, loaded: Result<PredictionConfig, String>) {
+        let now = Utc::now().format("%H:%M:%S");
+        match loaded {
+            Ok(config) => {
+                self.prediction_config = Some(config);
+                self.reachability.clear();
+                self.contact_plan_status = Some(format!("Contact plan loaded at {now}"));
+            }
+            Err(e) => {
+                let message = format!("Contact plan not reloaded at {now}: {e}");
+                self.contact_plan_status = Some(message.clone());
+                self.notify_observers(AppEvent::Error(message));
+            }
+        }
+    }
+
+    /// Opening time of the contact to the next hop towards `receiver` if it is closed now,
+    /// None when it is open or the peers are outside the contact plan
+    pub fn next_hop_opens_at(&self, receiver: &Peer, size: f64) -> Option<DateTime<Utc>> {
+        let config = self.prediction_config.as_ref()?;
+        let sender_ion_id = config.ion_node_for_peer(&self.localpeer)?;
+        let receiver_ion_id = config.ion_node_for_peer(receiver)?;
+        config
+            .next_hop_open(&sender_ion_id, &receiver_ion_id, size)
+            .ok()?
+            .err()
+    }
+
+    /// Volume the contact to the next hop towards `receiver` takes now for bundles of at least
+    /// `size`, up to `limit`, None for a receiver outside the contact plan
+    pub fn next_hop_volume(&self, receiver: &Peer, size: f64, limit: f64) -> Option<f64> {
+        let config = self.prediction_config.as_ref()?;
+        let sender_ion_id = config.ion_node_for_peer(&self.localpeer)?;
+        let receiver_ion_id = config.ion_node_for_peer(receiver)?;
+        config
+            .next_hop_volume(&sender_ion_id, &receiver_ion_id, size, limit)
+            .ok()
+    }
+
+    /// Receiver of `entry`, or the time to retry if it is unknown
+    fn outbox_receiver(
+        &self,
+        entry: &OutboxEntry,
+        now: DateTime<Utc>,
+    ) -> Result<&Peer, DateTime<Utc>> {
+        self.peers
+            .iter()
+            .find(|p| p.uuid == entry.receiver_uuid)
+            .ok_or_else(|| {
+                eprintln!("Outbox: unknown receiver {}", entry.receiver_uuid);
+                now + chrono::Duration::hours(1)
+            })
+    }
+
+    /// The serialized `control` of `entry` with its receiver,
+    /// or the time to retry if the contact to the next hop is not open yet
+    pub fn prepare_control_release(
+        &self,
+        entry: &OutboxEntry,
+        control: &OutboxControl,
+        now: DateTime<Utc>,
+    ) -> Result<(Vec<u8>, Peer), DateTime<Utc>> {
+        let receiver = self.outbox_receiver(entry, now)?;
+        let buf = control.encode(&entry.message_uuid, &self.localpeer.uuid);
+        let size = self.bundle_overhead.bundle_size(buf.len());
+        if let Some(opens_at) = self.next_hop_opens_at(receiver, size) {
+            return Err(opens_at);
+        }
+        Ok((buf, receiver.clone()))
+    }
+
+    /// The message to hand to the transport for `entry`,
+    /// or the time to retry if the contact to the next hop is not open yet
+    pub fn prepare_outbox_release(
+        &self,
+        entry: &OutboxEntry,
+        now: DateTime<Utc>,
+    ) -> Result<(ChatMessage, Peer), DateTime<Utc>> {
+        let receiver = self.outbox_receiver(entry, now)?;
+
+        // A retransmission, or a retry after a failure, keeps the time of the first
+        // transmission, which is shared by all the recipients
+        let tx = self
+            .message(&entry.message_uuid)
+            .and_then(|m| m.transmission_time(&entry.receiver_uuid))
+            .unwrap_or(now);
+        let mut msg = ChatMessage {
+            uuid: entry.message_uuid.clone(),
+            response: entry.response.clone(),
+            sender: self.localpeer.clone(),
+            text: entry.text.clone(),
+            shipment_status: MessageStatus::Sent(tx, None),
+            endpoint: None,
+            attempts: 0,
+            history: Vec::new(),
+            room_uuid: entry
+                .room_uuid
+                .clone()
+                .unwrap_or_else(|| self.default_room_uuid()),
+            recipients: Vec::new(),
+            routing: None,
+            predicted_ack: None,
+        };
+
+        // The predictions use the bundle as sent, not the length of the text
+        let size = self.bundle_overhead.bundle_size(encoded_size(&msg));
+        if let Some(opens_at) = self.next_hop_opens_at(receiver, size) {
+            println!(
+                "Outbox: next hop to {} closed until {}",
+                receiver.name,
+                opens_at.format("%H:%M:%S")
+            );
+            return Err(opens_at);
+        }
+
+        let pbat = if entry.pbat {
+            self.predict_arrival(receiver, size)
+        } else {
+            None
+        };
+        msg.predicted_ack = pbat.and_then(|pbat| self.predict_ack(receiver, &msg, pbat));
+        msg.shipment_status = MessageStatus::Sent(tx, pbat);
+        msg.routing = pbat.and(self.routing_label());
+        Ok((msg, receiver.clone()))
+    }
+
+    /// The transport accepted an outbox entry
+    pub fn outbox_sent(
+        &mut self,
+        entry: &OutboxEntry,
+        sent: ChatMessage,
+        carried_by: Vec<Endpoint>,
+    ) {
+        let policy = self.retransmission_policy(entry.room_uuid.as_deref());
+        match sent.shipment_status {
+            MessageStatus::Sent(_, Some(pbat)) if policy.max_attempts > 1 => {
+                // Wait for the ACK during the predicted round trip (twice the one-way
+                // prediction without return route), plus the time the receiver may hold
+                // it in a batch (the same `ack_batch` on every node) and the margin,
+                // then transmit again
+                let now = Utc::now();
+                let one_way = (pbat - now).max(Duration::zero());
+                let ack = sent.predicted_ack.unwrap_or(now + one_way * 2).max(now);
+                let batching = self
+                    .ack_batcher
+                    .as_ref()
+                    .map_or(Duration::zero(), |batcher| {
+                        batcher.lock().unwrap().max_delay()
+                    });
+                let deadline = ack + batching + Duration::seconds(policy.margin_secs);
+                self.outbox
+                    .await_ack(&entry.message_uuid, &entry.receiver_uuid, deadline);
+            }
+            _ => self
+                .outbox
+                .remove(&entry.message_uuid, &entry.receiver_uuid),
+        }
+
+        if let Some(message) = self.message_mut(&sent.uuid) {
+            let endpoint = carried_by.into_iter().next();
+            match message.recipient_mut(&entry.receiver_uuid) {
+                Some(recipient) => {
+                    recipient.attempts += 1;
+                    recipient.endpoint = endpoint.clone();
+                    if sent.routing.is_some() {
+                        recipient.routing = sent.routing.clone();
+                        recipient.predicted_ack = sent.predicted_ack;
+                    }
+                }
+                None => message.attempts += 1,
+            }
+            if sent.routing.is_some() {
+                message.routing = sent.routing;
+                // The last ACK expected, over the recipients
+                message.predicted_ack = message
+                    .recipients
+                    .iter()
+                    .filter_map(|r| r.predicted_ack)
+                    .max()
+                    .or(sent.predicted_ack);
+            }
+            // An ACK may already have arrived for a redundant or retried copy
+            message.transition_for(&entry.receiver_uuid, sent.shipment_status);
+            message.endpoint = endpoint;
+        }
+        self.sort_messages(self.sort_strategy.clone());
+    }
+
+    /// The transport refused `message` for the receiver of `entry`,
+    /// it stays in the outbox for a retry
+    pub fn outbox_failed(&mut self, entry: &OutboxEntry, message: &ChatMessage, reason: &str) {
+        if let Some(stored) = self.message_mut(&message.uuid) {
+            let failed =
+                MessageStatus::Failed(message.shipment_status.tx(), Utc::now(), reason.to_string());
+            stored.transition_for(&entry.receiver_uuid, failed);
+        }
+    }
+
+    /// Send a read receipt for each received message displayed for the first time
+    pub fn mark_displayed(&mut self, message_uuids: &[String]) {
+        for uuid in message_uuids {
+            // A file is only read once received entirely
+            let receiving = self
+                .transfers
+                .progress(uuid)
+                .is_some_and(|p| p.state == TransferState::InProgress);
+            if receiving || !self.displayed.insert(uuid.clone()) {
+                continue;
+            }
+            let Some(message) = self.message(uuid) else {
+                continue;
+            };
+            let Some(sender) = self.peers.iter().find(|p| p.uuid == message.sender.uuid) else {
+                continue;
+            };
+            if !sender.read_receipts.unwrap_or(self.read_receipts) {
+                continue;
+            }
+            match &self.ack_batcher {
+                Some(batcher) => {
+                    let batch = batcher.lock().unwrap().push(
+                        &sender.uuid,
+                        message.endpoint.as_ref(),
+                        &message.uuid,
+                        true,
+                    );
+                    if let Some(batch) = batch {
+                        send_ack_batch(batch, sender, &self.localpeer.uuid);
+                    }
+                }
+                None => send_read_receipt(message, sender, &self.localpeer.uuid),
+            }
         }
     }
 
@@ -138,16 +1368,34 @@ impl ChatModel {
     pub fn update_message_with_ack(
         &mut self,
         message_uuid: &str,
+        ack_sender_uuid: &str,
         is_read: bool,
         ack_time: DateTime<Utc>,
     ) -> bool {
-        for message in &mut self.messages {
-            if message.uuid == message_uuid {
-                message.update_with_ack(is_read, ack_time);
-                return true;
+        // A file is delivered once the recipient has every chunk
+        if let Some((transfer_uuid, complete)) = self
+            .transfers
+            .acknowledge_chunk(message_uuid, ack_sender_uuid)
+        {
+            if complete {
+                // A long text sent in chunks leaves the outbox with its last chunk
+                self.outbox.acknowledge(&transfer_uuid, ack_sender_uuid);
+            }
+            if let Some(message) = self.message_mut(&transfer_uuid).filter(|_| complete) {
+                message.update_with_ack(ack_sender_uuid, false, ack_time);
+            }
+            return true;
+        }
+
+        // Nothing left to retransmit to this recipient
+        self.outbox.acknowledge(message_uuid, ack_sender_uuid);
+        match self.message_mut(message_uuid) {
+            Some(message) => {
+                message.update_with_ack(ack_sender_uuid, is_read, ack_time);
+                true
             }
+            None => false, // Message not found
         }
-        false // Message not found
     }
 }
 
@@ -160,11 +1408,12 @@ impl SocketObserver for Mutex<ChatModel> {
     fn on_ack_received(
         &self,
         message_uuid: &str,
+        ack_sender_uuid: &str,
         is_read: bool,
         ack_time: chrono::DateTime<chrono::Utc>,
     ) {
         let mut model = self.lock().unwrap();
-        if model.update_message_with_ack(message_uuid, is_read, ack_time) {
+        if model.update_message_with_ack(message_uuid, ack_sender_uuid, is_read, ack_time) {
             println!("Updated message {message_uuid} with ACK (read: {is_read})");
             // Trigger UI update
             model.notify_observers(AppEvent::Sent("Message status updated".to_string()));
@@ -172,38 +1421,125 @@ impl SocketObserver for Mutex<ChatModel> {
             println!("ACK received for unknown message: {message_uuid}");
         }
     }
+
+    fn on_file_chunk(&self, message: ChatMessage, chunk: FileChunk) -> bool {
+        let mut model = self.lock().unwrap();
+        model.receive_file_chunk(message, chunk)
+    }
+
+    fn on_nack(&self, message_uuid: &str, nack_sender_uuid: &str) {
+        let mut model = self.lock().unwrap();
+        model.restart_file_transfer(message_uuid, nack_sender_uuid);
+    }
+
+    fn on_room_control(&self, sender_uuid: &str, action: RoomAction, room: Room) {
+        let mut model = self.lock().unwrap();
+        model.apply_room_control(sender_uuid, action, room);
+    }
+
+    fn on_presence(&self, sender_uuid: &str, presence: Presence, at: DateTime<Utc>) {
+        let mut model = self.lock().unwrap();
+        model.apply_presence(sender_uuid, presence, at);
+    }
+
+    fn on_path_arrival(&self, message_uuid: &str, arrivals: &[PathArrival]) {
+        let mut model = self.lock().unwrap();
+        model.path_arrivals.set(message_uuid, arrivals.to_vec());
+        if arrivals.len() > 1 {
+            model.notify_observers(AppEvent::Received(format!(
+                "Redundant copy of a message received ({} paths)",
+                arrivals.len()
+            )));
+        }
+    }
 }
 
 pub struct MessagePanel {
     pub message_view: RoomView,
+    /// Room shown by the views and receiving the new messages
+    pub room_uuid: String,
     pub create_modal_open: bool,
+    /// Form of the New Room / Edit Room dialog
+    pub room_draft: RoomDraft,
+    /// Form of the Export dialog, shown while set
+    pub export_draft: Option<ExportDraft>,
     pub message_to_send: String,
     pub forging_receiver: Peer,
+    /// Send to every participant of the room instead of `forging_receiver` only
+    pub fan_out: bool,
+    /// Message shown in the details popup
+    pub details_uuid: Option<String>,
     pub send_status: Option<String>,
     pub pbat_enabled: bool,
+    pub redundant: bool,
+    /// Sorting and filters of the table view
+    pub table: MessageTableState,
+    pub search: SearchState,
+}
+
+pub struct ContactPanel {
+    /// Form of the Add Peer / Edit Peer dialog, shown while set
+    pub peer_draft: Option<PeerDraft>,
+    pub status: Option<String>,
+}
+
+pub struct SettingsPanel {
+    /// Routing algorithm being edited, the current one until changed
+    pub routing_draft: Option<RoutingConfig>,
+    pub status: Option<String>,
 }
 
 pub struct ChatApp {
     pub model_arc: Arc<Mutex<ChatModel>>,
     pub handler_arc: Arc<Mutex<EventHandler>>,
+    /// Told about the peer changes, to recognise the senders added at runtime
+    pub socket_controller: Option<Arc<Mutex<DefaultSocketController>>>,
     pub context_menu: NavigationItems,
     pub message_panel: MessagePanel,
+    pub contact_panel: ContactPanel,
+    pub settings_panel: SettingsPanel,
 }
 
 impl ChatApp {
     pub fn new(model_arc: Arc<Mutex<ChatModel>>, handler_arc: Arc<Mutex<EventHandler>>) -> Self {
-        let forging_receiver = model_arc.lock().unwrap().peers[0].clone();
+        let (room_uuid, forging_receiver) = {
+            let model = model_arc.lock().unwrap();
+            let room_uuid = model.default_room_uuid();
+            let forging_receiver = model
+                .room_participants(&room_uuid)
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| model.peers[0].clone());
+            (room_uuid, forging_receiver)
+        };
         Self {
             model_arc,
             handler_arc,
+            socket_controller: None,
             context_menu: NavigationItems::default(),
             message_panel: MessagePanel {
                 message_view: RoomView::default(),
+                room_uuid,
                 create_modal_open: false,
+                room_draft: RoomDraft::default(),
+                export_draft: None,
                 message_to_send: String::new(),
                 forging_receiver,
+                fan_out: true,
+                details_uuid: None,
                 send_status: None,
                 pbat_enabled: false,
+                redundant: false,
+                table: MessageTableState::default(),
+                search: SearchState::default(),
+            },
+            contact_panel: ContactPanel {
+                peer_draft: None,
+                status: None,
+            },
+            settings_panel: SettingsPanel {
+                routing_draft: None,
+                status: None,
             },
         }
     }
@@ -238,8 +1574,37 @@ impl ModelObserver for EventHandler {
     }
 }
 
+impl ChatApp {
+    /// Add or replace `peer`, see `ChatModel::save_peer`, and give the peers to the socket
+    /// controller even if the configuration file could not be written
+    pub fn save_peer(&self, peer: Peer) -> io::Result<()> {
+        let result = self.model_arc.lock().unwrap().save_peer(peer);
+        self.sync_socket_peers();
+        result
+    }
+
+    /// Forget a peer, see `ChatModel::delete_peer`, and give the peers to the socket controller
+    pub fn delete_peer(&self, peer_uuid: &str) -> io::Result<()> {
+        let result = self.model_arc.lock().unwrap().delete_peer(peer_uuid);
+        self.sync_socket_peers();
+        result
+    }
+
+    /// Give the current peers to the socket controller, which only knows the senders among them
+    fn sync_socket_peers(&self) {
+        let peers = self.model_arc.lock().unwrap().peers.clone();
+        if let Some(controller) = &self.socket_controller {
+            controller.lock().unwrap().set_peers(peers);
+        }
+    }
+}
+
 impl eframe::App for ChatApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         display(self, ctx);
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.model_arc.lock().unwrap().flush_history();
+    }
 }
@@ -0,0 +1,188 @@
+use crate::app::ChatApp;
+use crate::utils::config::Peer;
+use crate::utils::socket::{Endpoint, EndpointKind};
+use eframe::egui;
+use egui::{ComboBox, Id, Modal, RichText};
+
+/// Names of the colors of `Peer::get_color`
+const COLOR_NAMES: [&str; 4] = ["Green", "Red", "Blue", "Yellow"];
+
+/// Content of the peer dialog, kept between frames
+pub struct PeerDraft {
+    pub uuid: String,
+    pub name: String,
+    pub color: u32,
+    pub endpoints: Vec<(EndpointKind, String)>,
+    /// Peer being edited, None when adding a new one
+    pub editing: Option<Peer>,
+}
+
+impl Default for PeerDraft {
+    fn default() -> Self {
+        Self {
+            uuid: String::new(),
+            name: String::new(),
+            color: 0,
+            endpoints: vec![(EndpointKind::Udp, String::new())],
+            editing: None,
+        }
+    }
+}
+
+impl PeerDraft {
+    pub fn edit(peer: &Peer) -> Self {
+        Self {
+            uuid: peer.uuid.clone(),
+            name: peer.name.clone(),
+            color: peer.color,
+            endpoints: peer
+                .endpoints
+                .iter()
+                .map(|endpoint| (endpoint.kind(), endpoint.to_string()))
+                .collect(),
+            editing: Some(peer.clone()),
+        }
+    }
+
+    fn endpoints(&self) -> Vec<Endpoint> {
+        self.endpoints
+            .iter()
+            .filter(|(_, address)| !address.trim().is_empty())
+            .map(|(kind, address)| Endpoint::new(*kind, address.trim().to_string()))
+            .collect()
+    }
+
+    /// The peer described by the form, keeping the settings it does not show
+    fn peer(&self) -> Peer {
+        let base = self.editing.clone().unwrap_or_default();
+        Peer {
+            uuid: self.uuid.trim().to_string(),
+            name: self.name.trim().to_string(),
+            endpoints: self.endpoints(),
+            color: self.color,
+            ..base
+        }
+    }
+}
+
+pub struct EditPeerForm {}
+
+impl EditPeerForm {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        let peers = app.model_arc.lock().unwrap().peers.clone();
+        let Some(draft) = &mut app.contact_panel.peer_draft else {
+            return;
+        };
+        let mut close = false;
+        let mut saved = None;
+
+        Modal::new(Id::new("edit_peer_modal")).show(ui.ctx(), |ui| {
+            ui.set_width(380.0);
+            ui.heading(if draft.editing.is_some() {
+                "Edit peer"
+            } else {
+                "Add a peer"
+            });
+
+            ui.separator();
+
+            egui::Grid::new("edit_peer_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("UUID:");
+                    // The UUID identifies the peer on the wire, it is chosen by the peer
+                    ui.add_enabled(
+                        draft.editing.is_none(),
+                        egui::TextEdit::singleline(&mut draft.uuid),
+                    );
+                    ui.end_row();
+
+                    ui.label("Name:");
+                    ui.text_edit_singleline(&mut draft.name);
+                    ui.end_row();
+
+                    ui.label("Color:");
+                    let color_name = COLOR_NAMES[(draft.color % 4) as usize];
+                    ComboBox::from_id_salt("peer_color")
+                        .selected_text(color_name)
+                        .show_ui(ui, |ui| {
+                            for (color, name) in COLOR_NAMES.iter().enumerate() {
+                                ui.selectable_value(&mut draft.color, color as u32, *name);
+                            }
+                        });
+                    ui.end_row();
+                });
+
+            ui.label("Endpoints:");
+            let mut removed = None;
+            for (index, (kind, address)) in draft.endpoints.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ComboBox::from_id_salt(("endpoint_kind", index))
+                        .width(60.0)
+                        .selected_text(format!("{kind:?}"))
+                        .show_ui(ui, |ui| {
+                            for option in EndpointKind::ALL {
+                                ui.selectable_value(kind, option, format!("{option:?}"));
+                            }
+                        });
+                    ui.text_edit_singleline(address);
+                    if Endpoint::new(*kind, address.trim().to_string()).is_valid() {
+                        ui.label(RichText::new("✓").color(egui::Color32::GREEN));
+                    } else {
+                        ui.label(RichText::new("✗").color(egui::Color32::RED))
+                            .on_hover_text("Not a valid address for this transport");
+                    }
+                    if ui.small_button("🗑").clicked() {
+                        removed = Some(index);
+                    }
+                });
+            }
+            if let Some(index) = removed {
+                draft.endpoints.remove(index);
+            }
+            if ui.button("Add endpoint").clicked() {
+                draft.endpoints.push((EndpointKind::Udp, String::new()));
+            }
+
+            ui.separator();
+
+            let peer = draft.peer();
+            let duplicate = draft.editing.is_none() && peers.iter().any(|p| p.uuid == peer.uuid);
+            if duplicate {
+                ui.colored_label(egui::Color32::RED, "A peer already uses this UUID");
+            }
+            let valid = !peer.uuid.is_empty() && !peer.name.is_empty() && !duplicate;
+
+            ui.horizontal(|ui| {
+                let submit = if draft.editing.is_some() {
+                    "Save"
+                } else {
+                    "Add"
+                };
+                if ui.add_enabled(valid, egui::Button::new(submit)).clicked() {
+                    saved = Some(peer);
+                    close = true;
+                }
+                if ui.button("Cancel").clicked() {
+                    close = true;
+                }
+            });
+        });
+
+        if let Some(peer) = saved {
+            let name = peer.name.clone();
+            let result = app.save_peer(peer);
+            app.contact_panel.status = Some(match result {
+                Ok(()) => format!("Saved {name}"),
+                Err(e) => format!("{name} changed for this session only: {e}"),
+            });
+        }
+        if close {
+            app.contact_panel.peer_draft = None;
+        }
+    }
+}
@@ -0,0 +1 @@
+pub mod edit_peer;
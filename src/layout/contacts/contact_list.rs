@@ -0,0 +1,143 @@
+use super::actions::edit_peer::{EditPeerForm, PeerDraft};
+use crate::app::ChatApp;
+use crate::layout::menu_bar::NavigationItems;
+use crate::utils::config::Peer;
+use chrono::Utc;
+use eframe::egui;
+use egui::{Grid, RichText, ScrollArea};
+
+enum PeerAction {
+    Chat(Peer),
+    Edit(Peer),
+    Delete(Peer),
+}
+
+pub struct ContactListView {}
+
+impl ContactListView {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Add Peer").clicked() {
+                app.contact_panel.peer_draft = Some(PeerDraft::default());
+            }
+            if let Some(status) = &app.contact_panel.status {
+                ui.label(status);
+            }
+        });
+        ui.separator();
+
+        let mut action = None;
+        {
+            let mut model = app.model_arc.lock().unwrap();
+            let local_uuid = model.localpeer.uuid.clone();
+            let peers = model.peers.clone();
+            let now = Utc::now();
+
+            ScrollArea::vertical().show(ui, |ui| {
+                Grid::new("contact_list")
+                    .num_columns(7)
+                    .striped(true)
+                    .spacing([20.0, 8.0])
+                    .show(ui, |ui| {
+                        ui.strong("Name");
+                        ui.strong("Presence");
+                        ui.strong("Reachability");
+                        ui.strong("Endpoints");
+                        ui.strong("Last message");
+                        ui.strong("ACKs");
+                        ui.label("");
+                        ui.end_row();
+
+                        for peer in peers.iter().filter(|peer| peer.uuid != local_uuid) {
+                            ui.label(RichText::new(&peer.name).color(peer.get_color()))
+                                .on_hover_text(format!("UUID {}", peer.uuid));
+
+                            let presence = model.peer_presence(&peer.uuid);
+                            ui.label(
+                                RichText::new(format!("● {}", presence.label()))
+                                    .color(presence.color()),
+                            );
+
+                            match model.reachability(peer) {
+                                Some(reachability) => ui.label(reachability.describe(now)),
+                                None => ui.weak("outside the contact plan"),
+                            };
+
+                            ui.vertical(|ui| {
+                                for endpoint in &peer.endpoints {
+                                    if endpoint.is_valid() {
+                                        ui.label(format!("✓ {:?} {endpoint}", endpoint.kind()));
+                                    } else {
+                                        ui.colored_label(
+                                            egui::Color32::RED,
+                                            format!("✗ {:?} {endpoint}", endpoint.kind()),
+                                        )
+                                        .on_hover_text("Invalid address, never used to send");
+                                    }
+                                }
+                            });
+
+                            let stats = model.peer_stats(&peer.uuid);
+                            match stats.last_message {
+                                Some(time) => {
+                                    ui.label(time.format("%Y-%m-%d %H:%M:%S").to_string())
+                                }
+                                None => ui.weak("never"),
+                            };
+                            match stats.ack_rate() {
+                                Some(rate) => ui.label(format!(
+                                    "{}/{} ({:.0}%)",
+                                    stats.acked,
+                                    stats.sent,
+                                    rate * 100.0
+                                )),
+                                None => ui.weak("-"),
+                            };
+
+                            ui.horizontal(|ui| {
+                                if ui.button("💬 Chat").clicked() {
+                                    action = Some(PeerAction::Chat(peer.clone()));
+                                }
+                                if ui.button("Edit").clicked() {
+                                    action = Some(PeerAction::Edit(peer.clone()));
+                                }
+                                if ui.button("Delete").clicked() {
+                                    action = Some(PeerAction::Delete(peer.clone()));
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+            });
+        }
+
+        match action {
+            Some(PeerAction::Chat(peer)) => {
+                let room_uuid = app.model_arc.lock().unwrap().direct_room(&peer);
+                app.message_panel.room_uuid = room_uuid;
+                app.message_panel.forging_receiver = peer;
+                app.message_panel.fan_out = false;
+                app.context_menu = NavigationItems::Rooms;
+            }
+            Some(PeerAction::Edit(peer)) => {
+                app.contact_panel.peer_draft = Some(PeerDraft::edit(&peer));
+            }
+            Some(PeerAction::Delete(peer)) => {
+                let result = app.delete_peer(&peer.uuid);
+                app.contact_panel.status = Some(match result {
+                    Ok(()) => format!("Deleted {}", peer.name),
+                    Err(e) => format!("{} deleted for this session only: {e}", peer.name),
+                });
+            }
+            None => {}
+        }
+
+        if app.contact_panel.peer_draft.is_some() {
+            EditPeerForm::new().show(app, ui);
+        }
+    }
+}
@@ -1,11 +1,14 @@
 use crate::app::ChatApp;
+use crate::utils::presence::Presence;
 use eframe::egui;
+use egui::{Color32, ComboBox, RichText};
 
 #[derive(Clone, Debug, PartialEq, Default)]
 pub enum NavigationItems {
     #[default]
     Rooms,
     Contacts,
+    Settings,
 }
 
 pub struct MenuBar {}
@@ -20,6 +23,32 @@ impl MenuBar {
         ui.horizontal(|ui| {
             ui.selectable_value(&mut app.context_menu, NavigationItems::Rooms, "Rooms");
             ui.selectable_value(&mut app.context_menu, NavigationItems::Contacts, "Contacts");
+            ui.selectable_value(&mut app.context_menu, NavigationItems::Settings, "Settings");
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                let mut model = app.model_arc.lock().unwrap();
+                let current = model.presence;
+                ComboBox::from_id_salt("Presence")
+                    .selected_text(
+                        RichText::new(format!("● {}", current.label())).color(current.color()),
+                    )
+                    .show_ui(ui, |ui| {
+                        for presence in Presence::SELECTABLE {
+                            if ui
+                                .selectable_label(current == presence, presence.label())
+                                .clicked()
+                                && current != presence
+                            {
+                                model.set_presence(presence);
+                            }
+                        }
+                    });
+
+                if let Some(warning) = model.contact_plan_warning() {
+                    ui.label(RichText::new(format!("⚠ {warning}")).color(Color32::ORANGE))
+                        .on_hover_text("Predictions are only possible within the contact plan");
+                }
+            });
         });
         ui.add_space(10.0);
     }
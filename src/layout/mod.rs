@@ -1,3 +1,5 @@
+pub mod contacts;
 pub mod menu_bar;
 pub mod rooms;
+pub mod settings;
 pub mod ui;
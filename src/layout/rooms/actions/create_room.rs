@@ -1,6 +1,17 @@
 use crate::app::ChatApp;
 use eframe::egui;
 use egui::{Id, Modal};
+use std::collections::HashSet;
+
+/// Content of the room dialog, kept between frames
+#[derive(Default)]
+pub struct RoomDraft {
+    pub name: String,
+    /// UUIDs of the peers taking part in the room
+    pub participants: HashSet<String>,
+    /// Room being edited, None when creating a new one
+    pub editing: Option<String>,
+}
 
 pub struct CreateRoomForm {}
 
@@ -9,16 +20,76 @@ impl CreateRoomForm {
         Self {}
     }
     pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        let (localpeer, peers) = {
+            let model = app.model_arc.lock().unwrap();
+            (model.localpeer.clone(), model.peers.clone())
+        };
+
         Modal::new(Id::new("create_room_modal")).show(ui.ctx(), |ui| {
             ui.set_width(250.0);
 
-            ui.heading("Create a new room");
+            let draft = &mut app.message_panel.room_draft;
+            let editing = draft.editing.clone();
+            ui.heading(if editing.is_some() {
+                "Edit room"
+            } else {
+                "Create a new room"
+            });
 
             ui.separator();
 
-            if ui.button("Cancel").clicked() {
-                app.message_panel.create_modal_open = false;
+            ui.horizontal(|ui| {
+                ui.label("Name:");
+                ui.text_edit_singleline(&mut draft.name);
+            });
+
+            ui.label("Participants:");
+            for peer in peers.iter().filter(|p| p.uuid != localpeer.uuid) {
+                let mut selected = draft.participants.contains(&peer.uuid);
+                if ui.checkbox(&mut selected, &peer.name).changed() {
+                    if selected {
+                        draft.participants.insert(peer.uuid.clone());
+                    } else {
+                        draft.participants.remove(&peer.uuid);
+                    }
+                }
             }
+
+            ui.separator();
+
+            let name = draft.name.trim().to_string();
+            // Keep the declaration order of the peers
+            let participants: Vec<String> = peers
+                .iter()
+                .filter(|p| draft.participants.contains(&p.uuid))
+                .map(|p| p.uuid.clone())
+                .collect();
+            let valid = !name.is_empty() && !participants.is_empty();
+
+            ui.horizontal(|ui| {
+                let submit = if editing.is_some() { "Save" } else { "Create" };
+                if ui.add_enabled(valid, egui::Button::new(submit)).clicked() {
+                    let mut model = app.model_arc.lock().unwrap();
+                    match &editing {
+                        Some(room_uuid) => model.update_room(room_uuid, &name, participants),
+                        None => {
+                            app.message_panel.room_uuid = model.create_room(&name, participants);
+                        }
+                    }
+                    app.message_panel.create_modal_open = false;
+                }
+                if let Some(room_uuid) = &editing {
+                    if ui.button("Leave").clicked() {
+                        let mut model = app.model_arc.lock().unwrap();
+                        model.leave_room(room_uuid);
+                        app.message_panel.room_uuid = model.default_room_uuid();
+                        app.message_panel.create_modal_open = false;
+                    }
+                }
+                if ui.button("Cancel").clicked() {
+                    app.message_panel.create_modal_open = false;
+                }
+            });
         });
     }
 }
@@ -0,0 +1,155 @@
+use crate::app::ChatApp;
+use crate::utils::export::{parse_time, write_export, ExportFilter, ExportFormat};
+use chrono::{DateTime, Utc};
+use eframe::egui;
+use egui::{Color32, ComboBox, Id, Modal};
+
+/// Content of the Export dialog, kept between frames
+#[derive(Default)]
+pub struct ExportDraft {
+    /// None to export every room
+    pub room_uuid: Option<String>,
+    /// UTC times typed by the user, `YYYY-MM-DD` or `YYYY-MM-DD HH:MM`
+    pub from: String,
+    pub to: String,
+    pub format: ExportFormat,
+    /// Outcome of the last export
+    pub status: Option<String>,
+}
+
+impl ExportDraft {
+    pub fn for_room(room_uuid: &str) -> Self {
+        Self {
+            room_uuid: Some(room_uuid.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+// Empty times are not bounds, invalid ones are reported
+fn time_bound(input: &str, end_of_day: bool) -> Result<Option<DateTime<Utc>>, ()> {
+    if input.trim().is_empty() {
+        return Ok(None);
+    }
+    parse_time(input, end_of_day).map(Some).ok_or(())
+}
+
+pub struct ExportForm {}
+
+impl ExportForm {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        let rooms = app.model_arc.lock().unwrap().rooms.clone();
+        let Some(draft) = &mut app.message_panel.export_draft else {
+            return;
+        };
+        let mut close = false;
+
+        Modal::new(Id::new("export_modal")).show(ui.ctx(), |ui| {
+            ui.set_width(300.0);
+            ui.heading("Export messages");
+            ui.separator();
+
+            egui::Grid::new("export_grid")
+                .num_columns(2)
+                .spacing([8.0, 6.0])
+                .show(ui, |ui| {
+                    ui.label("Room:");
+                    let room_name = match &draft.room_uuid {
+                        Some(uuid) => rooms
+                            .iter()
+                            .find(|r| r.uuid == *uuid)
+                            .map_or_else(|| uuid.clone(), |r| r.name.clone()),
+                        None => "All rooms".to_string(),
+                    };
+                    ComboBox::from_id_salt("export_room")
+                        .selected_text(room_name)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut draft.room_uuid, None, "All rooms");
+                            for room in &rooms {
+                                ui.selectable_value(
+                                    &mut draft.room_uuid,
+                                    Some(room.uuid.clone()),
+                                    &room.name,
+                                );
+                            }
+                        });
+                    ui.end_row();
+
+                    for (label, input, end_of_day) in [
+                        ("From:", &mut draft.from, false),
+                        ("To:", &mut draft.to, true),
+                    ] {
+                        ui.label(label);
+                        let valid = time_bound(input, end_of_day).is_ok();
+                        ui.add(
+                            egui::TextEdit::singleline(input)
+                                .hint_text("YYYY-MM-DD HH:MM")
+                                .text_color_opt((!valid).then_some(Color32::RED)),
+                        )
+                        .on_hover_text("UTC, empty for no bound");
+                        ui.end_row();
+                    }
+
+                    ui.label("Format:");
+                    ui.horizontal(|ui| {
+                        for format in ExportFormat::ALL {
+                            ui.radio_value(
+                                &mut draft.format,
+                                format,
+                                format.extension().to_uppercase(),
+                            );
+                        }
+                    });
+                    ui.end_row();
+                });
+
+            if let Some(status) = &draft.status {
+                ui.label(status);
+            }
+            ui.separator();
+
+            let bounds = time_bound(&draft.from, false).and_then(|from| {
+                let to = time_bound(&draft.to, true)?;
+                Ok((from, to))
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(bounds.is_ok(), egui::Button::new("Export…"))
+                    .clicked()
+                {
+                    let Ok((from, to)) = bounds else {
+                        return;
+                    };
+                    let extension = draft.format.extension();
+                    let picked = rfd::FileDialog::new()
+                        .add_filter(extension.to_uppercase(), &[extension])
+                        .set_file_name(format!("dtchat-export.{extension}"))
+                        .save_file();
+                    if let Some(path) = picked {
+                        let filter = ExportFilter {
+                            room_uuid: draft.room_uuid.clone(),
+                            from,
+                            to,
+                        };
+                        let records = app.model_arc.lock().unwrap().export_records(&filter);
+                        draft.status = Some(match write_export(&path, draft.format, &records) {
+                            Ok(count) => format!("{count} records written to {}", path.display()),
+                            Err(e) => format!("Export failed: {e}"),
+                        });
+                    }
+                }
+                if ui.button("Close").clicked() {
+                    close = true;
+                }
+            });
+        });
+
+        if close {
+            app.message_panel.export_draft = None;
+        }
+    }
+}
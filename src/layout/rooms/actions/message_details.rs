@@ -0,0 +1,108 @@
+use crate::app::ChatApp;
+use crate::utils::message::MessageStatus;
+use chrono::{DateTime, Utc};
+use eframe::egui;
+use egui::{Grid, Id, Modal};
+
+fn time_str(time: Option<DateTime<Utc>>) -> String {
+    time.map_or("-".to_string(), |t| t.format("%H:%M:%S").to_string())
+}
+
+pub struct MessageDetails {}
+
+impl MessageDetails {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        let Some(uuid) = app.message_panel.details_uuid.clone() else {
+            return;
+        };
+        let Some(message) = app.model_arc.lock().unwrap().message(&uuid).cloned() else {
+            app.message_panel.details_uuid = None;
+            return;
+        };
+
+        Modal::new(Id::new("message_details_modal")).show(ui.ctx(), |ui| {
+            ui.set_width(420.0);
+
+            ui.heading("Message details");
+            ui.label(format!("{}: {}", message.sender.name, message.text));
+            ui.label(format!("State: {}", message.shipment_status.label()));
+            let pbat = message.predicted_arrival();
+            if pbat.is_some() {
+                ui.label(format!("Predicted delivery: {}", time_str(pbat)));
+            }
+            if message.predicted_ack.is_some() {
+                ui.label(format!(
+                    "Predicted ACK time: {}",
+                    time_str(message.predicted_ack)
+                ));
+            }
+            if let Some(routing) = &message.routing {
+                ui.label(format!("PBAT by {routing}"));
+            }
+
+            if !message.recipients.is_empty() {
+                ui.separator();
+                ui.label(format!(
+                    "{}/{} delivered",
+                    message.delivered_count(),
+                    message.recipients.len()
+                ));
+                Grid::new("message_details_recipients")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Recipient");
+                        ui.strong("State");
+                        ui.strong("PBAT");
+                        ui.strong("Pred. ACK");
+                        ui.strong("Routing");
+                        ui.strong("ACK");
+                        ui.strong("Via");
+                        ui.strong("Attempts");
+                        ui.end_row();
+
+                        for recipient in &message.recipients {
+                            let pbat = match recipient.status {
+                                MessageStatus::Sent(_, pbat) => pbat,
+                                _ => None,
+                            };
+                            ui.label(&recipient.peer.name);
+                            ui.label(recipient.status.label());
+                            ui.label(time_str(pbat));
+                            ui.label(time_str(recipient.predicted_ack));
+                            ui.label(recipient.routing.as_deref().unwrap_or("-"));
+                            ui.label(time_str(recipient.status.rx()));
+                            ui.label(
+                                recipient
+                                    .endpoint
+                                    .as_ref()
+                                    .map_or("-".to_string(), |e| e.to_string()),
+                            );
+                            ui.label(recipient.attempts.to_string());
+                            ui.end_row();
+                        }
+                    });
+            }
+
+            if !message.history.is_empty() {
+                ui.separator();
+                ui.label("History:");
+                for (left_at, status) in &message.history {
+                    ui.label(format!(
+                        "{} until {}",
+                        status.label(),
+                        left_at.format("%H:%M:%S")
+                    ));
+                }
+            }
+
+            ui.separator();
+            if ui.button("Close").clicked() {
+                app.message_panel.details_uuid = None;
+            }
+        });
+    }
+}
@@ -1 +1,3 @@
 pub mod create_room;
+pub mod export_messages;
+pub mod message_details;
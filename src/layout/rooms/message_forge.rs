@@ -1,6 +1,7 @@
 use crate::app::ChatApp;
+use chrono::Utc;
 use eframe::egui;
-use egui::ComboBox;
+use egui::{ComboBox, RichText};
 
 pub struct MessageForge {}
 
@@ -10,25 +11,52 @@ impl MessageForge {
     }
 
     pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
-        let locked_model = app.model_arc.lock().unwrap();
+        let mut locked_model = app.model_arc.lock().unwrap();
 
         ui.add_space(4.0);
         ui.horizontal(|ui| {
             ui.label("Send to:");
             let forging_receiver = app.message_panel.forging_receiver.clone();
+            let participants = locked_model.room_participants(&app.message_panel.room_uuid);
+            let everyone = format!("Everyone ({})", participants.len());
 
             ComboBox::from_id_salt("Peer")
-                .selected_text(forging_receiver.name.clone())
+                .selected_text(if app.message_panel.fan_out {
+                    everyone.clone()
+                } else {
+                    forging_receiver.name.clone()
+                })
                 .show_ui(ui, |ui| {
-                    for peer in &locked_model.peers {
+                    if ui
+                        .selectable_label(app.message_panel.fan_out, everyone)
+                        .clicked()
+                    {
+                        app.message_panel.fan_out = true;
+                    }
+                    for peer in &participants {
                         if ui
-                            .selectable_label(forging_receiver.uuid == peer.uuid, peer.name.clone())
+                            .selectable_label(
+                                !app.message_panel.fan_out && forging_receiver.uuid == peer.uuid,
+                                RichText::new(format!("● {}", peer.name))
+                                    .color(locked_model.peer_presence(&peer.uuid).color()),
+                            )
                             .clicked()
                         {
                             app.message_panel.forging_receiver = peer.clone();
+                            app.message_panel.fan_out = false;
                         }
                     }
                 });
+
+            // Presence and reachability of the selected peer
+            if !app.message_panel.fan_out {
+                let presence = locked_model.peer_presence(&forging_receiver.uuid);
+                ui.label(RichText::new("●").color(presence.color()))
+                    .on_hover_text(presence.label());
+                if let Some(reachability) = locked_model.reachability(&forging_receiver) {
+                    ui.weak(reachability.describe(Utc::now()));
+                }
+            }
         });
         ui.add_space(4.0);
     }
@@ -1,87 +1,46 @@
-use std::sync::{Arc, Mutex};
-
-use crate::app::{AppEvent, ChatApp, ChatModel, MessageDirection};
+use crate::app::{AppEvent, ChatApp};
 use crate::utils::colors::COLORS;
 use crate::utils::config::Peer;
 use crate::utils::message::{ChatMessage, MessageStatus};
+use crate::utils::outbox::flush_outbox_now;
 use crate::utils::proto::generate_uuid;
-use crate::utils::socket::{Endpoint, GenericSocket, SendingSocket, TOKIO_RUNTIME};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use eframe::egui;
 use egui::{vec2, CornerRadius, TextEdit};
+use std::path::PathBuf;
 
-// Parse the whole adress to ion_id
-fn extract_ion_id_from_bp_address(bp_address: &str) -> String {
-    if let Some(after_ipn) = bp_address.strip_prefix("ipn:") {
-        if let Some(dot_pos) = after_ipn.find('.') {
-            return after_ipn[..dot_pos].to_string();
-        }
-    }
-    bp_address.to_string()
+/// Receivers picked in the settings (with a valid endpoint), and the names of the others
+fn pick_receivers(app: &ChatApp) -> (Vec<Peer>, String) {
+    let receivers = if app.message_panel.fan_out {
+        app.model_arc
+            .lock()
+            .unwrap()
+            .room_participants(&app.message_panel.room_uuid)
+    } else {
+        vec![app.message_panel.forging_receiver.clone()]
+    };
+    let (receivers, unreachable): (Vec<Peer>, Vec<Peer>) = receivers
+        .into_iter()
+        .filter(|peer| peer.name != "local peer")
+        .partition(|peer| !peer.send_endpoints().is_empty());
+    let unreachable = unreachable
+        .iter()
+        .map(|peer| peer.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    (receivers, unreachable)
 }
 
-pub fn f64_to_utc(timestamp: f64) -> DateTime<Utc> {
-    let secs = timestamp.trunc() as i64;
-    let nsecs = ((timestamp.fract()) * 1_000_000_000.0).round() as u32;
-    let naive = DateTime::from_timestamp(secs, nsecs).expect("Invalid timestamp");
-    DateTime::from_naive_utc_and_offset(naive.naive_utc(), Utc)
+fn no_receiver_status(unreachable: &str) -> String {
+    if unreachable.is_empty() {
+        "Cannot send message to local peer".to_string()
+    } else {
+        format!("No valid endpoint for {unreachable}.")
+    }
 }
 
 pub struct MessagePrompt {}
 
-pub fn manage_send(model: Arc<Mutex<ChatModel>>, msg: ChatMessage, receiver: Peer) {
-    println!("the receivers endpoint is : {:?}", receiver.endpoints[0]);
-
-    // Try to create the socket synchronously (assuming GenericSocket::new is sync)
-    match GenericSocket::new(&receiver.endpoints[0]) {
-        Ok(socket) => {
-            // Clone things to move into async task
-            let model_clone = Arc::clone(&model);
-            let msg_clone = msg.clone();
-
-            {
-                model_clone
-                    .lock()
-                    .unwrap()
-                    .add_message(msg.clone(), MessageDirection::Sent);
-            }
-
-            // Spawn async task to send the message in background
-            TOKIO_RUNTIME.spawn(async move {
-                let mut socket = socket; // mutable socket for sending
-
-                #[cfg(feature = "delayed_ack")]
-                {
-                    use std::env;
-                    use tokio::time::{sleep, Duration};
-                    // We delay the send to have a delayed ack, the message is still displayed instantly
-                    let delay_ms = env::var("DTCHAT_ACK_DELAY_MS")
-                        .ok()
-                        .and_then(|s| s.parse().ok())
-                        .unwrap_or(500); // Default to 500ms
-                    println!("delayed_ack : waiting {delay_ms} seconds before send");
-                    sleep(Duration::from_millis(delay_ms)).await;
-                }
-
-                if let Err(e) = socket.send_message(&msg_clone) {
-                    // On error, notify observers
-                    model_clone
-                        .lock()
-                        .unwrap()
-                        .notify_observers(AppEvent::Error(format!("Socket error: {e}")));
-                }
-            });
-        }
-
-        Err(_) => {
-            model
-                .lock()
-                .unwrap()
-                .notify_observers(AppEvent::Error("Socket initialization failed.".to_string()));
-        }
-    }
-}
-
 impl MessagePrompt {
     pub fn new() -> Self {
         Self {}
@@ -100,10 +59,23 @@ impl MessagePrompt {
             });
         ui.add_space(4.0);
         let mut send_message = false;
+        // Files dropped on the window are sent like the picked ones
+        let (mut files, hovering) = ui.ctx().input(|i| {
+            let dropped: Vec<PathBuf> = i
+                .raw
+                .dropped_files
+                .iter()
+                .filter_map(|f| f.path.clone())
+                .collect();
+            (dropped, !i.raw.hovered_files.is_empty())
+        });
+        if hovering {
+            ui.label("Drop the file to send it to the room");
+        }
         ui.horizontal(|ui| {
             let text_edit = TextEdit::singleline(&mut app.message_panel.message_to_send)
                 .hint_text("Write a message...")
-                .desired_width(ui.available_width() - 200.0);
+                .desired_width(ui.available_width() - 330.0);
             let response = ui.add(text_edit);
             if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                 send_message = true;
@@ -111,6 +83,14 @@ impl MessagePrompt {
             }
 
             ui.checkbox(&mut app.message_panel.pbat_enabled, "PBAT");
+            ui.checkbox(&mut app.message_panel.redundant, "Redundant")
+                .on_hover_text("Send over every valid endpoint of the peer at once");
+
+            if ui.button("📎").on_hover_text("Send a file").clicked() {
+                if let Some(path) = rfd::FileDialog::new().pick_file() {
+                    files.push(path);
+                }
+            }
 
             if ui
                 .add(
@@ -124,64 +104,61 @@ impl MessagePrompt {
                 send_message = true;
             }
         });
+        for path in files {
+            let (receivers, unreachable) = pick_receivers(app);
+            if receivers.is_empty() {
+                app.message_panel.send_status = Some(no_receiver_status(&unreachable));
+                continue;
+            }
+            let mut model = app.model_arc.lock().unwrap();
+            match model.send_file(&path, &app.message_panel.room_uuid, &receivers) {
+                Ok(()) if !unreachable.is_empty() => model.notify_observers(AppEvent::Error(
+                    format!("No valid endpoint for {unreachable}, file not sent to them."),
+                )),
+                Ok(()) => {}
+                Err(e) => {
+                    app.message_panel.send_status =
+                        Some(format!("Cannot read {}: {e}", path.display()))
+                }
+            }
+        }
         if send_message && !app.message_panel.message_to_send.trim().is_empty() {
-            let forging_receiver = app.message_panel.forging_receiver.clone();
-            if forging_receiver.name == "local peer" {
-                app.message_panel.send_status =
-                    Some("Cannot send message to local peer".to_string());
-            } else {
-                let model_clone = app.model_arc.clone();
-                let receiver_clone = forging_receiver.clone();
-
-                let mut prediction_time: Option<DateTime<Utc>> = None;
-
-                // Do the prediction here
-                if app.message_panel.pbat_enabled {
-                    let sender_ion_id = {
-                        let model_lock = app.model_arc.lock().unwrap();
-                        let sender = &model_lock.localpeer;
-                        let mut found_ion_id = None;
-
-                        // Find BP endpoint in sender's endpoints
-                        for endpoint in &sender.endpoints {
-                            if let Endpoint::Bp(bp_address) = endpoint {
-                                found_ion_id = Some(extract_ion_id_from_bp_address(bp_address));
-                                break;
-                            }
-                        }
+            let (receivers, unreachable) = pick_receivers(app);
 
-                        // Use found ION ID or fallback to UUID
-                        found_ion_id.unwrap_or_else(|| sender.uuid.clone())
+            if receivers.is_empty() {
+                app.message_panel.send_status = Some(no_receiver_status(&unreachable));
+            } else {
+                {
+                    let mut model = app.model_arc.lock().unwrap();
+                    let msg = ChatMessage {
+                        uuid: generate_uuid(),
+                        response: None,
+                        sender: model.localpeer.clone(),
+                        text: app.message_panel.message_to_send.clone(),
+                        shipment_status: MessageStatus::Draft(Utc::now()),
+                        endpoint: None,
+                        attempts: 0,
+                        history: Vec::new(),
+                        room_uuid: app.message_panel.room_uuid.clone(),
+                        recipients: Vec::new(),
+                        routing: None,
+                        predicted_ack: None,
                     };
-
-                    let receiver_ion_id =
-                        if let Endpoint::Bp(bp_address) = &forging_receiver.endpoints[0] {
-                            extract_ion_id_from_bp_address(bp_address)
-                        } else {
-                            forging_receiver.uuid.clone()
-                        };
-                    let message_size = app.message_panel.message_to_send.len() as f64;
-
-                    let model_lock = app.model_arc.lock().unwrap();
-                    if let Some(config) = &model_lock.prediction_config {
-                        if let Ok(arrival_time) =
-                            config.predict(&sender_ion_id, &receiver_ion_id, message_size)
-                        {
-                            prediction_time = Some(f64_to_utc(arrival_time));
-                        }
+                    // The PBAT, and the fragments of a message too big for a single bundle,
+                    // are computed per receiver when the outbox releases the message
+                    model.queue_message(
+                        msg,
+                        &receivers,
+                        app.message_panel.pbat_enabled,
+                        app.message_panel.redundant,
+                    );
+                    if !unreachable.is_empty() {
+                        model.notify_observers(AppEvent::Error(format!(
+                            "No valid endpoint for {unreachable}, message not sent to them."
+                        )));
                     }
                 }
-
-                let msg = ChatMessage {
-                    uuid: generate_uuid(),
-                    response: None,
-                    sender: model_clone.lock().unwrap().localpeer.clone(),
-                    text: app.message_panel.message_to_send.clone(),
-                    shipment_status: MessageStatus::Sent(Utc::now(), prediction_time),
-                };
-                TOKIO_RUNTIME.spawn_blocking(move || {
-                    manage_send(model_clone, msg, receiver_clone);
-                });
+                flush_outbox_now(app.model_arc.clone());
 
                 app.message_panel.message_to_send.clear();
             }
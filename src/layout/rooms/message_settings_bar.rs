@@ -2,7 +2,8 @@ use crate::app::ChatApp;
 use eframe::egui;
 use egui::{Align, ComboBox, Layout};
 
-use super::actions::create_room::CreateRoomForm;
+use super::actions::create_room::{CreateRoomForm, RoomDraft};
+use super::actions::export_messages::{ExportDraft, ExportForm};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RoomView {
@@ -11,6 +12,7 @@ pub enum RoomView {
     List,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for RoomView {
     fn default() -> Self {
         Self::Graph
@@ -29,7 +31,13 @@ impl MessageSettingsBar {
         ui.horizontal(|ui| {
             ui.with_layout(Layout::left_to_right(Align::Center), |ui| {
                 let locked_model = app.model_arc.lock().unwrap();
-                let default_room_selected = locked_model.rooms[0].clone();
+                let selected_room_name = locked_model
+                    .rooms
+                    .iter()
+                    .find(|room| room.uuid == app.message_panel.room_uuid)
+                    .map_or(app.message_panel.room_uuid.clone(), |room| {
+                        room.name.clone()
+                    });
 
                 ui.label("View:");
                 ComboBox::from_id_salt("message_view")
@@ -54,27 +62,53 @@ impl MessageSettingsBar {
 
                 ui.label("Room:");
                 ComboBox::from_id_salt("room_list")
-                    .selected_text(default_room_selected.name)
+                    .selected_text(selected_room_name)
                     .show_ui(ui, |ui| {
-                        for room_arc in &locked_model.rooms {
-                            let room_name = room_arc.name.clone();
+                        for room in &locked_model.rooms {
                             if ui
                                 .selectable_label(
-                                    room_arc.uuid == default_room_selected.uuid,
-                                    room_name,
+                                    room.uuid == app.message_panel.room_uuid,
+                                    room.name.clone(),
                                 )
                                 .clicked()
                             {
-                                // app.message_panel.rooms.swap(0, i);
+                                app.message_panel.room_uuid = room.uuid.clone();
+                                // Keep the receiver only if they take part in the new room
+                                let participants = locked_model.room_participants(&room.uuid);
+                                if !participants.contains(&app.message_panel.forging_receiver) {
+                                    if let Some(first) = participants.first() {
+                                        app.message_panel.forging_receiver = first.clone();
+                                    }
+                                }
                             }
                         }
                     });
             });
 
             ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                if ui.button("Export").clicked() {
+                    app.message_panel.export_draft =
+                        Some(ExportDraft::for_room(&app.message_panel.room_uuid));
+                }
                 if ui.button("New Room").clicked() {
+                    app.message_panel.room_draft = RoomDraft::default();
                     app.message_panel.create_modal_open = true;
                 }
+                if ui.button("Edit Room").clicked() {
+                    let model = app.model_arc.lock().unwrap();
+                    if let Some(room) = model
+                        .rooms
+                        .iter()
+                        .find(|room| room.uuid == app.message_panel.room_uuid)
+                    {
+                        app.message_panel.room_draft = RoomDraft {
+                            name: room.name.clone(),
+                            participants: room.participants.iter().cloned().collect(),
+                            editing: Some(room.uuid.clone()),
+                        };
+                        app.message_panel.create_modal_open = true;
+                    }
+                }
             });
         });
 
@@ -82,6 +116,9 @@ impl MessageSettingsBar {
             let mut create_room_modal = CreateRoomForm::new();
             create_room_modal.show(app, ui);
         }
+        if app.message_panel.export_draft.is_some() {
+            ExportForm::new().show(app, ui);
+        }
 
         ui.add_space(4.0);
     }
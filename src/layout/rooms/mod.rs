@@ -2,4 +2,5 @@ pub mod actions;
 pub mod message_forge;
 pub mod message_prompt;
 pub mod message_settings_bar;
+pub mod search_bar;
 pub mod views;
@@ -0,0 +1,249 @@
+use crate::app::ChatApp;
+use crate::utils::export::parse_time;
+use crate::utils::message::MessageStatus;
+use crate::utils::search::{tokenize, SearchHit, SearchQuery};
+use eframe::egui;
+use egui::text::LayoutJob;
+use egui::{Color32, ComboBox, TextFormat};
+use std::time::{Duration, Instant};
+
+/// Results listed under the search bar
+const MAX_HITS: usize = 200;
+// Results are refreshed at most this often while messages change
+const REFRESH_PERIOD: Duration = Duration::from_millis(500);
+
+/// Search criteria and results, kept between frames
+#[derive(Default)]
+pub struct SearchState {
+    pub text: String,
+    pub sender_uuid: Option<String>,
+    pub room_uuid: Option<String>,
+    pub status: Option<&'static str>,
+    /// UTC times typed by the user, `YYYY-MM-DD` or `YYYY-MM-DD HH:MM`
+    pub from: String,
+    pub to: String,
+    hits: Vec<SearchHit>,
+    total: usize,
+    // Query, index revision and time of the results
+    searched: Option<(SearchQuery, u64, Instant)>,
+    /// Message picked in the results
+    pub selected: Option<String>,
+    /// Bring the selected message into view on the next frame
+    pub jump: bool,
+}
+
+impl SearchState {
+    fn query(&self) -> SearchQuery {
+        SearchQuery {
+            text: self.text.trim().to_string(),
+            sender_uuid: self.sender_uuid.clone(),
+            room_uuid: self.room_uuid.clone(),
+            from: parse_time(&self.from, false),
+            to: parse_time(&self.to, true),
+            status: self.status,
+        }
+    }
+
+    /// Words highlighted in the messages, none when no text is searched
+    pub fn words(&self) -> Vec<String> {
+        tokenize(&self.text).collect()
+    }
+}
+
+/// `text` with the words starting with one of `words` highlighted
+pub fn highlighted(text: &str, words: &[String], color: Color32) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    append_highlighted(&mut job, text, words, color);
+    job
+}
+
+/// Append `text` to `job`, with the words starting with one of `words` highlighted
+pub fn append_highlighted(job: &mut LayoutJob, text: &str, words: &[String], color: Color32) {
+    let plain = TextFormat {
+        color,
+        ..Default::default()
+    };
+    let matched = TextFormat {
+        color: Color32::BLACK,
+        background: Color32::YELLOW,
+        ..Default::default()
+    };
+
+    let mut rest = text;
+    while !rest.is_empty() {
+        // Alternate between a run of word characters and a run of separators
+        let in_word = rest.starts_with(char::is_alphanumeric);
+        let end = rest
+            .find(|c: char| c.is_alphanumeric() != in_word)
+            .unwrap_or(rest.len());
+        let (run, tail) = rest.split_at(end);
+        let lower = run.to_lowercase();
+        let format = if in_word && words.iter().any(|word| lower.starts_with(word.as_str())) {
+            matched.clone()
+        } else {
+            plain.clone()
+        };
+        job.append(run, 0.0, format);
+        rest = tail;
+    }
+}
+
+pub struct SearchBar {}
+
+impl SearchBar {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        let mut model = app.model_arc.lock().unwrap();
+        let state = &mut app.message_panel.search;
+
+        ui.horizontal(|ui| {
+            ui.label("🔍");
+            ui.add(
+                egui::TextEdit::singleline(&mut state.text)
+                    .hint_text("Search messages")
+                    .desired_width(200.0),
+            );
+
+            let peer_name = |uuid: &Option<String>| match uuid {
+                Some(uuid) => std::iter::once(&model.localpeer)
+                    .chain(&model.peers)
+                    .find(|p| p.uuid == *uuid)
+                    .map_or_else(|| uuid.clone(), |p| p.name.clone()),
+                None => "Any sender".to_string(),
+            };
+            ComboBox::from_id_salt("search_sender")
+                .selected_text(peer_name(&state.sender_uuid))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.sender_uuid, None, "Any sender");
+                    for peer in std::iter::once(&model.localpeer).chain(&model.peers) {
+                        ui.selectable_value(
+                            &mut state.sender_uuid,
+                            Some(peer.uuid.clone()),
+                            &peer.name,
+                        );
+                    }
+                });
+
+            let room_name = match &state.room_uuid {
+                Some(uuid) => model
+                    .rooms
+                    .iter()
+                    .find(|r| r.uuid == *uuid)
+                    .map_or_else(|| uuid.clone(), |r| r.name.clone()),
+                None => "Any room".to_string(),
+            };
+            ComboBox::from_id_salt("search_room")
+                .selected_text(room_name)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.room_uuid, None, "Any room");
+                    for room in &model.rooms {
+                        ui.selectable_value(
+                            &mut state.room_uuid,
+                            Some(room.uuid.clone()),
+                            &room.name,
+                        );
+                    }
+                });
+
+            ComboBox::from_id_salt("search_status")
+                .selected_text(state.status.unwrap_or("Any status"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.status, None, "Any status");
+                    for status in MessageStatus::LABELS {
+                        ui.selectable_value(&mut state.status, Some(status), status);
+                    }
+                });
+
+            ui.label("From");
+            let from_valid =
+                state.from.trim().is_empty() || parse_time(&state.from, false).is_some();
+            ui.add(
+                egui::TextEdit::singleline(&mut state.from)
+                    .hint_text("YYYY-MM-DD HH:MM")
+                    .desired_width(120.0)
+                    .text_color_opt((!from_valid).then_some(Color32::RED)),
+            )
+            .on_hover_text("UTC");
+            ui.label("to");
+            let to_valid = state.to.trim().is_empty() || parse_time(&state.to, true).is_some();
+            ui.add(
+                egui::TextEdit::singleline(&mut state.to)
+                    .hint_text("YYYY-MM-DD HH:MM")
+                    .desired_width(120.0)
+                    .text_color_opt((!to_valid).then_some(Color32::RED)),
+            )
+            .on_hover_text("UTC");
+
+            if ui.button("Clear").clicked() {
+                *state = SearchState::default();
+            }
+        });
+
+        let query = state.query();
+        if query.is_empty() {
+            state.hits.clear();
+            state.searched = None;
+            return;
+        }
+
+        let outdated = match &state.searched {
+            Some((searched, revision, at)) => {
+                *searched != query
+                    || (model.search_outdated(*revision) && at.elapsed() > REFRESH_PERIOD)
+            }
+            None => true,
+        };
+        if outdated {
+            (state.hits, state.total) = model.search(&query, MAX_HITS);
+            state.searched = Some((query, model.search_revision(), Instant::now()));
+        }
+
+        ui.label(if state.total > state.hits.len() {
+            format!("{} matches, latest {} shown", state.total, state.hits.len())
+        } else {
+            format!("{} matches", state.total)
+        });
+
+        let words = state.words();
+        let mut picked = None;
+        egui::ScrollArea::vertical()
+            .max_height(150.0)
+            .auto_shrink([false, true])
+            .show(ui, |ui| {
+                for hit in &state.hits {
+                    let sender = std::iter::once(&model.localpeer)
+                        .chain(&model.peers)
+                        .find(|p| p.uuid == hit.sender_uuid);
+                    let room = model
+                        .rooms
+                        .iter()
+                        .find(|r| r.uuid == hit.room_uuid)
+                        .map_or(hit.room_uuid.as_str(), |r| r.name.as_str());
+                    ui.horizontal(|ui| {
+                        let selected = state.selected.as_ref() == Some(&hit.uuid);
+                        let header = format!(
+                            "{} · {} · {}:",
+                            hit.tx.format("%Y-%m-%d %H:%M:%S"),
+                            room,
+                            sender.map_or(hit.sender_uuid.as_str(), |p| p.name.as_str())
+                        );
+                        if ui.selectable_label(selected, header).clicked() {
+                            picked = Some(hit.clone());
+                        }
+                        let color = sender.map_or(Color32::GRAY, |p| p.get_color());
+                        ui.label(highlighted(&hit.text, &words, color));
+                    });
+                }
+            });
+        drop(model);
+
+        if let Some(hit) = picked {
+            app.message_panel.room_uuid = hit.room_uuid;
+            app.message_panel.search.selected = Some(hit.uuid);
+            app.message_panel.search.jump = true;
+        }
+    }
+}
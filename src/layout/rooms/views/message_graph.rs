@@ -1,9 +1,13 @@
 use std::{collections::HashMap, ops::RangeInclusive};
 
 use crate::app::ChatApp;
+use crate::utils::message::MessageStatus;
 use chrono::{DateTime, Local, Utc};
 use egui::{Color32, Vec2b};
-use egui_plot::{AxisHints, BoxElem, BoxPlot, BoxSpread, GridMark, Legend, Plot, VLine};
+use egui_plot::{
+    AxisHints, BoxElem, BoxPlot, BoxSpread, GridMark, Legend, Line, LineStyle, Plot, PlotBounds,
+    VLine,
+};
 pub struct MessageGraphView {}
 
 trait AutoReset {
@@ -47,10 +51,22 @@ impl MessageGraphView {
         let now = Local::now().timestamp_millis() as f64;
         // + Local::now().timestamp_subsec_millis() as f64 / 1000.0;
 
-        let locked_model = app.model_arc.lock().unwrap();
+        let mut locked_model = app.model_arc.lock().unwrap();
         let mut per_sender = HashMap::new();
-
-        for (index, message) in locked_model.messages.iter().enumerate() {
+        // Received messages with their row and time span, to find the ones in view
+        let mut received = Vec::new();
+        // Predicted round trips: row, transmission, delivery and ACK times
+        let mut round_trips = Vec::new();
+
+        let messages = locked_model.room_messages(&app.message_panel.room_uuid);
+        let message_count = messages.len();
+        // Row and transmission time of the message picked in the search results
+        let selected = app.message_panel.search.selected.as_ref().and_then(|uuid| {
+            let index = messages.iter().position(|m| m.uuid == *uuid)?;
+            Some((index as f64, messages[index].get_timestamps().0))
+        });
+        let jump = std::mem::take(&mut app.message_panel.search.jump);
+        for (index, message) in messages.iter().enumerate() {
             let key = message.sender.uuid.clone();
             per_sender
                 .entry(key)
@@ -59,20 +75,42 @@ impl MessageGraphView {
             if let Some((_sender, box_elems)) = per_sender.get_mut(&message.sender.uuid) {
                 let (tx, pbat_opt, rx_opt) = message.get_timestamps();
 
-                let upper_whisker = if let Some(received) = rx_opt {
-                    received - 1.0
-                } else if let Some(pbat) = pbat_opt {
-                    pbat
-                } else {
-                    tx - 1.0
+                let upper_whisker = match &message.shipment_status {
+                    // Still waiting: grow with the current time
+                    MessageStatus::Draft(_) | MessageStatus::Queued(_) => now,
+                    // Up to the time the message was given up
+                    MessageStatus::Failed(_, at, _) | MessageStatus::Expired(_, at) => {
+                        at.timestamp_millis() as f64
+                    }
+                    _ => match (rx_opt, pbat_opt) {
+                        (Some(received), _) => received - 1.0,
+                        (None, Some(pbat)) => pbat,
+                        (None, None) => tx - 1.0,
+                    },
+                };
+
+                let name = match &message.shipment_status {
+                    MessageStatus::Sent(..) | MessageStatus::Received(..) => message.text.clone(),
+                    status => format!("[{}] {}", status.label(), message.text),
                 };
 
+                if matches!(message.shipment_status, MessageStatus::Received(..)) {
+                    received.push((message.uuid.clone(), index as f64, tx, upper_whisker));
+                }
+
+                if let Some(pbat) = message.predicted_arrival() {
+                    let ack = message
+                        .predicted_ack
+                        .map(|ack| ack.timestamp_millis() as f64);
+                    round_trips.push((index as f64, tx, pbat.timestamp_millis() as f64, ack));
+                }
+
                 box_elems.push(
                     BoxElem::new(
                         index as f64,
                         BoxSpread::new(tx + 1.0, tx, tx, rx_opt.unwrap_or(tx), upper_whisker),
                     )
-                    .name(message.text.clone()),
+                    .name(name),
                 );
             };
         }
@@ -89,12 +127,15 @@ impl MessageGraphView {
             .placement(egui_plot::VPlacement::Top)];
 
         let reset_requested = ui.button("Reset view").clicked();
+        // Only a focused window counts as read
+        let focused = ui.input(|i| i.focused);
+        let mut displayed = Vec::new();
         Plot::new("Box Plot Demo")
             .legend(Legend::default())
             .allow_zoom(true)
             .allow_drag(true)
             .custom_x_axes(x_axes)
-            .include_y((locked_model.messages.len() + 1) as f64)
+            .include_y((message_count + 1) as f64)
             .custom_y_axes(vec![])
             .allow_scroll(Vec2b { x: true, y: false })
             .allow_drag(Vec2b { x: true, y: false })
@@ -116,6 +157,40 @@ impl MessageGraphView {
                         .color(Color32::from_rgb(255, 0, 0)),
                 );
 
+                if let Some((y, tx)) = selected {
+                    plot_ui.vline(
+                        VLine::new("Selected message", tx)
+                            .name("Selected message")
+                            .color(Color32::YELLOW),
+                    );
+                    if jump {
+                        // Keep the zoom, centred on the message
+                        let bounds = plot_ui.plot_bounds();
+                        let (half_width, half_height) =
+                            (bounds.width() / 2.0, bounds.height() / 2.0);
+                        plot_ui.set_plot_bounds(PlotBounds::from_min_max(
+                            [tx - half_width, y - half_height],
+                            [tx + half_width, y + half_height],
+                        ));
+                    }
+                }
+
+                for (y, tx, pbat, ack) in round_trips {
+                    plot_ui.line(
+                        Line::new("Predicted delivery", vec![[tx, y], [pbat, y]])
+                            .color(Color32::LIGHT_BLUE)
+                            .width(2.0),
+                    );
+                    if let Some(ack) = ack {
+                        plot_ui.line(
+                            Line::new("Predicted ACK", vec![[pbat, y], [ack, y]])
+                                .color(Color32::ORANGE)
+                                .style(LineStyle::dashed_loose())
+                                .width(2.0),
+                        );
+                    }
+                }
+
                 for (_uuid, (peer, boxes)) in per_sender {
                     let peer_name = peer.name.clone();
 
@@ -145,7 +220,18 @@ impl MessageGraphView {
 
                     plot_ui.box_plot(box_for_senders);
                 }
+
+                if focused {
+                    let bounds = plot_ui.plot_bounds();
+                    let (min, max) = (bounds.min(), bounds.max());
+                    for (uuid, y, start, end) in &received {
+                        if (min[1]..=max[1]).contains(y) && *start <= max[0] && *end >= min[0] {
+                            displayed.push(uuid.clone());
+                        }
+                    }
+                }
             });
+        locked_model.mark_displayed(&displayed);
 
         let ctx = app.handler_arc.lock().unwrap().ctx.clone();
         ctx.request_repaint();
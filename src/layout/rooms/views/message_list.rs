@@ -1,4 +1,9 @@
 use crate::app::{ChatApp, SortStrategy};
+use crate::layout::rooms::actions::message_details::MessageDetails;
+use crate::layout::rooms::search_bar::append_highlighted;
+use crate::utils::file_transfer::{TransferProgress, TransferState};
+use crate::utils::message::MessageStatus;
+use crate::utils::socket::Endpoint;
 
 pub struct MessageListView {}
 
@@ -15,6 +20,49 @@ fn get_str_for_strat(local_peer_uuid: &String, strat: &SortStrategy) -> String {
     }
 }
 
+fn endpoint_str(endpoint: &Option<Endpoint>) -> String {
+    match endpoint {
+        Some(endpoint) => endpoint.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Progress bar of a file message, with the folder of a received file
+fn show_transfer(ui: &mut egui::Ui, progress: &TransferProgress) {
+    let fraction = progress.done as f32 / progress.total.max(1) as f32;
+    ui.add(
+        egui::ProgressBar::new(fraction)
+            .desired_width(120.0)
+            .text(format!("{}/{} chunks", progress.done, progress.total)),
+    );
+    match &progress.state {
+        TransferState::InProgress => {
+            if let Some(completion) = progress.completion {
+                ui.label(format!("done ~{}", completion.format("%H:%M:%S")))
+                    .on_hover_text("Predicted arrival of the last chunk");
+            }
+            ui.ctx()
+                .request_repaint_after(std::time::Duration::from_millis(500));
+        }
+        TransferState::Complete => {}
+        TransferState::Saved(path) => {
+            if ui
+                .small_button("Open folder")
+                .on_hover_text(path.display().to_string())
+                .clicked()
+            {
+                let folder = path.parent().unwrap_or(path);
+                if let Err(e) = open::that(folder) {
+                    eprintln!("Failed to open {}: {e}", folder.display());
+                }
+            }
+        }
+        TransferState::Failed(reason) => {
+            ui.colored_label(egui::Color32::RED, reason);
+        }
+    }
+}
+
 impl MessageListView {
     pub fn new() -> Self {
         Self {}
@@ -25,6 +73,13 @@ impl MessageListView {
         //let sort_for_peer = locked_model.localpeer.clone();
         let sort_strat = locked_model.sort_strategy.clone();
         let local_peer = locked_model.localpeer.clone();
+        // Only a focused window counts as read
+        let focused = ui.input(|i| i.focused);
+        let mut displayed = Vec::new();
+        let mut clicked = None;
+        let words = app.message_panel.search.words();
+        let selected = app.message_panel.search.selected.clone();
+        let mut jump = app.message_panel.search.jump;
 
         egui::ScrollArea::vertical()
             .auto_shrink([false; 2])
@@ -59,20 +114,76 @@ impl MessageListView {
                     });
                 });
 
-                for message in &locked_model.messages {
-                    ui.horizontal(|ui| {
+                for message in locked_model.room_messages(&app.message_panel.room_uuid) {
+                    let row = ui.horizontal(|ui| {
                         let color = message.sender.get_color();
                         let sent_by_me = local_peer.uuid == message.sender.uuid;
-                        ui.label(
-                            egui::RichText::new(format!(
-                                "{}: {}",
-                                message.get_shipment_status_str(sent_by_me),
-                                message.text
-                            ))
-                            .color(color),
+                        let via = match locked_model.path_arrivals.arrivals(&message.uuid) {
+                            // Compare the latency of each path to the first copy received
+                            arrivals if arrivals.len() > 1 => {
+                                let first = &arrivals[0];
+                                let mut paths = vec![format!("first via {}", endpoint_str(&first.endpoint))];
+                                for arrival in &arrivals[1..] {
+                                    paths.push(format!(
+                                        "+{}ms via {}",
+                                        (arrival.rx_time - first.rx_time).num_milliseconds(),
+                                        endpoint_str(&arrival.endpoint)
+                                    ));
+                                }
+                                format!("[{}]", paths.join(", "))
+                            }
+                            _ => match &message.endpoint {
+                                Some(endpoint) => format!("[via {endpoint}]"),
+                                None => String::new(),
+                            },
+                        };
+                        let mut job = egui::text::LayoutJob::default();
+                        append_highlighted(
+                            &mut job,
+                            &format!("{}{}: ", message.get_shipment_status_str(sent_by_me), via),
+                            &[],
+                            color,
                         );
+                        append_highlighted(&mut job, &message.text, &words, color);
+                        let label = ui.add(egui::Label::new(job).sense(egui::Sense::click()))
+                        .on_hover_text("Click for details");
+                        if let Some(progress) = locked_model.transfers.progress(&message.uuid) {
+                            show_transfer(ui, &progress);
+                        }
+                        label
                     });
+                    if row.inner.clicked() {
+                        clicked = Some(message.uuid.clone());
+                    }
+                    // Message picked in the search results
+                    if selected.as_ref() == Some(&message.uuid) {
+                        ui.painter().rect_stroke(
+                            row.response.rect.expand(2.0),
+                            2.0,
+                            egui::Stroke::new(1.5, egui::Color32::YELLOW),
+                            egui::StrokeKind::Outside,
+                        );
+                        if jump {
+                            row.response.scroll_to_me(Some(egui::Align::Center));
+                            jump = false;
+                        }
+                    }
+                    if focused
+                        && matches!(message.shipment_status, MessageStatus::Received(..))
+                        && ui.is_rect_visible(row.response.rect)
+                    {
+                        displayed.push(message.uuid.clone());
+                    }
                 }
             });
+
+        locked_model.mark_displayed(&displayed);
+        drop(locked_model);
+        app.message_panel.search.jump = jump;
+
+        if clicked.is_some() {
+            app.message_panel.details_uuid = clicked;
+        }
+        MessageDetails::new().show(app, ui);
     }
 }
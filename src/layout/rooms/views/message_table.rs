@@ -0,0 +1,440 @@
+use crate::app::ChatApp;
+use crate::utils::config::Peer;
+use crate::utils::message::{ChatMessage, MessageStatus};
+use crate::utils::proto::encoded_size;
+use crate::utils::socket::Endpoint;
+use chrono::{DateTime, Utc};
+use eframe::egui;
+use egui::ComboBox;
+use egui_extras::{Column, TableBuilder};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Repaint period, for the changes made by the workers to show up
+const REFRESH_PERIOD: Duration = Duration::from_secs(1);
+const ROW_HEIGHT: f32 = 18.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableColumn {
+    Uuid,
+    Sender,
+    Recipient,
+    Room,
+    Tx,
+    Pbat,
+    PredictedAck,
+    Routing,
+    Rx,
+    Error,
+    Endpoint,
+    Size,
+    Status,
+}
+
+impl TableColumn {
+    const ALL: [TableColumn; 13] = [
+        Self::Uuid,
+        Self::Sender,
+        Self::Recipient,
+        Self::Room,
+        Self::Tx,
+        Self::Pbat,
+        Self::PredictedAck,
+        Self::Routing,
+        Self::Rx,
+        Self::Error,
+        Self::Endpoint,
+        Self::Size,
+        Self::Status,
+    ];
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Uuid => "UUID",
+            Self::Sender => "Sender",
+            Self::Recipient => "Recipient",
+            Self::Room => "Room",
+            Self::Tx => "Tx time",
+            Self::Pbat => "PBAT",
+            Self::PredictedAck => "Pred. ACK",
+            Self::Routing => "Routing",
+            Self::Rx => "Rx/ACK time",
+            Self::Error => "Pred. error",
+            Self::Endpoint => "Endpoint",
+            Self::Size => "Size (B)",
+            Self::Status => "Status",
+        }
+    }
+}
+
+/// One delivery of a message: a message sent to several peers gives one row per recipient
+struct MessageRow {
+    uuid: String,
+    sender_uuid: String,
+    sender: String,
+    recipient_uuid: String,
+    recipient: String,
+    room: String,
+    tx: DateTime<Utc>,
+    pbat: Option<DateTime<Utc>>,
+    /// Predicted return of the ACK, routed from `pbat`
+    predicted_ack: Option<DateTime<Utc>>,
+    /// Algorithm that produced `pbat`
+    routing: String,
+    rx: Option<DateTime<Utc>>,
+    endpoint: String,
+    size: usize,
+    status: &'static str,
+}
+
+fn time_str(time: Option<DateTime<Utc>>) -> String {
+    time.map_or_else(String::new, |t| {
+        t.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+    })
+}
+
+impl MessageRow {
+    /// Actual minus predicted arrival, in seconds
+    fn error(&self) -> Option<f64> {
+        Some((self.rx? - self.pbat?).num_milliseconds() as f64 / 1000.0)
+    }
+
+    fn cell(&self, column: TableColumn) -> String {
+        match column {
+            TableColumn::Uuid => self.uuid.clone(),
+            TableColumn::Sender => self.sender.clone(),
+            TableColumn::Recipient => self.recipient.clone(),
+            TableColumn::Room => self.room.clone(),
+            TableColumn::Tx => time_str(Some(self.tx)),
+            TableColumn::Pbat => time_str(self.pbat),
+            TableColumn::PredictedAck => time_str(self.predicted_ack),
+            TableColumn::Routing => self.routing.clone(),
+            TableColumn::Rx => time_str(self.rx),
+            TableColumn::Error => self
+                .error()
+                .map_or_else(String::new, |error| format!("{error:+.3} s")),
+            TableColumn::Endpoint => self.endpoint.clone(),
+            TableColumn::Size => self.size.to_string(),
+            TableColumn::Status => self.status.to_string(),
+        }
+    }
+
+    fn cmp_by(&self, other: &Self, column: TableColumn) -> Ordering {
+        match column {
+            TableColumn::Uuid => self.uuid.cmp(&other.uuid),
+            TableColumn::Sender => self.sender.cmp(&other.sender),
+            TableColumn::Recipient => self.recipient.cmp(&other.recipient),
+            TableColumn::Room => self.room.cmp(&other.room),
+            TableColumn::Tx => self.tx.cmp(&other.tx),
+            TableColumn::Pbat => self.pbat.cmp(&other.pbat),
+            TableColumn::PredictedAck => self.predicted_ack.cmp(&other.predicted_ack),
+            TableColumn::Routing => self.routing.cmp(&other.routing),
+            TableColumn::Rx => self.rx.cmp(&other.rx),
+            TableColumn::Error => self
+                .error()
+                .partial_cmp(&other.error())
+                .unwrap_or(Ordering::Equal),
+            TableColumn::Endpoint => self.endpoint.cmp(&other.endpoint),
+            TableColumn::Size => self.size.cmp(&other.size),
+            TableColumn::Status => self.status.cmp(other.status),
+        }
+    }
+
+    /// Tab-separated cells, pasted as a row by spreadsheets
+    fn to_tsv(&self) -> String {
+        TableColumn::ALL
+            .iter()
+            .map(|column| self.cell(*column))
+            .collect::<Vec<_>>()
+            .join("\t")
+    }
+}
+
+/// Sorting, filters and rows of the table, kept between frames
+pub struct MessageTableState {
+    sort_column: TableColumn,
+    ascending: bool,
+    /// Sender or recipient UUID
+    peer_filter: Option<String>,
+    status_filter: Option<&'static str>,
+    room_filter: Option<String>,
+    rows: Vec<MessageRow>,
+    // Model revision of the last build, None to rebuild on the next frame
+    built: Option<u64>,
+    // Serialized size of each message, with the text length it was computed for
+    sizes: HashMap<String, (usize, usize)>,
+}
+
+impl Default for MessageTableState {
+    fn default() -> Self {
+        Self {
+            sort_column: TableColumn::Tx,
+            ascending: true,
+            peer_filter: None,
+            status_filter: None,
+            room_filter: None,
+            rows: Vec::new(),
+            built: None,
+            sizes: HashMap::new(),
+        }
+    }
+}
+
+impl MessageTableState {
+    fn needs_rebuild(&self, revision: u64) -> bool {
+        self.built != Some(revision)
+    }
+
+    /// `sizes` holds the sizes of the previous build, reused while the text is the same
+    fn push_rows(
+        &mut self,
+        message: &ChatMessage,
+        room: &str,
+        local_peer: &Peer,
+        sizes: &HashMap<String, (usize, usize)>,
+    ) {
+        let size = match sizes.get(&message.uuid) {
+            Some((text_len, size)) if *text_len == message.text.len() => *size,
+            _ => encoded_size(message),
+        };
+        self.sizes
+            .insert(message.uuid.clone(), (message.text.len(), size));
+        let endpoint = |endpoint: &Option<Endpoint>| {
+            endpoint
+                .as_ref()
+                .map_or_else(String::new, |e| e.to_string())
+        };
+        let row = |recipient_uuid: &str, recipient: &str| MessageRow {
+            uuid: message.uuid.clone(),
+            sender_uuid: message.sender.uuid.clone(),
+            sender: message.sender.name.clone(),
+            recipient_uuid: recipient_uuid.to_string(),
+            recipient: recipient.to_string(),
+            room: room.to_string(),
+            tx: message.shipment_status.tx(),
+            pbat: message.predicted_arrival(),
+            predicted_ack: message.predicted_ack,
+            routing: message.routing.clone().unwrap_or_default(),
+            rx: message.shipment_status.rx(),
+            endpoint: endpoint(&message.endpoint),
+            size,
+            status: message.shipment_status.label(),
+        };
+
+        if message.recipients.is_empty() {
+            // Received, or sent before the per-recipient tracking
+            self.rows.push(row(&local_peer.uuid, &local_peer.name));
+            return;
+        }
+        for recipient in &message.recipients {
+            self.rows.push(MessageRow {
+                tx: recipient.status.tx(),
+                pbat: recipient.pbat,
+                predicted_ack: recipient.predicted_ack,
+                routing: recipient.routing.clone().unwrap_or_default(),
+                rx: recipient.status.rx(),
+                endpoint: endpoint(&recipient.endpoint),
+                status: recipient.status.label(),
+                ..row(&recipient.peer.uuid, &recipient.peer.name)
+            });
+        }
+    }
+
+    fn keep(&self, row: &MessageRow) -> bool {
+        let peer_ok = self
+            .peer_filter
+            .as_ref()
+            .is_none_or(|uuid| row.sender_uuid == *uuid || row.recipient_uuid == *uuid);
+        let status_ok = self.status_filter.is_none_or(|status| row.status == status);
+        peer_ok && status_ok
+    }
+}
+
+pub struct MessageTableView {}
+
+impl MessageTableView {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        let model = app.model_arc.lock().unwrap();
+        let state = &mut app.message_panel.table;
+
+        // Filters
+        ui.horizontal(|ui| {
+            let before = (
+                state.peer_filter.clone(),
+                state.status_filter,
+                state.room_filter.clone(),
+            );
+
+            ui.label("Room:");
+            let room_name = |uuid: &Option<String>| match uuid {
+                Some(uuid) => model
+                    .rooms
+                    .iter()
+                    .find(|r| r.uuid == *uuid)
+                    .map_or_else(|| uuid.clone(), |r| r.name.clone()),
+                None => "All".to_string(),
+            };
+            ComboBox::from_id_salt("table_room_filter")
+                .selected_text(room_name(&state.room_filter))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.room_filter, None, "All");
+                    for room in &model.rooms {
+                        ui.selectable_value(
+                            &mut state.room_filter,
+                            Some(room.uuid.clone()),
+                            &room.name,
+                        );
+                    }
+                });
+
+            ui.label("Peer:");
+            let peer_name = match &state.peer_filter {
+                Some(uuid) => model
+                    .peers
+                    .iter()
+                    .chain(std::iter::once(&model.localpeer))
+                    .find(|p| p.uuid == *uuid)
+                    .map_or_else(|| uuid.clone(), |p| p.name.clone()),
+                None => "All".to_string(),
+            };
+            ComboBox::from_id_salt("table_peer_filter")
+                .selected_text(peer_name)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.peer_filter, None, "All");
+                    for peer in std::iter::once(&model.localpeer).chain(&model.peers) {
+                        ui.selectable_value(
+                            &mut state.peer_filter,
+                            Some(peer.uuid.clone()),
+                            &peer.name,
+                        );
+                    }
+                });
+
+            ui.label("Status:");
+            ComboBox::from_id_salt("table_status_filter")
+                .selected_text(state.status_filter.unwrap_or("All"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.status_filter, None, "All");
+                    for status in MessageStatus::LABELS {
+                        ui.selectable_value(&mut state.status_filter, Some(status), status);
+                    }
+                });
+
+            if before
+                != (
+                    state.peer_filter.clone(),
+                    state.status_filter,
+                    state.room_filter.clone(),
+                )
+            {
+                state.built = None;
+            }
+            ui.label(format!("{} rows", state.rows.len()));
+        });
+        ui.separator();
+
+        if state.needs_rebuild(model.revision()) {
+            state.rows.clear();
+            // Only the sizes of the messages still shown are kept
+            let sizes = std::mem::take(&mut state.sizes);
+            for (room_uuid, messages) in &model.messages {
+                if state
+                    .room_filter
+                    .as_ref()
+                    .is_some_and(|uuid| uuid != room_uuid)
+                {
+                    continue;
+                }
+                let room = model
+                    .rooms
+                    .iter()
+                    .find(|r| r.uuid == *room_uuid)
+                    .map_or_else(|| room_uuid.clone(), |r| r.name.clone());
+                for message in messages {
+                    state.push_rows(message, &room, &model.localpeer, &sizes);
+                }
+            }
+            let rows = std::mem::take(&mut state.rows);
+            state.rows = rows.into_iter().filter(|row| state.keep(row)).collect();
+            let (column, ascending) = (state.sort_column, state.ascending);
+            state.rows.sort_by(|a, b| {
+                let ordering = a.cmp_by(b, column);
+                if ascending {
+                    ordering
+                } else {
+                    ordering.reverse()
+                }
+            });
+            state.built = Some(model.revision());
+        }
+        drop(model);
+        ui.ctx().request_repaint_after(REFRESH_PERIOD);
+
+        let mut table = TableBuilder::new(ui)
+            .striped(true)
+            .resizable(true)
+            .auto_shrink([false; 2])
+            .cell_layout(egui::Layout::left_to_right(egui::Align::Center));
+        for _ in TableColumn::ALL {
+            table = table.column(Column::auto().at_least(60.0).clip(true));
+        }
+        table = table.column(Column::remainder());
+
+        let mut sort_by = None;
+        table
+            .header(ROW_HEIGHT + 4.0, |mut header| {
+                for column in TableColumn::ALL {
+                    header.col(|ui| {
+                        let arrow = match (state.sort_column == column, state.ascending) {
+                            (true, true) => " ⬆",
+                            (true, false) => " ⬇",
+                            (false, _) => "",
+                        };
+                        if ui
+                            .button(format!("{}{arrow}", column.title()))
+                            .on_hover_text("Sort by this column")
+                            .clicked()
+                        {
+                            sort_by = Some(column);
+                        }
+                    });
+                }
+                header.col(|_| {});
+            })
+            .body(|body| {
+                // Only the visible rows are laid out
+                body.rows(ROW_HEIGHT, state.rows.len(), |mut table_row| {
+                    let row = &state.rows[table_row.index()];
+                    for column in TableColumn::ALL {
+                        table_row.col(|ui| {
+                            ui.label(row.cell(column));
+                        });
+                    }
+                    table_row.col(|ui| {
+                        if ui
+                            .small_button("📋")
+                            .on_hover_text("Copy the row")
+                            .clicked()
+                        {
+                            ui.ctx().copy_text(row.to_tsv());
+                        }
+                    });
+                });
+            });
+
+        if let Some(column) = sort_by {
+            if state.sort_column == column {
+                state.ascending = !state.ascending;
+            } else {
+                state.sort_column = column;
+                state.ascending = true;
+            }
+            state.built = None;
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod message_graph;
 pub mod message_list;
+pub mod message_table;
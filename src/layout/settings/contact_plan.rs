@@ -0,0 +1,84 @@
+use crate::app::ChatApp;
+use crate::utils::prediction_config::{reload_contact_plan, EpochSource};
+use crate::utils::socket::TOKIO_RUNTIME;
+use chrono::{DateTime, Utc};
+use eframe::egui;
+use egui::{Color32, Grid, RichText};
+
+fn time_str(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+pub struct ContactPlanSettingsView {}
+
+impl ContactPlanSettingsView {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        ui.heading("Contact plan");
+        let model = app.model_arc.lock().unwrap();
+
+        let reload = ui
+            .horizontal(|ui| {
+                if let Some(source) = &model.contact_plan {
+                    ui.label(format!("File: {}", source.path));
+                }
+                ui.add_enabled(
+                    model.contact_plan.is_some(),
+                    egui::Button::new("Reload contact plan"),
+                )
+                .on_hover_text("The file is also reloaded when it changes")
+                .clicked()
+            })
+            .inner;
+        if let Some(status) = &model.contact_plan_status {
+            ui.label(status);
+        }
+
+        match &model.prediction_config {
+            None => {
+                ui.label("No contact plan loaded, the PBAT is not available.");
+            }
+            Some(config) => {
+                let (epoch, source) = config.epoch();
+                Grid::new("contact_plan_settings")
+                    .num_columns(2)
+                    .spacing([8.0, 6.0])
+                    .show(ui, |ui| {
+                        ui.label("Epoch (+0):");
+                        ui.label(format!("{} ({})", time_str(epoch), source.label()));
+                        ui.end_row();
+
+                        ui.label("Contacts:");
+                        ui.label(match config.plan_span() {
+                            Some((start, end)) => {
+                                format!("{} to {}", time_str(start), time_str(end))
+                            }
+                            None => "none".to_string(),
+                        });
+                        ui.end_row();
+                    });
+
+                if source == EpochSource::AppStart {
+                    ui.label(
+                        RichText::new(
+                            "⚠ Set contact_plan_epoch so that every node reads the plan from the same time",
+                        )
+                        .color(Color32::ORANGE),
+                    );
+                }
+                if let Some(warning) = config.time_warning(Utc::now()) {
+                    ui.label(RichText::new(format!("⚠ {warning}")).color(Color32::ORANGE));
+                }
+            }
+        }
+        drop(model);
+
+        if reload {
+            let model = app.model_arc.clone();
+            TOKIO_RUNTIME.spawn_blocking(move || reload_contact_plan(&model));
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod contact_plan;
+pub mod routing;
@@ -0,0 +1,83 @@
+use crate::app::ChatApp;
+use crate::utils::config::ContactManagerKind;
+use crate::utils::prediction_config::ROUTER_NAMES;
+use eframe::egui;
+use egui::{ComboBox, Grid};
+
+pub struct RoutingSettingsView {}
+
+impl RoutingSettingsView {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show(&mut self, app: &mut ChatApp, ui: &mut egui::Ui) {
+        ui.heading("Routing");
+        let current = {
+            let model = app.model_arc.lock().unwrap();
+            model
+                .prediction_config
+                .as_ref()
+                .map(|config| config.routing().clone())
+        };
+        let Some(current) = current else {
+            ui.label("No contact plan loaded, the PBAT is not available.");
+            return;
+        };
+        ui.label(format!("Predictions use {}", current.label()));
+        ui.add_space(6.0);
+
+        let panel = &mut app.settings_panel;
+        let draft = panel.routing_draft.get_or_insert_with(|| current.clone());
+        Grid::new("routing_settings")
+            .num_columns(2)
+            .spacing([8.0, 6.0])
+            .show(ui, |ui| {
+                ui.label("Router:");
+                ComboBox::from_id_salt("routing_router")
+                    .selected_text(&draft.router)
+                    .show_ui(ui, |ui| {
+                        for router in ROUTER_NAMES {
+                            ui.selectable_value(&mut draft.router, router.to_string(), router);
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Contact manager:");
+                ui.horizontal(|ui| {
+                    for manager in ContactManagerKind::ALL {
+                        ui.radio_value(&mut draft.contact_manager, manager, manager.label());
+                    }
+                });
+                ui.end_row();
+            });
+
+        ui.add_space(6.0);
+        let changed = *draft != current;
+        let (apply, reset) = ui
+            .horizontal(|ui| {
+                (
+                    ui.add_enabled(changed, egui::Button::new("Apply"))
+                        .clicked(),
+                    ui.add_enabled(changed, egui::Button::new("Reset"))
+                        .clicked(),
+                )
+            })
+            .inner;
+        if apply {
+            let routing = draft.clone();
+            let label = routing.label();
+            let result = app.model_arc.lock().unwrap().set_routing(routing);
+            panel.status = Some(match result {
+                Ok(()) => format!("Routing switched to {label}"),
+                Err(e) => format!("Failed to switch the routing: {e}"),
+            });
+        }
+        if reset {
+            panel.routing_draft = Some(current);
+        }
+        if let Some(status) = &panel.status {
+            ui.label(status);
+        }
+    }
+}
@@ -1,9 +1,15 @@
 use super::{
+    contacts::contact_list::ContactListView,
     menu_bar::NavigationItems,
     rooms::{
         message_settings_bar::{MessageSettingsBar, RoomView},
-        views::{message_graph::MessageGraphView, message_list::MessageListView},
+        search_bar::SearchBar,
+        views::{
+            message_graph::MessageGraphView, message_list::MessageListView,
+            message_table::MessageTableView,
+        },
     },
+    settings::{contact_plan::ContactPlanSettingsView, routing::RoutingSettingsView},
 };
 use crate::app::ChatApp;
 use crate::layout::menu_bar::MenuBar;
@@ -24,6 +30,10 @@ pub fn display(app: &mut ChatApp, ctx: &egui::Context) {
                 MessageSettingsBar::new().show(app, ui);
             });
 
+            TopBottomPanel::top("search_bar").show(ctx, |ui| {
+                SearchBar::new().show(app, ui);
+            });
+
             TopBottomPanel::bottom("message_inputs_panel").show(ctx, |ui| {
                 let mut forge = MessageForge::new();
                 forge.show(app, ui);
@@ -40,7 +50,8 @@ pub fn display(app: &mut ChatApp, ctx: &egui::Context) {
 
             CentralPanel::default().show(ctx, |ui| match app.message_panel.message_view {
                 RoomView::Table => {
-                    ui.label("Table View");
+                    let mut message_table = MessageTableView::new();
+                    message_table.show(app, ui);
                 }
                 RoomView::Graph => {
                     let mut message_graph = MessageGraphView::new();
@@ -54,7 +65,15 @@ pub fn display(app: &mut ChatApp, ctx: &egui::Context) {
         }
         NavigationItems::Contacts => {
             CentralPanel::default().show(ctx, |ui| {
-                ui.label("Contacts");
+                let mut contact_list = ContactListView::new();
+                contact_list.show(app, ui);
+            });
+        }
+        NavigationItems::Settings => {
+            CentralPanel::default().show(ctx, |ui| {
+                ContactPlanSettingsView::new().show(app, ui);
+                ui.separator();
+                RoutingSettingsView::new().show(app, ui);
             });
         }
     }
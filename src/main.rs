@@ -1,3 +1,4 @@
+use chrono::Utc;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 mod app;
@@ -6,20 +7,22 @@ mod utils;
 
 use app::{ChatApp, ChatModel, EventHandler};
 
-#[cfg(feature = "dev")]
-use chrono::{Duration, Utc};
-
 use utils::{
+    ack::{start_ack_batch_worker, AckBatcher},
     config::AppConfigManager,
-    prediction_config::PredictionConfig,
+    export::{export_records, write_export, ExportArgs, EXPORT_USAGE},
+    file_transfer::{start_file_transfer_worker, FileTransfers},
+    history::{start_history_worker, HistoryStore},
+    outbox::{start_outbox_worker, Outbox},
+    prediction_config::{start_contact_plan_watcher, ContactPlanSource, PredictionConfig},
+    presence::Presence,
+    proto::set_max_frame_size,
+    rooms::RoomStore,
     socket::{DefaultSocketController, SocketController},
 };
 
 #[cfg(feature = "dev")]
-use utils::{
-    message::{ChatMessage, MessageStatus},
-    proto::generate_uuid,
-};
+use utils::fixtures;
 
 #[derive(Clone)]
 pub struct ArcChatApp {
@@ -27,6 +30,14 @@ pub struct ArcChatApp {
 }
 
 fn main() -> Result<(), eframe::Error> {
+    let export_args = match ExportArgs::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{EXPORT_USAGE}");
+            std::process::exit(2);
+        }
+    };
+
     let config_path = match std::env::var("DTCHAT_CONFIG") {
         Ok(path) => path,
         Err(_) => {
@@ -38,20 +49,53 @@ fn main() -> Result<(), eframe::Error> {
         }
     };
     let config: AppConfigManager = AppConfigManager::load_yaml_from_file(&config_path);
+    set_max_frame_size(config.max_message_size);
+    let storage_dir = config.local_storage_dir();
+    let transfers = FileTransfers::new(config.downloads_dir(), config.file_transfer);
+    let mut history = HistoryStore::new(&storage_dir.join("history"), config.history);
+
+    // Export the stored history and exit, without starting the GUI
+    if let Some(args) = export_args {
+        let rooms = RoomStore::load(&storage_dir.join("rooms.yaml"), config.room_list).rooms();
+        let messages = history.read_only(&config.local_peer, &config.peer_list);
+        let result = args.filter(&rooms).and_then(|filter| {
+            let messages: Vec<_> = messages.iter().collect();
+            let records = export_records(&messages, &rooms, &config.local_peer, &filter);
+            write_export(&args.path, args.format, &records).map_err(|e| e.to_string())
+        });
+        match result {
+            Ok(count) => {
+                println!("{count} records written to {}", args.path.display());
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Export failed: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
 
     let shared_peers = config.peer_list;
     let shared_rooms = config.room_list;
     let local_peer = config.local_peer;
-    let contact_plan = config.a_sabr;
+    let contact_plan = ContactPlanSource {
+        path: config.a_sabr,
+        dtn_node_map: config.dtn_node_map,
+        routing: config.routing,
+        epoch: config.contact_plan_epoch,
+        copy_dir: storage_dir.join("contact_plans"),
+    };
+    let retransmission = config.retransmission;
+    let read_receipts = config.read_receipts;
+    let ack_batcher = config
+        .ack_batch
+        .map(|policy| Arc::new(Mutex::new(AckBatcher::new(policy))));
 
-    if !Path::new(&contact_plan).exists() {
+    if !Path::new(&contact_plan.path).exists() {
         eprintln!("Contact plan missing !!!");
     }
 
-    #[cfg(feature = "dev")]
-    let mut now = Utc::now() - Duration::seconds(40);
-
-    let prediction_config = match PredictionConfig::new(&contact_plan) {
+    let prediction_config = match PredictionConfig::new(&contact_plan, Utc::now()) {
         Ok(config) => Some(config),
         Err(e) => {
             eprintln!("Failed to create prediction_config: {e}");
@@ -59,94 +103,63 @@ fn main() -> Result<(), eframe::Error> {
         }
     };
 
-    #[cfg(feature = "dev")]
     let mut model = ChatModel::new(
         shared_peers.clone(),
         local_peer.clone(),
-        shared_rooms.clone(),
-        prediction_config,
-    );
-
-    #[cfg(not(feature = "dev"))]
-    let model = ChatModel::new(
-        shared_peers.clone(),
-        local_peer.clone(),
-        shared_rooms.clone(),
+        RoomStore::load(&storage_dir.join("rooms.yaml"), shared_rooms),
         prediction_config,
+        Outbox::load(&storage_dir.join("outbox.yaml")),
+        retransmission,
+        read_receipts,
     );
+    model.load_messages(history.load(&local_peer, &shared_peers));
+    model.history = Some(history);
+    model.bundle_overhead = config.bundle_overhead;
 
     #[cfg(feature = "dev")]
     {
-        model.messages.push(ChatMessage {
-            uuid: generate_uuid(),
-            response: None,
-            sender: local_peer.clone(),
-            text: "Hello from local peer".to_owned(),
-            shipment_status: MessageStatus::Received(now, now + Duration::seconds(10)),
-        });
-
-        now += Duration::seconds(2);
-
-        model.messages.push(ChatMessage {
-            uuid: generate_uuid(),
-            response: None,
-            sender: shared_peers[2].clone(),
-            text: "Bob at your service !".to_owned(),
-            shipment_status: MessageStatus::Received(now, now + Duration::seconds(30)),
-        });
-
-        now += Duration::seconds(1);
-
-        model.messages.push(ChatMessage {
-            uuid: generate_uuid(),
-            response: None,
-            sender: shared_peers[0].clone(),
-            text: "Hello local peer, how are you?".to_owned(),
-            shipment_status: MessageStatus::Received(now, now + Duration::seconds(10)),
-        });
-
-        now += Duration::seconds(2);
-
-        model.messages.push(ChatMessage {
-            uuid: generate_uuid(),
-            response: None,
-            sender: shared_peers[0].clone(),
-            text: "I'm john does".to_owned(),
-            shipment_status: MessageStatus::Received(now, now + Duration::seconds(10)),
-        });
-
-        now += Duration::seconds(13);
-
-        model.messages.push(ChatMessage {
-            uuid: generate_uuid(),
-            response: None,
-            sender: local_peer.clone(),
-            text: "Hello john doe, Some news from alice ?".to_owned(),
-            shipment_status: MessageStatus::Received(now, now + Duration::seconds(10)),
-        });
-
-        now += Duration::seconds(5);
-
-        model.messages.push(ChatMessage {
-            uuid: generate_uuid(),
-            response: None,
-            sender: shared_peers[1].clone(),
-            text: "Sorry, I'm a bit late!".to_owned(),
-            shipment_status: MessageStatus::Received(now, now + Duration::seconds(12)),
-        });
+        let seed_room = model.default_room_uuid();
+        model.load_messages(fixtures::seed_messages(
+            &local_peer,
+            &shared_peers,
+            &seed_room,
+        ));
     }
 
-    let model_arc = Arc::new(Mutex::new(model));
+    // Messages left queued by a previous run
+    model.restore_outbox();
+    model.ack_batcher = ack_batcher.clone();
+    model.transfers = transfers;
+    model.config_path = config_path.into();
+    model.contact_plan = Some(contact_plan);
+    model.set_presence(Presence::Online);
 
-    match DefaultSocketController::init_controller(local_peer.clone(), shared_peers.clone()) {
-        Ok(controller) => {
-            controller.lock().unwrap().add_observer(model_arc.clone());
-        }
-        Err(e) => {
-            eprintln!("Failed to initialize socket controller: {e:?}");
-        }
+    let model_arc = Arc::new(Mutex::new(model));
+    start_outbox_worker(model_arc.clone());
+    start_file_transfer_worker(model_arc.clone());
+    start_history_worker(model_arc.clone());
+    start_contact_plan_watcher(model_arc.clone());
+    if let Some(batcher) = &ack_batcher {
+        start_ack_batch_worker(batcher.clone(), model_arc.clone());
     }
 
+    let socket_controller =
+        match DefaultSocketController::init_controller(local_peer.clone(), shared_peers.clone()) {
+            Ok(controller_arc) => {
+                let mut controller = controller_arc.lock().unwrap();
+                controller.add_observer(model_arc.clone());
+                if let Some(batcher) = ack_batcher {
+                    controller.set_ack_batcher(batcher);
+                }
+                drop(controller);
+                Some(controller_arc)
+            }
+            Err(e) => {
+                eprintln!("Failed to initialize socket controller: {e:?}");
+                None
+            }
+        };
+
     let options = eframe::NativeOptions::default();
     eframe::run_native(
         "DTCHat",
@@ -155,7 +168,9 @@ fn main() -> Result<(), eframe::Error> {
             move |cc| -> Result<Box<dyn eframe::App>, Box<dyn std::error::Error + Send + Sync>> {
                 let handler_arc = Arc::new(Mutex::new(EventHandler::new(cc.egui_ctx.clone())));
                 model_arc.lock().unwrap().add_observer(handler_arc.clone());
-                Ok(Box::new(ChatApp::new(model_arc, handler_arc)))
+                let mut app = ChatApp::new(model_arc, handler_arc);
+                app.socket_controller = socket_controller;
+                Ok(Box::new(app))
             },
         ),
     )?;
@@ -1,15 +1,23 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::app::ChatModel;
+use crate::utils::config::{AckBatchPolicy, Peer};
 use crate::utils::message::ChatMessage;
 use crate::utils::proto::dtchat_proto::proto_message::Content;
-use crate::utils::proto::dtchat_proto::DeliveryStatus;
+use crate::utils::proto::dtchat_proto::{BatchedDelivery, DeliveryBatch, DeliveryStatus};
 use crate::utils::proto::{dtchat_proto, generate_uuid};
-use crate::utils::socket::{self, GenericSocket};
+use crate::utils::socket::{self, Endpoint, GenericSocket};
+
+/// How often the pending ACK batches are checked
+const ACK_BATCH_TICK: std::time::Duration = std::time::Duration::from_secs(1);
 
 pub type AckResult<T> = Result<T, AckError>;
 
 #[derive(Debug)]
 pub enum AckError {
     Network(Box<dyn std::error::Error + Send + Sync>),
-    #[allow(dead_code)]
     Serialization(String),
     #[allow(dead_code)]
     InvalidMessage(String), // Invalid message format
@@ -27,7 +35,6 @@ impl std::fmt::Display for AckError {
 
 impl std::error::Error for AckError {}
 
-#[allow(dead_code)]
 pub fn create_ack_message(
     received_msg: &ChatMessage,
     local_peer_uuid: &str,
@@ -43,11 +50,31 @@ pub fn create_ack_message(
         uuid: generate_uuid(),
         sender_uuid: local_peer_uuid.to_string(), // ACK is sent by the local peer
         timestamp: chrono::Utc::now().timestamp_millis(),
-        room_uuid: "default".to_string(), // Using default room
+        room_uuid: received_msg.room_uuid.clone(),
         content: Some(Content::Delivery(delivery_status)),
     }
 }
 
+/// Ask the sender of the file `transfer_uuid` to send all its chunks again,
+/// `uuid` kept across the retries
+pub fn create_nack_message(
+    uuid: &str,
+    transfer_uuid: &str,
+    local_peer_uuid: &str,
+) -> dtchat_proto::ProtoMessage {
+    dtchat_proto::ProtoMessage {
+        uuid: uuid.to_string(),
+        sender_uuid: local_peer_uuid.to_string(),
+        timestamp: Utc::now().timestamp_millis(),
+        room_uuid: String::new(),
+        content: Some(Content::Delivery(DeliveryStatus {
+            message_uuid: transfer_uuid.to_string(),
+            received: false,
+            read: false,
+        })),
+    }
+}
+
 pub async fn send_ack_message(
     received_msg: &ChatMessage,
     socket: &mut GenericSocket,
@@ -99,3 +126,215 @@ pub fn send_ack_message_non_blocking(
         }
     });
 }
+
+/// Tell `sender_peer` that `received_msg` was displayed to the local user
+pub fn send_read_receipt(received_msg: &ChatMessage, sender_peer: &Peer, local_peer_uuid: &str) {
+    if sender_peer.endpoints.is_empty() {
+        return;
+    }
+    let endpoint = socket::choose_ack_endpoint(sender_peer, received_msg.endpoint.as_ref());
+    match GenericSocket::new(&endpoint) {
+        Ok(mut socket) => {
+            send_ack_message_non_blocking(received_msg, &mut socket, local_peer_uuid, true)
+        }
+        Err(e) => eprintln!("Failed to create socket for read receipt: {e}"),
+    }
+}
+
+/// ACKs waiting to be sent to one peer
+struct PendingAcks {
+    deliveries: Vec<BatchedDelivery>,
+    // Endpoint the last acknowledged message was received on
+    endpoint: Option<Endpoint>,
+    since: DateTime<Utc>,
+}
+
+/// ACKs to send to one peer in a single bundle
+pub struct AckBatch {
+    pub peer_uuid: String,
+    pub endpoint: Option<Endpoint>,
+    pub deliveries: Vec<BatchedDelivery>,
+}
+
+/// Aggregate the ACKs per peer, flushed on a count threshold, a delay or a contact opening
+pub struct AckBatcher {
+    policy: AckBatchPolicy,
+    pending: HashMap<String, PendingAcks>,
+    // State of the link to each peer at the last check
+    link_open: HashMap<String, bool>,
+}
+
+impl AckBatcher {
+    pub fn new(policy: AckBatchPolicy) -> Self {
+        Self {
+            policy,
+            pending: HashMap::new(),
+            link_open: HashMap::new(),
+        }
+    }
+
+    /// Longest time an ACK may wait in a batch
+    pub fn max_delay(&self) -> Duration {
+        Duration::seconds(self.policy.max_delay_secs)
+    }
+
+    /// Queue an ACK for `peer_uuid`, returns its batch once it reaches the count threshold
+    pub fn push(
+        &mut self,
+        peer_uuid: &str,
+        received_on_endpoint: Option<&Endpoint>,
+        message_uuid: &str,
+        read: bool,
+    ) -> Option<AckBatch> {
+        let now = Utc::now();
+        let pending = self
+            .pending
+            .entry(peer_uuid.to_string())
+            .or_insert_with(|| PendingAcks {
+                deliveries: Vec::new(),
+                endpoint: None,
+                since: now,
+            });
+        pending.deliveries.push(BatchedDelivery {
+            message_uuid: message_uuid.to_string(),
+            read,
+            timestamp: now.timestamp_millis(),
+        });
+        if received_on_endpoint.is_some() {
+            pending.endpoint = received_on_endpoint.cloned();
+        }
+
+        if pending.deliveries.len() >= self.policy.max_count {
+            self.take(peer_uuid)
+        } else {
+            None
+        }
+    }
+
+    /// Peers with ACKs waiting, with the size of their batch once serialized
+    pub fn pending_sizes(&self, local_peer_uuid: &str) -> Vec<(String, usize)> {
+        use prost::Message;
+
+        self.pending
+            .iter()
+            .map(|(peer_uuid, pending)| {
+                let batch = AckBatch {
+                    peer_uuid: peer_uuid.clone(),
+                    endpoint: None,
+                    deliveries: pending.deliveries.clone(),
+                };
+                let size = create_ack_batch_message(&batch, local_peer_uuid).encoded_len();
+                (peer_uuid.clone(), size)
+            })
+            .collect()
+    }
+
+    /// Batches due at `now`: waiting for too long, or whose link to the peer has just opened
+    pub fn take_due(
+        &mut self,
+        now: DateTime<Utc>,
+        link_open: &HashMap<String, bool>,
+    ) -> Vec<AckBatch> {
+        let max_delay = self.max_delay();
+        let mut due = Vec::new();
+        for (peer_uuid, open) in link_open {
+            let was_open = self.link_open.insert(peer_uuid.clone(), *open);
+            let opened = *open && was_open == Some(false);
+            let expired = self
+                .pending
+                .get(peer_uuid)
+                .is_some_and(|pending| now - pending.since >= max_delay);
+            if opened || expired {
+                due.extend(self.take(peer_uuid));
+            }
+        }
+        due
+    }
+
+    fn take(&mut self, peer_uuid: &str) -> Option<AckBatch> {
+        self.pending.remove(peer_uuid).map(|pending| AckBatch {
+            peer_uuid: peer_uuid.to_string(),
+            endpoint: pending.endpoint,
+            deliveries: pending.deliveries,
+        })
+    }
+}
+
+pub fn create_ack_batch_message(
+    batch: &AckBatch,
+    local_peer_uuid: &str,
+) -> dtchat_proto::ProtoMessage {
+    dtchat_proto::ProtoMessage {
+        uuid: generate_uuid(),
+        sender_uuid: local_peer_uuid.to_string(),
+        timestamp: Utc::now().timestamp_millis(),
+        // A batch may acknowledge messages of several rooms
+        room_uuid: String::new(),
+        content: Some(Content::DeliveryBatch(DeliveryBatch {
+            deliveries: batch.deliveries.clone(),
+        })),
+    }
+}
+
+/// Send `batch` to `peer` in the background
+pub fn send_ack_batch(batch: AckBatch, peer: &Peer, local_peer_uuid: &str) {
+    use prost::Message;
+
+    if peer.endpoints.is_empty() {
+        return;
+    }
+    let endpoint = socket::choose_ack_endpoint(peer, batch.endpoint.as_ref());
+    let proto_msg = create_ack_batch_message(&batch, local_peer_uuid);
+    let buf = proto_msg.encode_to_vec();
+
+    socket::TOKIO_RUNTIME.spawn_blocking(move || {
+        let result = GenericSocket::new(&endpoint).and_then(|mut socket| socket.send(&buf));
+        match result {
+            Ok(_) => println!(
+                "Sent ACK batch of {} deliveries via {endpoint}",
+                batch.deliveries.len()
+            ),
+            Err(e) => eprintln!("Failed to send ACK batch via {endpoint}: {e}"),
+        }
+    });
+}
+
+/// Periodically flush the ACK batches that are due, for as long as the application runs
+pub fn start_ack_batch_worker(batcher: Arc<Mutex<AckBatcher>>, model: Arc<Mutex<ChatModel>>) {
+    socket::TOKIO_RUNTIME.spawn(async move {
+        let mut interval = tokio::time::interval(ACK_BATCH_TICK);
+        loop {
+            interval.tick().await;
+            let now = Utc::now();
+            let local_peer_uuid = model.lock().unwrap().localpeer.uuid.clone();
+            let pending = batcher.lock().unwrap().pending_sizes(&local_peer_uuid);
+            if pending.is_empty() {
+                continue;
+            }
+
+            let (link_open, peers) = {
+                let model = model.lock().unwrap();
+                let link_open = pending
+                    .into_iter()
+                    .map(|(uuid, size)| {
+                        let size = model.bundle_overhead.bundle_size(size);
+                        let open = model
+                            .peers
+                            .iter()
+                            .find(|p| p.uuid == uuid)
+                            .is_none_or(|peer| model.next_hop_opens_at(peer, size).is_none());
+                        (uuid, open)
+                    })
+                    .collect::<HashMap<_, _>>();
+                (link_open, model.peers.clone())
+            };
+
+            let due = batcher.lock().unwrap().take_due(now, &link_open);
+            for batch in due {
+                if let Some(peer) = peers.iter().find(|p| p.uuid == batch.peer_uuid) {
+                    send_ack_batch(batch, peer, &local_peer_uuid);
+                }
+            }
+        }
+    });
+}
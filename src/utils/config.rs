@@ -1,14 +1,24 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use super::socket::Endpoint;
+use super::proto::{DEFAULT_MAX_FRAME_SIZE, FRAME_HEADER_LEN};
+use super::socket::{Endpoint, EndpointKind};
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
 pub struct Peer {
     pub uuid: String,
     pub name: String,
     pub endpoints: Vec<Endpoint>,
     pub color: u32,
+    /// Transports to try first when sending (e.g. [Bp, Tcp, Udp]), declaration order otherwise
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub send_priority: Vec<EndpointKind>,
+    /// Send read receipts to this peer, overrides the global `read_receipts`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_receipts: Option<bool>,
 }
 
 impl Default for Peer {
@@ -18,6 +28,8 @@ impl Default for Peer {
             name: "Unknown".to_string(),
             endpoints: Vec::new(),
             color: 0,
+            send_priority: Vec::new(),
+            read_receipts: None,
         }
     }
 }
@@ -33,12 +45,206 @@ impl Peer {
             _ => egui::Color32::WHITE,
         }
     }
+
+    /// Valid endpoints in the order they should be tried when sending to this peer
+    pub fn send_endpoints(&self) -> Vec<Endpoint> {
+        let rank = |endpoint: &Endpoint| {
+            self.send_priority
+                .iter()
+                .position(|kind| *kind == endpoint.kind())
+                .unwrap_or(self.send_priority.len())
+        };
+
+        let mut endpoints: Vec<Endpoint> = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.is_valid())
+            .cloned()
+            .collect();
+        // Stable sort: endpoints of the same rank keep their declaration order
+        endpoints.sort_by_key(|endpoint| rank(endpoint));
+        endpoints
+    }
+}
+
+/// Application-level retransmission of PBAT messages left without ACK
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetransmissionPolicy {
+    /// Transmissions of a message, the first one included
+    pub max_attempts: u32,
+    /// Added to the predicted round trip before retransmitting, in seconds
+    pub margin_secs: i64,
+}
+
+impl Default for RetransmissionPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            margin_secs: 5,
+        }
+    }
+}
+
+/// When to flush the delivery ACKs waiting for a peer
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AckBatchPolicy {
+    /// Send the batch as soon as it holds this many ACKs
+    pub max_count: usize,
+    /// Longest time an ACK may wait, in seconds
+    pub max_delay_secs: i64,
+}
+
+impl Default for AckBatchPolicy {
+    fn default() -> Self {
+        Self {
+            max_count: 16,
+            max_delay_secs: 30,
+        }
+    }
+}
+
+/// Resending of the file chunks left without ACK, and size of the files received
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct FileTransferPolicy {
+    /// Time to wait for the ACK of a chunk before sending it again, in seconds
+    pub resend_secs: i64,
+    /// Transmissions of a chunk, the first one included
+    pub max_attempts: u32,
+    /// Largest file accepted from a peer, also bounds the files being received at once, in bytes
+    pub max_file_size: u64,
+}
+
+impl Default for FileTransferPolicy {
+    fn default() -> Self {
+        Self {
+            resend_secs: 60,
+            max_attempts: 5,
+            max_file_size: 100 << 20,
+        }
+    }
+}
+
+/// How much message history is kept on disk
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HistoryPolicy {
+    /// Messages kept per room, the oldest are dropped first
+    pub max_messages: usize,
+    /// Drop the messages sent or received longer ago, keep them all when unset
+    pub max_age_days: Option<u32>,
+}
+
+impl Default for HistoryPolicy {
+    fn default() -> Self {
+        Self {
+            max_messages: 10_000,
+            max_age_days: None,
+        }
+    }
+}
+
+/// Bytes added around the serialized payload of each bundle, counted in the PBAT
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct BundleOverhead {
+    /// BP primary block, payload block header and CRCs
+    pub bundle_header: usize,
+    /// Convergence layer framing of a bundle, e.g. a TCPCL segment header
+    pub convergence_layer: usize,
+    /// Share of the size added on the link, e.g. `0.05` for 5 %
+    pub link_ratio: f64,
+}
+
+impl Default for BundleOverhead {
+    fn default() -> Self {
+        Self {
+            bundle_header: 64,
+            convergence_layer: 32,
+            link_ratio: 0.0,
+        }
+    }
+}
+
+impl BundleOverhead {
+    /// Bytes added to each payload besides the link ratio, the DTChat frame header included
+    pub fn per_bundle(&self) -> usize {
+        FRAME_HEADER_LEN + self.bundle_header + self.convergence_layer
+    }
+
+    /// Bytes on the link for a bundle carrying `payload` serialized bytes
+    pub fn bundle_size(&self, payload: usize) -> f64 {
+        (payload + self.per_bundle()) as f64 * (1.0 + self.link_ratio)
+    }
+
+    /// Largest payload fitting in `volume` bytes of a contact
+    pub fn payload_in(&self, volume: f64) -> f64 {
+        (volume / (1.0 + self.link_ratio) - self.per_bundle() as f64).max(0.0)
+    }
+}
+
+/// A-SABR contact manager, which models the volume and queueing of each contact
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ContactManagerKind {
+    /// Earliest volume limit
+    #[default]
+    Evl,
+    /// Queue delay
+    Qd,
+    /// Earliest transmission opportunity
+    Eto,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
+impl ContactManagerKind {
+    pub const ALL: [ContactManagerKind; 3] = [Self::Evl, Self::Qd, Self::Eto];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Evl => "EVL",
+            Self::Qd => "QD",
+            Self::Eto => "ETO",
+        }
+    }
+}
+
+/// Routing algorithm used for the predictions
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RoutingConfig {
+    /// A-SABR router name, e.g. `CgrFirstEndingContactGraph` or `SpsnMpt`
+    pub router: String,
+    pub contact_manager: ContactManagerKind,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            router: "CgrFirstEndingContactGraph".to_string(),
+            contact_manager: ContactManagerKind::default(),
+        }
+    }
+}
+
+impl RoutingConfig {
+    /// Name recorded with the predictions, e.g. `CgrFirstEndingContactGraph/EVL`
+    pub fn label(&self) -> String {
+        format!("{}/{}", self.router, self.contact_manager.label())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct Room {
     pub uuid: String,
     pub name: String,
+    /// UUIDs of the peers taking part in the room, besides the local peer
+    #[serde(default)]
+    pub participants: Vec<String>,
+    /// Overrides the global retransmission policy for this room
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retransmission: Option<RetransmissionPolicy>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -47,6 +253,50 @@ pub struct AppConfigManager {
     pub local_peer: Peer,
     pub room_list: Vec<Room>,
     pub a_sabr: String,
+    /// Largest serialized message (in bytes) accepted on the wire
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+    /// dtn-scheme EID, or its node part, to ION node name used by the contact plan
+    #[serde(default)]
+    pub dtn_node_map: HashMap<String, String>,
+    /// Directory holding the local state (outbox...), one sub-directory per local peer
+    #[serde(default = "default_storage_dir")]
+    pub storage_dir: String,
+    #[serde(default)]
+    pub retransmission: RetransmissionPolicy,
+    /// Tell the senders when their messages are displayed
+    #[serde(default = "default_read_receipts")]
+    pub read_receipts: bool,
+    /// Aggregate the ACKs instead of sending one bundle per message
+    #[serde(default)]
+    pub ack_batch: Option<AckBatchPolicy>,
+    #[serde(default)]
+    pub file_transfer: FileTransferPolicy,
+    /// Directory receiving the files, `<storage_dir>/<local peer uuid>/downloads` by default
+    #[serde(default)]
+    pub downloads_dir: Option<String>,
+    #[serde(default)]
+    pub history: HistoryPolicy,
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    /// UTC time of the `+0` of the contact plan, shared by all the nodes
+    /// (RFC 3339, `YYYY-MM-DD HH:MM:SS` or ION `yyyy/mm/dd-hh:mm:ss`)
+    #[serde(default)]
+    pub contact_plan_epoch: Option<String>,
+    #[serde(default)]
+    pub bundle_overhead: BundleOverhead,
+}
+
+fn default_max_message_size() -> usize {
+    DEFAULT_MAX_FRAME_SIZE
+}
+
+fn default_storage_dir() -> String {
+    "dtchat_data".to_string()
+}
+
+fn default_read_receipts() -> bool {
+    true
 }
 
 impl AppConfigManager {
@@ -54,4 +304,38 @@ impl AppConfigManager {
         let config_str = fs::read_to_string(file_path).expect("Failed to read config file");
         serde_yaml::from_str(&config_str).expect("Failed to parse YAML")
     }
+
+    /// Replace the `peer_list` of the configuration file, the other keys are kept
+    pub fn save_peer_list(file_path: &Path, peers: &[Peer]) -> io::Result<()> {
+        let config_str = fs::read_to_string(file_path)?;
+        let mut config: serde_yaml::Value =
+            serde_yaml::from_str(&config_str).map_err(io::Error::other)?;
+        let Some(mapping) = config.as_mapping_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the configuration is not a YAML mapping",
+            ));
+        };
+        let peer_list = serde_yaml::to_value(peers).map_err(io::Error::other)?;
+        mapping.insert("peer_list".into(), peer_list);
+
+        let content = serde_yaml::to_string(&config).map_err(io::Error::other)?;
+        // Write then rename so a crash never leaves a truncated file
+        let tmp_path = file_path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, file_path)
+    }
+
+    /// Directory holding the state of the local peer
+    pub fn local_storage_dir(&self) -> PathBuf {
+        Path::new(&self.storage_dir).join(&self.local_peer.uuid)
+    }
+
+    /// Directory where the received files are saved
+    pub fn downloads_dir(&self) -> PathBuf {
+        match &self.downloads_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => self.local_storage_dir().join("downloads"),
+        }
+    }
 }
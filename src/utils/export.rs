@@ -0,0 +1,340 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::utils::config::{Peer, Room};
+use crate::utils::message::{ChatMessage, MessageStatus};
+use crate::utils::proto::encoded_size;
+use crate::utils::socket::Endpoint;
+
+/// Parse a UTC time typed as `YYYY-MM-DD HH:MM`, or `YYYY-MM-DD` for the start
+/// (or the end when `end_of_day`) of that day
+pub fn parse_time(input: &str, end_of_day: bool) -> Option<DateTime<Utc>> {
+    let input = input.trim();
+    if let Ok(time) = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M") {
+        return Some(time.and_utc());
+    }
+    let date = NaiveDate::parse_from_str(input, "%Y-%m-%d").ok()?;
+    let time = if end_of_day {
+        date.and_hms_opt(23, 59, 59)?
+    } else {
+        date.and_hms_opt(0, 0, 0)?
+    };
+    Some(time.and_utc())
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 2] = [Self::Csv, Self::Json];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension().eq_ignore_ascii_case(name))
+    }
+
+    /// Format matching the extension of `path`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_name(path.extension()?.to_str()?)
+    }
+}
+
+/// Messages kept in an export, by room and transmission time
+#[derive(Clone, Debug, Default)]
+pub struct ExportFilter {
+    pub room_uuid: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl ExportFilter {
+    fn keep(&self, message: &ChatMessage) -> bool {
+        let tx = message.shipment_status.tx();
+        self.room_uuid
+            .as_ref()
+            .is_none_or(|room| message.room_uuid == *room)
+            && self.from.is_none_or(|from| tx >= from)
+            && self.to.is_none_or(|to| tx <= to)
+    }
+}
+
+/// One delivery of a message: a message sent to several peers gives one record per recipient
+#[derive(Clone, Debug, Serialize)]
+pub struct ExportRecord {
+    pub uuid: String,
+    pub sender_uuid: String,
+    pub sender: String,
+    pub receiver_uuid: String,
+    pub receiver: String,
+    pub room_uuid: String,
+    pub room: String,
+    pub text: String,
+    pub status: &'static str,
+    /// Times in RFC 3339, UTC
+    pub tx_time: String,
+    pub pbat: Option<String>,
+    /// Predicted return of the ACK to the sender
+    pub predicted_ack: Option<String>,
+    /// Routing algorithm that produced the PBAT
+    pub routing: Option<String>,
+    pub ack_time: Option<String>,
+    pub read_time: Option<String>,
+    pub endpoint: Option<String>,
+    /// Serialized size in bytes
+    pub size: usize,
+    pub retransmissions: u32,
+}
+
+impl ExportRecord {
+    const CSV_HEADER: [&'static str; 18] = [
+        "uuid",
+        "sender_uuid",
+        "sender",
+        "receiver_uuid",
+        "receiver",
+        "room_uuid",
+        "room",
+        "text",
+        "status",
+        "tx_time",
+        "pbat",
+        "predicted_ack",
+        "routing",
+        "ack_time",
+        "read_time",
+        "endpoint",
+        "size",
+        "retransmissions",
+    ];
+
+    fn csv_cells(&self) -> [String; 18] {
+        let opt = |value: &Option<String>| value.clone().unwrap_or_default();
+        [
+            self.uuid.clone(),
+            self.sender_uuid.clone(),
+            self.sender.clone(),
+            self.receiver_uuid.clone(),
+            self.receiver.clone(),
+            self.room_uuid.clone(),
+            self.room.clone(),
+            self.text.clone(),
+            self.status.to_string(),
+            self.tx_time.clone(),
+            opt(&self.pbat),
+            opt(&self.predicted_ack),
+            opt(&self.routing),
+            opt(&self.ack_time),
+            opt(&self.read_time),
+            opt(&self.endpoint),
+            self.size.to_string(),
+            self.retransmissions.to_string(),
+        ]
+    }
+}
+
+fn time_str(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Records of the `messages` kept by `filter`, oldest transmission first
+pub fn export_records(
+    messages: &[&ChatMessage],
+    rooms: &[Room],
+    local_peer: &Peer,
+    filter: &ExportFilter,
+) -> Vec<ExportRecord> {
+    let mut records = Vec::new();
+    for message in messages.iter().filter(|message| filter.keep(message)) {
+        let room = rooms
+            .iter()
+            .find(|r| r.uuid == message.room_uuid)
+            .map_or_else(|| message.room_uuid.clone(), |r| r.name.clone());
+        let size = encoded_size(message);
+        let endpoint = |endpoint: &Option<Endpoint>| endpoint.as_ref().map(|e| e.to_string());
+        let status = &message.shipment_status;
+        let read_time = |status: &MessageStatus| match status {
+            MessageStatus::Read(_, at) => Some(time_str(*at)),
+            _ => None,
+        };
+        // Arrival time for a received message
+        let ack_time = message.delivered_at().or(match status {
+            MessageStatus::Received(_, at) => Some(*at),
+            _ => None,
+        });
+        let record = |receiver_uuid: &str, receiver: &str| ExportRecord {
+            uuid: message.uuid.clone(),
+            sender_uuid: message.sender.uuid.clone(),
+            sender: message.sender.name.clone(),
+            receiver_uuid: receiver_uuid.to_string(),
+            receiver: receiver.to_string(),
+            room_uuid: message.room_uuid.clone(),
+            room: room.clone(),
+            text: message.text.clone(),
+            status: status.label(),
+            tx_time: time_str(status.tx()),
+            pbat: message.predicted_arrival().map(time_str),
+            predicted_ack: message.predicted_ack.map(time_str),
+            routing: message.routing.clone(),
+            ack_time: ack_time.map(time_str),
+            read_time: read_time(status),
+            endpoint: endpoint(&message.endpoint),
+            size,
+            retransmissions: message.attempts.saturating_sub(1),
+        };
+
+        if message.recipients.is_empty() {
+            // Received, or sent before the per-recipient tracking
+            records.push(record(&local_peer.uuid, &local_peer.name));
+            continue;
+        }
+        for recipient in &message.recipients {
+            records.push(ExportRecord {
+                status: recipient.status.label(),
+                tx_time: time_str(recipient.status.tx()),
+                pbat: recipient.pbat.map(time_str),
+                predicted_ack: recipient.predicted_ack.map(time_str),
+                routing: recipient.routing.clone(),
+                ack_time: recipient.delivered_at.map(time_str),
+                read_time: read_time(&recipient.status),
+                endpoint: endpoint(&recipient.endpoint),
+                retransmissions: recipient.attempts.saturating_sub(1),
+                ..record(&recipient.peer.uuid, &recipient.peer.name)
+            });
+        }
+    }
+    records.sort_by(|a, b| a.tx_time.cmp(&b.tx_time));
+    records
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(records: &[ExportRecord]) -> String {
+    let mut content = ExportRecord::CSV_HEADER.join(",");
+    content.push('\n');
+    for record in records {
+        let cells: Vec<String> = record.csv_cells().iter().map(|c| csv_field(c)).collect();
+        content += &cells.join(",");
+        content.push('\n');
+    }
+    content
+}
+
+/// Write `records` to `path`, returns the number of records written
+pub fn write_export(
+    path: &Path,
+    format: ExportFormat,
+    records: &[ExportRecord],
+) -> io::Result<usize> {
+    let content = match format {
+        ExportFormat::Csv => to_csv(records),
+        ExportFormat::Json => serde_json::to_string_pretty(records).map_err(io::Error::other)?,
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(records.len())
+}
+
+/// `--export` request read from the command line
+#[derive(Debug)]
+pub struct ExportArgs {
+    pub path: PathBuf,
+    pub format: ExportFormat,
+    /// Room UUID or name
+    pub room: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+pub const EXPORT_USAGE: &str = "Usage: DTChat --export <file> [--format csv|json] [--room <uuid|name>] [--from <YYYY-MM-DD[ HH:MM]>] [--to <YYYY-MM-DD[ HH:MM]>]";
+
+impl ExportArgs {
+    /// None when `--export` is not given, the GUI starts then
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut args = args.into_iter();
+        let (mut path, mut format, mut room, mut from, mut to) = (None, None, None, None, None);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {arg}"));
+            match arg.as_str() {
+                "--export" => path = Some(PathBuf::from(value()?)),
+                "--format" => {
+                    let name = value()?;
+                    format = Some(
+                        ExportFormat::from_name(&name)
+                            .ok_or(format!("Unknown export format: {name}"))?,
+                    );
+                }
+                "--room" => room = Some(value()?),
+                "--from" => {
+                    let time = value()?;
+                    from = Some(parse_time(&time, false).ok_or(format!("Invalid time: {time}"))?);
+                }
+                "--to" => {
+                    let time = value()?;
+                    to = Some(parse_time(&time, true).ok_or(format!("Invalid time: {time}"))?);
+                }
+                // Other arguments are not ours
+                _ => {}
+            }
+        }
+
+        let Some(path) = path else {
+            if room.is_some() || from.is_some() || to.is_some() || format.is_some() {
+                return Err("Export options given without --export".to_string());
+            }
+            return Ok(None);
+        };
+        let format = format
+            .or_else(|| ExportFormat::from_path(&path))
+            .unwrap_or_default();
+        Ok(Some(Self {
+            path,
+            format,
+            room,
+            from,
+            to,
+        }))
+    }
+
+    /// Filter of this request, `room` is looked up by UUID then by name
+    pub fn filter(&self, rooms: &[Room]) -> Result<ExportFilter, String> {
+        let room_uuid = match &self.room {
+            Some(room) => Some(
+                rooms
+                    .iter()
+                    .find(|r| r.uuid == *room)
+                    .or_else(|| rooms.iter().find(|r| r.name == *room))
+                    .map(|r| r.uuid.clone())
+                    .ok_or(format!("Unknown room: {room}"))?,
+            ),
+            None => None,
+        };
+        Ok(ExportFilter {
+            room_uuid,
+            from: self.from,
+            to: self.to,
+        })
+    }
+}
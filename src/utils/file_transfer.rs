@@ -0,0 +1,917 @@
+use chrono::{DateTime, Duration, Utc};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::app::ChatModel;
+use crate::utils::config::{FileTransferPolicy, Peer};
+use crate::utils::message::ChatMessage;
+use crate::utils::prediction_config::MIN_FRAGMENT_SIZE;
+use crate::utils::proto::{dtchat_proto, max_frame_size, Content};
+use crate::utils::socket::{send_to_peer, TOKIO_RUNTIME};
+
+/// How often the chunks due are sent
+const FILE_TRANSFER_TICK: std::time::Duration = std::time::Duration::from_secs(1);
+/// Room left in a frame for the fields sent along with the chunk data
+pub const CHUNK_OVERHEAD: usize = 1024;
+/// Type of the transfers carrying a text message too big for a single bundle
+pub const TEXT_MIME: &str = "text/x-dtchat-message";
+/// File name of these transfers
+pub const LONG_TEXT_NAME: &str = "message.txt";
+
+/// Largest piece of a file sent in one frame
+pub fn chunk_size() -> usize {
+    max_frame_size().saturating_sub(CHUNK_OVERHEAD).max(1)
+}
+
+/// Bytes of a chunk of `msg` once serialized, besides its data.
+/// Counts the widest chunk index and the data length prefix of a full chunk.
+pub fn chunk_envelope(msg: &ChatMessage, filename: &str, mime_type: &str, size: usize) -> usize {
+    let last_index = size.div_ceil(MIN_FRAGMENT_SIZE) as u32;
+    let chunk = dtchat_proto::ProtoMessage {
+        uuid: chunk_uuid(&msg.uuid, last_index),
+        sender_uuid: msg.sender.uuid.clone(),
+        timestamp: Utc::now().timestamp_millis(),
+        room_uuid: msg.room_uuid.clone(),
+        content: Some(Content::File(dtchat_proto::FileTransfer {
+            filename: filename.to_string(),
+            mime_type: mime_type.to_string(),
+            file_size: size as u64,
+            chunk_data: Vec::new(),
+            chunk_index: last_index,
+            total_chunks: last_index,
+            // Same length as the hex SHA-256
+            file_hash: "0".repeat(64),
+            transfer_uuid: msg.uuid.clone(),
+        })),
+    };
+    // An empty field is not serialized, the data adds its tag and length
+    chunk.encoded_len() + 1 + prost::length_delimiter_len(chunk_size())
+}
+
+/// Hex SHA-256 of a whole file
+pub fn file_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+pub fn format_size(size: u64) -> String {
+    match size {
+        s if s >= 1 << 20 => format!("{:.1} MiB", s as f64 / (1 << 20) as f64),
+        s if s >= 1 << 10 => format!("{:.1} KiB", s as f64 / (1 << 10) as f64),
+        s => format!("{s} B"),
+    }
+}
+
+/// Text of the message standing for a file, or for a long text until it is complete
+pub fn file_label(filename: &str, mime_type: &str, size: u64) -> String {
+    if mime_type == TEXT_MIME {
+        format!("✉ Long message ({})", format_size(size))
+    } else {
+        format!("📎 {filename} ({})", format_size(size))
+    }
+}
+
+pub fn mime_type(filename: &str) -> &'static str {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("txt" | "log") => "text/plain",
+        Some("json") => "application/json",
+        Some("yaml" | "yml") => "application/yaml",
+        Some("pdf") => "application/pdf",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+// Every chunk travels in its own message, ACKed by the receiver like any other
+fn chunk_uuid(transfer_uuid: &str, index: u32) -> String {
+    format!("{transfer_uuid}:{index}")
+}
+
+fn parse_chunk_uuid(message_uuid: &str) -> Option<(&str, u32)> {
+    let (transfer_uuid, index) = message_uuid.rsplit_once(':')?;
+    Some((transfer_uuid, index.parse().ok()?))
+}
+
+/// A piece of a file, as carried by the FileTransfer content
+#[derive(Clone, Debug)]
+pub struct FileChunk {
+    pub transfer_uuid: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub file_size: u64,
+    pub file_hash: String,
+    pub index: u32,
+    pub total_chunks: u32,
+    pub data: Vec<u8>,
+}
+
+impl FileChunk {
+    /// Check the fields of the chunk against each other, the file bounded by `max_file_size`
+    fn check(&self, max_file_size: u64) -> Result<(), String> {
+        if self.file_size > max_file_size {
+            return Err(format!(
+                "file of {} over the {} limit",
+                format_size(self.file_size),
+                format_size(max_file_size)
+            ));
+        }
+        // A chunk carries one byte at least, an empty file is a single empty chunk
+        if self.total_chunks == 0 || u64::from(self.total_chunks) > self.file_size.max(1) {
+            return Err(format!(
+                "{} chunks for a file of {} bytes",
+                self.total_chunks, self.file_size
+            ));
+        }
+        if self.index >= self.total_chunks {
+            return Err(format!("chunk {} of {}", self.index, self.total_chunks));
+        }
+        // Every chunk but the last is a whole fragment, which gives the chunk count
+        let len = self.data.len() as u64;
+        let whole = self.index + 1 < self.total_chunks;
+        if len > self.file_size
+            || (whole && (len == 0 || self.file_size.div_ceil(len) != u64::from(self.total_chunks)))
+        {
+            return Err(format!(
+                "chunk {} of {len} bytes for {} chunks of a file of {} bytes",
+                self.index, self.total_chunks, self.file_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Why a received chunk is not ACKed
+#[derive(Debug, PartialEq)]
+pub enum ChunkRejection {
+    /// The chunk does not fit the file it belongs to, or the file is too big
+    Invalid(String),
+    /// The chunk completed a file that does not match its hash, every chunk is needed again
+    Corrupted,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransferState {
+    InProgress,
+    /// Every recipient has every chunk
+    Complete,
+    /// Received file written to this path
+    Saved(PathBuf),
+    Failed(String),
+}
+
+/// Progress of the file behind a message
+#[derive(Clone, Debug)]
+pub struct TransferProgress {
+    /// Chunks acknowledged (sent file) or received
+    pub done: u32,
+    pub total: u32,
+    pub state: TransferState,
+    /// Predicted arrival of the last chunk at the last recipient, for a sent file
+    pub completion: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Default)]
+struct ChunkState {
+    sent_at: Option<DateTime<Utc>>,
+    attempts: u32,
+    acked: bool,
+    /// Bytes on the link of the last transmission
+    volume: f64,
+}
+
+/// Chunks of a file sent to one recipient
+struct Delivery {
+    peer: Peer,
+    // Sized to the contact towards the recipient
+    fragment_size: usize,
+    chunks: Vec<ChunkState>,
+    failed: bool,
+    // Times the recipient asked for the whole file again
+    restarts: u32,
+    completion: Option<DateTime<Utc>>,
+}
+
+impl Delivery {
+    fn acked(&self) -> u32 {
+        self.chunks.iter().filter(|chunk| chunk.acked).count() as u32
+    }
+
+    fn done(&self) -> bool {
+        self.failed || self.chunks.iter().all(|chunk| chunk.acked)
+    }
+}
+
+struct OutgoingTransfer {
+    room_uuid: String,
+    filename: String,
+    mime_type: String,
+    hash: String,
+    // Dropped once every delivery is done
+    data: Vec<u8>,
+    deliveries: Vec<Delivery>,
+}
+
+impl OutgoingTransfer {
+    fn create_chunk_message(
+        &self,
+        transfer_uuid: &str,
+        delivery: &Delivery,
+        index: u32,
+        local_peer_uuid: &str,
+    ) -> dtchat_proto::ProtoMessage {
+        let start = index as usize * delivery.fragment_size;
+        let end = (start + delivery.fragment_size).min(self.data.len());
+        dtchat_proto::ProtoMessage {
+            uuid: chunk_uuid(transfer_uuid, index),
+            sender_uuid: local_peer_uuid.to_string(),
+            timestamp: Utc::now().timestamp_millis(),
+            room_uuid: self.room_uuid.clone(),
+            content: Some(Content::File(dtchat_proto::FileTransfer {
+                filename: self.filename.clone(),
+                mime_type: self.mime_type.clone(),
+                file_size: self.data.len() as u64,
+                chunk_data: self.data[start..end].to_vec(),
+                chunk_index: index,
+                total_chunks: delivery.chunks.len() as u32,
+                file_hash: self.hash.clone(),
+                transfer_uuid: transfer_uuid.to_string(),
+            })),
+        }
+    }
+}
+
+struct IncomingTransfer {
+    filename: String,
+    mime_type: String,
+    file_size: u64,
+    hash: String,
+    total_chunks: u32,
+    // Content of a long text message, once complete
+    text: Option<String>,
+    // Chunks received so far, by index, dropped once the file is complete
+    chunks: BTreeMap<u32, Vec<u8>>,
+    received_bytes: u64,
+    last_chunk_at: DateTime<Utc>,
+    state: TransferState,
+}
+
+impl IncomingTransfer {
+    fn new(chunk: &FileChunk, now: DateTime<Utc>) -> Self {
+        Self {
+            filename: chunk.filename.clone(),
+            mime_type: chunk.mime_type.clone(),
+            file_size: chunk.file_size,
+            hash: chunk.file_hash.clone(),
+            total_chunks: chunk.total_chunks,
+            text: None,
+            chunks: BTreeMap::new(),
+            received_bytes: 0,
+            last_chunk_at: now,
+            state: TransferState::InProgress,
+        }
+    }
+
+    /// Whether `chunk` describes the same file as the chunks received before
+    fn matches(&self, chunk: &FileChunk) -> bool {
+        self.filename == chunk.filename
+            && self.mime_type == chunk.mime_type
+            && self.file_size == chunk.file_size
+            && self.hash == chunk.file_hash
+            && self.total_chunks == chunk.total_chunks
+    }
+}
+
+/// A chunk to hand to the transport
+pub struct OutgoingChunk {
+    pub transfer_uuid: String,
+    pub peer: Peer,
+    pub index: u32,
+    /// Encoded message carrying the chunk
+    pub payload: Vec<u8>,
+    /// Predicted arrival of the whole file at `peer`
+    pub completion: Option<DateTime<Utc>>,
+}
+
+/// Files sent and received by the local peer
+#[derive(Default)]
+pub struct FileTransfers {
+    downloads_dir: PathBuf,
+    policy: FileTransferPolicy,
+    outgoing: HashMap<String, OutgoingTransfer>,
+    incoming: HashMap<String, IncomingTransfer>,
+}
+
+impl FileTransfers {
+    pub fn new(downloads_dir: PathBuf, policy: FileTransferPolicy) -> Self {
+        Self {
+            downloads_dir,
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Start sending `data` to each recipient, in fragments of the given size, with the
+    /// predicted arrival of the last one. The recipients are added to the transfer if it is
+    /// already started.
+    pub fn start_transfer(
+        &mut self,
+        transfer_uuid: &str,
+        room_uuid: &str,
+        filename: &str,
+        mime_type: &str,
+        data: Vec<u8>,
+        recipients: Vec<(Peer, usize, Option<DateTime<Utc>>)>,
+    ) {
+        let deliveries = recipients
+            .into_iter()
+            .map(|(peer, fragment_size, completion)| {
+                let total_chunks = data.len().div_ceil(fragment_size).max(1);
+                Delivery {
+                    peer,
+                    fragment_size,
+                    chunks: vec![ChunkState::default(); total_chunks],
+                    failed: false,
+                    restarts: 0,
+                    completion,
+                }
+            })
+            .collect();
+
+        // Another recipient of a message already in chunks
+        if let Some(transfer) = self.outgoing.get_mut(transfer_uuid) {
+            if transfer.data.is_empty() {
+                transfer.data = data;
+            }
+            transfer.deliveries.extend(deliveries);
+            return;
+        }
+        let transfer = OutgoingTransfer {
+            room_uuid: room_uuid.to_string(),
+            filename: filename.to_string(),
+            mime_type: mime_type.to_string(),
+            hash: file_hash(&data),
+            data,
+            deliveries,
+        };
+        self.outgoing.insert(transfer_uuid.to_string(), transfer);
+    }
+
+    /// Recipients still missing chunks, with the bytes on the link of these chunks,
+    /// `bundle_size` giving the bytes on the link of a serialized chunk
+    pub fn pending_volumes(&self, bundle_size: impl Fn(usize) -> f64) -> Vec<(Peer, f64)> {
+        let mut pending: Vec<(Peer, f64)> = Vec::new();
+        for delivery in self.outgoing.values().flat_map(|t| &t.deliveries) {
+            if delivery.done() {
+                continue;
+            }
+            let missing = delivery.chunks.iter().filter(|chunk| !chunk.acked).count();
+            let volume = missing as f64 * bundle_size(delivery.fragment_size + CHUNK_OVERHEAD);
+            match pending
+                .iter_mut()
+                .find(|(p, _)| p.uuid == delivery.peer.uuid)
+            {
+                Some((_, total)) => *total += volume,
+                None => pending.push((delivery.peer.clone(), volume)),
+            }
+        }
+        pending
+    }
+
+    /// Chunks never sent, or left without ACK for `resend_secs`, as many as the link to their
+    /// recipient takes: `link_volume` holds the bytes the open contact to each recipient
+    /// still takes (none for a recipient outside the contact plan), `bundle_size` the bytes on
+    /// the link of a serialized chunk. Also returns the deliveries given up after `max_attempts`.
+    pub fn take_due(
+        &mut self,
+        now: DateTime<Utc>,
+        local_peer_uuid: &str,
+        link_volume: &HashMap<String, f64>,
+        bundle_size: impl Fn(usize) -> f64,
+    ) -> (Vec<OutgoingChunk>, Vec<(String, Peer)>) {
+        let resend_after = Duration::seconds(self.policy.resend_secs);
+        let waiting = |chunk: &ChunkState| {
+            chunk.acked || chunk.sent_at.is_some_and(|at| now < at + resend_after)
+        };
+        // The chunks sent and still waiting for their ACK keep their room on the contact
+        let mut budgets = link_volume.clone();
+        for delivery in self.outgoing.values().flat_map(|t| &t.deliveries) {
+            if let Some(budget) = budgets.get_mut(&delivery.peer.uuid) {
+                *budget -= delivery
+                    .chunks
+                    .iter()
+                    .filter(|chunk| !chunk.acked && waiting(chunk))
+                    .map(|chunk| chunk.volume)
+                    .sum::<f64>();
+            }
+        }
+
+        let mut due = Vec::new();
+        let mut expired = Vec::new();
+        for (transfer_uuid, transfer) in &mut self.outgoing {
+            if transfer.deliveries.iter().all(Delivery::done) {
+                transfer.data = Vec::new();
+                continue;
+            }
+
+            for delivery_index in 0..transfer.deliveries.len() {
+                if transfer.deliveries[delivery_index].done() {
+                    continue;
+                }
+                let peer = transfer.deliveries[delivery_index].peer.clone();
+                let mut budget = budgets.get(&peer.uuid).copied().unwrap_or(f64::INFINITY);
+                for index in 0..transfer.deliveries[delivery_index].chunks.len() {
+                    let chunk = &transfer.deliveries[delivery_index].chunks[index];
+                    if waiting(chunk) {
+                        continue;
+                    }
+                    if chunk.attempts >= self.policy.max_attempts {
+                        transfer.deliveries[delivery_index].failed = true;
+                        expired.push((transfer_uuid.clone(), peer.clone()));
+                        break;
+                    }
+                    let delivery = &transfer.deliveries[delivery_index];
+                    let payload = transfer
+                        .create_chunk_message(
+                            transfer_uuid,
+                            delivery,
+                            index as u32,
+                            local_peer_uuid,
+                        )
+                        .encode_to_vec();
+                    let volume = bundle_size(payload.len());
+                    if volume > budget {
+                        break;
+                    }
+                    budget -= volume;
+                    due.push(OutgoingChunk {
+                        transfer_uuid: transfer_uuid.clone(),
+                        peer: peer.clone(),
+                        index: index as u32,
+                        payload,
+                        completion: delivery.completion,
+                    });
+
+                    let chunk = &mut transfer.deliveries[delivery_index].chunks[index];
+                    chunk.sent_at = Some(now);
+                    chunk.attempts += 1;
+                    chunk.volume = volume;
+                }
+                if let Some(left) = budgets.get_mut(&peer.uuid) {
+                    *left = budget;
+                }
+            }
+        }
+        (due, expired)
+    }
+
+    /// Record the ACK of a chunk by `peer_uuid`, None if `message_uuid` is not a chunk.
+    /// Otherwise returns the transfer and whether this ACK completed the delivery to the peer.
+    pub fn acknowledge_chunk(
+        &mut self,
+        message_uuid: &str,
+        peer_uuid: &str,
+    ) -> Option<(String, bool)> {
+        let (transfer_uuid, index) = parse_chunk_uuid(message_uuid)?;
+        let transfer = self.outgoing.get_mut(transfer_uuid)?;
+        let delivery = transfer
+            .deliveries
+            .iter_mut()
+            .find(|d| d.peer.uuid == peer_uuid)?;
+        let chunk = delivery.chunks.get_mut(index as usize)?;
+        if chunk.acked {
+            return Some((transfer_uuid.to_string(), false));
+        }
+        chunk.acked = true;
+        Some((
+            transfer_uuid.to_string(),
+            delivery.done() && !delivery.failed,
+        ))
+    }
+
+    /// Send every chunk of the file again to `peer_uuid`, which could not put it back together.
+    /// Returns None for an unknown delivery, false once given up after `max_attempts` restarts.
+    pub fn restart_delivery(&mut self, transfer_uuid: &str, peer_uuid: &str) -> Option<bool> {
+        let max_restarts = self.policy.max_attempts;
+        let delivery = self
+            .outgoing
+            .get_mut(transfer_uuid)?
+            .deliveries
+            .iter_mut()
+            .find(|d| d.peer.uuid == peer_uuid)?;
+        if delivery.failed {
+            return Some(false);
+        }
+        if delivery.restarts >= max_restarts {
+            delivery.failed = true;
+            return Some(false);
+        }
+        delivery.restarts += 1;
+        delivery.chunks.fill(ChunkState::default());
+        Some(true)
+    }
+
+    /// Bytes of the files being received
+    fn receiving_bytes(&self) -> u64 {
+        self.incoming
+            .values()
+            .filter(|t| t.state == TransferState::InProgress)
+            .map(|t| t.file_size)
+            .sum()
+    }
+
+    /// Store a received chunk, returns the final state once the file is complete.
+    /// A chunk rejected is not ACKed, so that the sender sends it again.
+    pub fn receive_chunk(
+        &mut self,
+        chunk: FileChunk,
+        now: DateTime<Utc>,
+    ) -> Result<Option<TransferState>, ChunkRejection> {
+        let max_file_size = self.policy.max_file_size;
+        chunk
+            .check(max_file_size)
+            .map_err(ChunkRejection::Invalid)?;
+
+        if !self.incoming.contains_key(&chunk.transfer_uuid) {
+            if self.receiving_bytes() + chunk.file_size > max_file_size {
+                // Make room by forgetting the files the sender stopped sending long ago
+                let idle = Duration::seconds(
+                    self.policy.resend_secs * i64::from(self.policy.max_attempts),
+                );
+                self.incoming.retain(|_, t| {
+                    t.state != TransferState::InProgress || now < t.last_chunk_at + idle
+                });
+            }
+            let receiving = self.receiving_bytes();
+            if receiving + chunk.file_size > max_file_size {
+                return Err(ChunkRejection::Invalid(format!(
+                    "{} already being received, over the {} limit",
+                    format_size(receiving),
+                    format_size(max_file_size)
+                )));
+            }
+            self.incoming.insert(
+                chunk.transfer_uuid.clone(),
+                IncomingTransfer::new(&chunk, now),
+            );
+        }
+        let Some(transfer) = self.incoming.get_mut(&chunk.transfer_uuid) else {
+            return Ok(None);
+        };
+
+        if !transfer.matches(&chunk) {
+            return Err(ChunkRejection::Invalid(format!(
+                "chunk {} does not match the file of its transfer",
+                chunk.index
+            )));
+        }
+        // Copy of a chunk already stored
+        if transfer.state != TransferState::InProgress || transfer.chunks.contains_key(&chunk.index)
+        {
+            return Ok(None);
+        }
+        let len = chunk.data.len() as u64;
+        if transfer.received_bytes + len > transfer.file_size {
+            return Err(ChunkRejection::Invalid(format!(
+                "chunk {} goes past the {} bytes of the file",
+                chunk.index, transfer.file_size
+            )));
+        }
+        transfer.received_bytes += len;
+        transfer.last_chunk_at = now;
+        transfer.chunks.insert(chunk.index, chunk.data);
+        if transfer.chunks.len() < transfer.total_chunks as usize {
+            return Ok(None);
+        }
+
+        let data: Vec<u8> = std::mem::take(&mut transfer.chunks)
+            .into_values()
+            .flatten()
+            .collect();
+        transfer.received_bytes = 0;
+        if data.len() as u64 != transfer.file_size || file_hash(&data) != transfer.hash {
+            // Left in progress and empty, for the chunks sent again
+            return Err(ChunkRejection::Corrupted);
+        }
+        transfer.state = if transfer.mime_type == TEXT_MIME {
+            transfer.text = Some(String::from_utf8_lossy(&data).into_owned());
+            TransferState::Complete
+        } else {
+            match save_file(&self.downloads_dir, &transfer.filename, &data) {
+                Ok(path) => TransferState::Saved(path),
+                Err(e) => TransferState::Failed(format!("could not save the file: {e}")),
+            }
+        };
+        Ok(Some(transfer.state.clone()))
+    }
+
+    /// Content of a long text message received entirely
+    pub fn take_text(&mut self, uuid: &str) -> Option<String> {
+        self.incoming.get_mut(uuid)?.text.take()
+    }
+
+    /// Progress of the file behind message `uuid`, None for a text message
+    pub fn progress(&self, uuid: &str) -> Option<TransferProgress> {
+        if let Some(transfer) = self.outgoing.get(uuid) {
+            let total = transfer
+                .deliveries
+                .iter()
+                .map(|d| d.chunks.len() as u32)
+                .sum();
+            let done = transfer.deliveries.iter().map(Delivery::acked).sum();
+            let failed: Vec<&str> = transfer
+                .deliveries
+                .iter()
+                .filter(|d| d.failed)
+                .map(|d| d.peer.name.as_str())
+                .collect();
+            let state = if !failed.is_empty() {
+                TransferState::Failed(format!("not delivered to {}", failed.join(", ")))
+            } else if done == total {
+                TransferState::Complete
+            } else {
+                TransferState::InProgress
+            };
+            // Known only when every recipient is in the contact plan
+            let completion = transfer
+                .deliveries
+                .iter()
+                .map(|d| d.completion)
+                .collect::<Option<Vec<_>>>()
+                .and_then(|times| times.into_iter().max());
+            return Some(TransferProgress {
+                done,
+                total,
+                state,
+                completion,
+            });
+        }
+
+        let transfer = self.incoming.get(uuid)?;
+        let total = transfer.total_chunks;
+        let done = match transfer.state {
+            TransferState::InProgress => transfer.chunks.len() as u32,
+            _ => total,
+        };
+        Some(TransferProgress {
+            done,
+            total,
+            state: transfer.state.clone(),
+            completion: None,
+        })
+    }
+}
+
+/// Write a received file to `dir`, renamed if a file of the same name exists
+fn save_file(dir: &Path, filename: &str, data: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    // The name comes from the wire, keep its last component only
+    let name = Path::new(filename)
+        .file_name()
+        .map_or("file".to_string(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+    let stem = Path::new(&name)
+        .file_stem()
+        .map_or(name.clone(), |s| s.to_string_lossy().into_owned());
+    let extension = Path::new(&name)
+        .extension()
+        .map_or(String::new(), |e| format!(".{}", e.to_string_lossy()));
+
+    let mut path = dir.join(&name);
+    let mut copy = 1;
+    while path.exists() {
+        path = dir.join(format!("{stem} ({copy}){extension}"));
+        copy += 1;
+    }
+
+    // Write then rename so a crash never leaves a truncated file
+    let tmp_path = path.with_extension("part");
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(path)
+}
+
+fn send_chunks(chunks: Vec<OutgoingChunk>) {
+    for chunk in chunks {
+        // A chunk left without ACK is sent again later
+        if !send_to_peer(&chunk.peer, &chunk.payload) {
+            eprintln!(
+                "Could not send chunk {} of file {} to {}",
+                chunk.index, chunk.transfer_uuid, chunk.peer.name
+            );
+        }
+    }
+}
+
+/// Periodically send the file chunks that are due, for as long as the application runs
+pub fn start_file_transfer_worker(model: Arc<Mutex<ChatModel>>) {
+    TOKIO_RUNTIME.spawn(async move {
+        let mut interval = tokio::time::interval(FILE_TRANSFER_TICK);
+        loop {
+            interval.tick().await;
+            let chunks = model.lock().unwrap().release_file_chunks(Utc::now());
+            if chunks.is_empty() {
+                continue;
+            }
+            if let Err(e) = tokio::task::spawn_blocking(move || send_chunks(chunks)).await {
+                eprintln!("File transfer failed: {e}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSFER: &str = "transfer";
+
+    fn transfers() -> FileTransfers {
+        FileTransfers::new(PathBuf::new(), FileTransferPolicy::default())
+    }
+
+    /// Chunks of `data` sent as a long text message, which is not written to disk
+    fn chunks(data: &[u8], fragment_size: usize) -> Vec<FileChunk> {
+        let total_chunks = data.len().div_ceil(fragment_size) as u32;
+        data.chunks(fragment_size)
+            .enumerate()
+            .map(|(index, chunk)| FileChunk {
+                transfer_uuid: TRANSFER.to_string(),
+                filename: "message.txt".to_string(),
+                mime_type: TEXT_MIME.to_string(),
+                file_size: data.len() as u64,
+                file_hash: file_hash(data),
+                index: index as u32,
+                total_chunks,
+                data: chunk.to_vec(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut transfers = transfers();
+        let mut chunks = chunks(b"0123456789", 4);
+        let last = chunks.remove(0);
+        for chunk in chunks.into_iter().rev() {
+            assert_eq!(transfers.receive_chunk(chunk, Utc::now()), Ok(None));
+        }
+        assert_eq!(
+            transfers.receive_chunk(last, Utc::now()),
+            Ok(Some(TransferState::Complete))
+        );
+        assert_eq!(transfers.take_text(TRANSFER).as_deref(), Some("0123456789"));
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut transfers = transfers();
+        let chunks = chunks(b"0123456789", 4);
+        assert_eq!(
+            transfers.receive_chunk(chunks[0].clone(), Utc::now()),
+            Ok(None)
+        );
+        assert_eq!(
+            transfers.receive_chunk(chunks[0].clone(), Utc::now()),
+            Ok(None)
+        );
+        assert_eq!(transfers.progress(TRANSFER).map(|p| p.done), Some(1));
+        for chunk in chunks.iter().skip(1) {
+            transfers.receive_chunk(chunk.clone(), Utc::now()).unwrap();
+        }
+        // A copy arriving after the file is complete is still ACKed
+        assert_eq!(
+            transfers.receive_chunk(chunks[2].clone(), Utc::now()),
+            Ok(None)
+        );
+        assert_eq!(transfers.progress(TRANSFER).map(|p| p.done), Some(3));
+    }
+
+    #[test]
+    fn out_of_range_index() {
+        let mut transfers = transfers();
+        let mut chunk = chunks(b"0123456789", 4).remove(2);
+        chunk.index = 3;
+        assert!(matches!(
+            transfers.receive_chunk(chunk, Utc::now()),
+            Err(ChunkRejection::Invalid(_))
+        ));
+        assert!(transfers.progress(TRANSFER).is_none());
+    }
+
+    #[test]
+    fn chunk_count_bounded_by_file_size() {
+        let mut transfers = transfers();
+        let mut chunk = chunks(b"0123456789", 4).remove(2);
+        chunk.total_chunks = u32::MAX;
+        assert!(transfers.receive_chunk(chunk, Utc::now()).is_err());
+
+        let mut chunk = chunks(b"0123456789", 4).remove(0);
+        chunk.file_size = FileTransferPolicy::default().max_file_size + 1;
+        assert!(transfers.receive_chunk(chunk, Utc::now()).is_err());
+    }
+
+    #[test]
+    fn mismatching_metadata() {
+        let mut transfers = transfers();
+        let mut chunks = chunks(b"0123456789", 4);
+        transfers
+            .receive_chunk(chunks.remove(0), Utc::now())
+            .unwrap();
+        let mut other = chunks.remove(0);
+        other.file_hash = file_hash(b"something else");
+        assert!(matches!(
+            transfers.receive_chunk(other, Utc::now()),
+            Err(ChunkRejection::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn hash_mismatch() {
+        let mut transfers = transfers();
+        let good = chunks(b"0123456789", 4);
+        let mut bad = good.clone();
+        bad[1].data = b"xxxx".to_vec();
+        assert_eq!(
+            transfers.receive_chunk(bad[0].clone(), Utc::now()),
+            Ok(None)
+        );
+        assert_eq!(
+            transfers.receive_chunk(bad[1].clone(), Utc::now()),
+            Ok(None)
+        );
+        assert_eq!(
+            transfers.receive_chunk(bad[2].clone(), Utc::now()),
+            Err(ChunkRejection::Corrupted)
+        );
+
+        // The chunks sent again after the NACK are accepted
+        let progress = transfers.progress(TRANSFER).unwrap();
+        assert_eq!(
+            (progress.done, progress.state),
+            (0, TransferState::InProgress)
+        );
+        let mut state = Ok(None);
+        for chunk in good {
+            state = transfers.receive_chunk(chunk, Utc::now());
+        }
+        assert_eq!(state, Ok(Some(TransferState::Complete)));
+    }
+
+    #[test]
+    fn release_bounded_by_link_volume() {
+        let mut transfers = transfers();
+        let peer = |uuid: &str| Peer {
+            uuid: uuid.to_string(),
+            ..Peer::default()
+        };
+        let data = vec![7u8; 10 * MIN_FRAGMENT_SIZE];
+        let recipients = vec![
+            (peer("near"), MIN_FRAGMENT_SIZE, None),
+            (peer("far"), MIN_FRAGMENT_SIZE, None),
+        ];
+        transfers.start_transfer(TRANSFER, "room", "file", "x", data, recipients);
+        let bundle_size = |payload: usize| payload as f64 + 100.0;
+        let transfer = &transfers.outgoing[TRANSFER];
+        let chunk = transfer.create_chunk_message(TRANSFER, &transfer.deliveries[0], 1, "local");
+        let chunk_volume = bundle_size(chunk.encode_to_vec().len());
+        // Room for three and a half chunks to "near", "far" is outside the contact plan
+        let link_volume = HashMap::from([("near".to_string(), 3.5 * chunk_volume)]);
+        let count = |chunks: &[OutgoingChunk], uuid: &str| {
+            chunks
+                .iter()
+                .filter(|chunk| chunk.peer.uuid == uuid)
+                .count()
+        };
+
+        let now = Utc::now();
+        let (chunks, expired) = transfers.take_due(now, "local", &link_volume, bundle_size);
+        assert!(expired.is_empty());
+        assert_eq!(count(&chunks, "near"), 3);
+        assert_eq!(count(&chunks, "far"), 10);
+        assert!(chunks
+            .iter()
+            .all(|chunk| bundle_size(chunk.payload.len()) <= chunk_volume));
+
+        // The chunks waiting for their ACK still take their room on the contact
+        let (chunks, _) = transfers.take_due(now, "local", &link_volume, bundle_size);
+        assert!(chunks.is_empty());
+        for index in 0..3 {
+            transfers.acknowledge_chunk(&chunk_uuid(TRANSFER, index), "near");
+        }
+        let (chunks, _) = transfers.take_due(now, "local", &link_volume, bundle_size);
+        assert_eq!(count(&chunks, "near"), 3);
+        assert_eq!(chunks[0].index, 3);
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::utils::config::Peer;
+use crate::utils::message::{ChatMessage, MessageStatus};
+use crate::utils::proto::generate_uuid;
+
+fn fixture(sender: &Peer, text: &str, status: MessageStatus, room_uuid: &str) -> ChatMessage {
+    ChatMessage {
+        uuid: generate_uuid(),
+        response: None,
+        sender: sender.clone(),
+        text: text.to_owned(),
+        shipment_status: status,
+        endpoint: None,
+        attempts: 0,
+        history: Vec::new(),
+        room_uuid: room_uuid.to_string(),
+        recipients: Vec::new(),
+        routing: None,
+        predicted_ack: None,
+    }
+}
+
+/// Sample conversation of the `dev` builds, between the local peer and the first three peers
+pub fn seed_messages(local_peer: &Peer, peers: &[Peer], room_uuid: &str) -> Vec<ChatMessage> {
+    if peers.len() < 3 {
+        return Vec::new();
+    }
+    let mut now: DateTime<Utc> = Utc::now() - Duration::seconds(40);
+    let mut messages = Vec::new();
+
+    messages.push(fixture(
+        local_peer,
+        "Hello from local peer",
+        MessageStatus::Read(now, now + Duration::seconds(10)),
+        room_uuid,
+    ));
+    now += Duration::seconds(2);
+    messages.push(fixture(
+        &peers[2],
+        "Bob at your service !",
+        MessageStatus::Received(now, now + Duration::seconds(30)),
+        room_uuid,
+    ));
+    now += Duration::seconds(1);
+    messages.push(fixture(
+        &peers[0],
+        "Hello local peer, how are you?",
+        MessageStatus::Received(now, now + Duration::seconds(10)),
+        room_uuid,
+    ));
+    now += Duration::seconds(2);
+    messages.push(fixture(
+        &peers[0],
+        "I'm john does",
+        MessageStatus::Received(now, now + Duration::seconds(10)),
+        room_uuid,
+    ));
+    now += Duration::seconds(13);
+    messages.push(fixture(
+        local_peer,
+        "Hello john doe, Some news from alice ?",
+        MessageStatus::Delivered(now, now + Duration::seconds(10)),
+        room_uuid,
+    ));
+    now += Duration::seconds(5);
+    messages.push(fixture(
+        &peers[1],
+        "Sorry, I'm a bit late!",
+        MessageStatus::Received(now, now + Duration::seconds(12)),
+        room_uuid,
+    ));
+    messages
+}
@@ -0,0 +1,479 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::app::ChatModel;
+use crate::utils::config::{HistoryPolicy, Peer};
+use crate::utils::message::{ChatMessage, MessageStatus, Recipient};
+use crate::utils::socket::{Endpoint, TOKIO_RUNTIME};
+
+/// How often the changed messages are appended to the history
+const HISTORY_TICK: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// A `MessageStatus`, times in milliseconds since the UTC epoch
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StoredStatus {
+    state: String,
+    tx: i64,
+    /// PBAT of a sent message, time of the ACK, failure or expiry otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+fn millis_to_utc(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap_or_default()
+}
+
+impl From<&MessageStatus> for StoredStatus {
+    fn from(status: &MessageStatus) -> Self {
+        let ms = |t: &DateTime<Utc>| t.timestamp_millis();
+        let (at, reason) = match status {
+            MessageStatus::Draft(_) | MessageStatus::Queued(_) => (None, None),
+            MessageStatus::Sent(_, pbat) => (pbat.as_ref().map(ms), None),
+            MessageStatus::Delivered(_, at)
+            | MessageStatus::Read(_, at)
+            | MessageStatus::Expired(_, at)
+            | MessageStatus::Received(_, at) => (Some(ms(at)), None),
+            MessageStatus::Failed(_, at, reason) => (Some(ms(at)), Some(reason.clone())),
+        };
+        Self {
+            state: status.label().to_string(),
+            tx: ms(&status.tx()),
+            at,
+            reason,
+        }
+    }
+}
+
+impl StoredStatus {
+    fn status(&self) -> Option<MessageStatus> {
+        let tx = millis_to_utc(self.tx);
+        let at = self.at.map(millis_to_utc);
+        Some(match self.state.as_str() {
+            "Draft" => MessageStatus::Draft(tx),
+            "Queued" => MessageStatus::Queued(tx),
+            "Sent" => MessageStatus::Sent(tx, at),
+            "Delivered" => MessageStatus::Delivered(tx, at?),
+            "Read" => MessageStatus::Read(tx, at?),
+            "Failed" => MessageStatus::Failed(tx, at?, self.reason.clone().unwrap_or_default()),
+            "Expired" => MessageStatus::Expired(tx, at?),
+            "Received" => MessageStatus::Received(tx, at?),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StoredRecipient {
+    peer_uuid: String,
+    status: StoredStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    endpoint: Option<Endpoint>,
+    attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pbat: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    delivered_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    routing: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    predicted_ack: Option<i64>,
+}
+
+/// One line of a history file: the state of a message after a change
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StoredMessage {
+    uuid: String,
+    sender_uuid: String,
+    text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<String>,
+    status: StoredStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    endpoint: Option<Endpoint>,
+    attempts: u32,
+    /// Previous states with the time each one was left, oldest first
+    #[serde(default)]
+    history: Vec<(i64, StoredStatus)>,
+    #[serde(default)]
+    recipients: Vec<StoredRecipient>,
+    /// Routing algorithm of the PBAT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    routing: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    predicted_ack: Option<i64>,
+}
+
+impl From<&ChatMessage> for StoredMessage {
+    fn from(message: &ChatMessage) -> Self {
+        Self {
+            uuid: message.uuid.clone(),
+            sender_uuid: message.sender.uuid.clone(),
+            text: message.text.clone(),
+            response: message.response.clone(),
+            status: (&message.shipment_status).into(),
+            endpoint: message.endpoint.clone(),
+            attempts: message.attempts,
+            history: message
+                .history
+                .iter()
+                .map(|(left_at, status)| (left_at.timestamp_millis(), status.into()))
+                .collect(),
+            recipients: message
+                .recipients
+                .iter()
+                .map(|recipient| StoredRecipient {
+                    peer_uuid: recipient.peer.uuid.clone(),
+                    status: (&recipient.status).into(),
+                    endpoint: recipient.endpoint.clone(),
+                    attempts: recipient.attempts,
+                    pbat: recipient.pbat.map(|pbat| pbat.timestamp_millis()),
+                    delivered_at: recipient.delivered_at.map(|at| at.timestamp_millis()),
+                    routing: recipient.routing.clone(),
+                    predicted_ack: recipient.predicted_ack.map(|at| at.timestamp_millis()),
+                })
+                .collect(),
+            routing: message.routing.clone(),
+            predicted_ack: message.predicted_ack.map(|at| at.timestamp_millis()),
+        }
+    }
+}
+
+impl StoredMessage {
+    fn message(&self, room_uuid: &str, local_peer: &Peer, peers: &[Peer]) -> Option<ChatMessage> {
+        // Peers deleted since keep their UUID as name
+        let peer = |uuid: &str| {
+            std::iter::once(local_peer)
+                .chain(peers)
+                .find(|p| p.uuid == uuid)
+                .cloned()
+                .unwrap_or_else(|| Peer {
+                    uuid: uuid.to_string(),
+                    name: uuid.to_string(),
+                    ..Peer::default()
+                })
+        };
+        Some(ChatMessage {
+            uuid: self.uuid.clone(),
+            response: self.response.clone(),
+            sender: peer(&self.sender_uuid),
+            text: self.text.clone(),
+            shipment_status: self.status.status()?,
+            endpoint: self.endpoint.clone(),
+            attempts: self.attempts,
+            history: self
+                .history
+                .iter()
+                .filter_map(|(left_at, status)| Some((millis_to_utc(*left_at), status.status()?)))
+                .collect(),
+            room_uuid: room_uuid.to_string(),
+            recipients: self
+                .recipients
+                .iter()
+                .filter_map(|recipient| {
+                    Some(Recipient {
+                        peer: peer(&recipient.peer_uuid),
+                        status: recipient.status.status()?,
+                        endpoint: recipient.endpoint.clone(),
+                        attempts: recipient.attempts,
+                        pbat: recipient.pbat.map(millis_to_utc),
+                        delivered_at: recipient.delivered_at.map(millis_to_utc),
+                        routing: recipient.routing.clone(),
+                        predicted_ack: recipient.predicted_ack.map(millis_to_utc),
+                    })
+                })
+                .collect(),
+            routing: self.routing.clone(),
+            predicted_ack: self.predicted_ack.map(millis_to_utc),
+        })
+    }
+}
+
+/// Messages of the local peer, one append-only file per room
+pub struct HistoryStore {
+    dir: PathBuf,
+    policy: HistoryPolicy,
+    // Lines of each room file, compacted again once they reach twice the retention limit
+    lines: HashMap<PathBuf, usize>,
+}
+
+impl HistoryStore {
+    pub fn new(dir: &Path, policy: HistoryPolicy) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            policy,
+            lines: HashMap::new(),
+        }
+    }
+
+    /// A room UUID may come from a peer, any byte other than an ASCII alphanumeric,
+    /// `-` or `_` is escaped so the file always stays inside the history directory
+    fn room_path(&self, room_uuid: &str) -> PathBuf {
+        let mut stem = String::new();
+        for byte in room_uuid.bytes() {
+            if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+                stem.push(byte as char);
+            } else {
+                stem += &format!("%{byte:02x}");
+            }
+        }
+        self.dir.join(format!("{stem}.jsonl"))
+    }
+
+    /// Room UUID of a file stem written by `room_path`
+    fn room_uuid(stem: &str) -> Option<String> {
+        let mut bytes = Vec::new();
+        let mut rest = stem.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == b'%' {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            } else {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+        String::from_utf8(bytes).ok()
+    }
+
+    /// Read every room, keeping the last state of each message within the retention limit,
+    /// the files holding older lines are compacted
+    pub fn load(&mut self, local_peer: &Peer, peers: &[Peer]) -> Vec<ChatMessage> {
+        let (messages, lines) = self.read(local_peer, peers, true);
+        self.lines = lines;
+        messages
+    }
+
+    /// Read every room like `load`, leaving the files untouched
+    pub fn read_only(&self, local_peer: &Peer, peers: &[Peer]) -> Vec<ChatMessage> {
+        self.read(local_peer, peers, false).0
+    }
+
+    /// The messages, with the lines left in each room file
+    fn read(
+        &self,
+        local_peer: &Peer,
+        peers: &[Peer],
+        compact: bool,
+    ) -> (Vec<ChatMessage>, HashMap<PathBuf, usize>) {
+        let mut messages = Vec::new();
+        let mut room_lines = HashMap::new();
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return (messages, room_lines);
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "jsonl") {
+                continue;
+            }
+            let Some(room_uuid) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(Self::room_uuid)
+            else {
+                continue;
+            };
+            match self.load_room(&path, compact) {
+                Ok((stored, lines)) => {
+                    messages.extend(
+                        stored
+                            .iter()
+                            .filter_map(|message| message.message(&room_uuid, local_peer, peers)),
+                    );
+                    room_lines.insert(path, lines);
+                }
+                Err(e) => eprintln!("Failed to load history {}: {e}", path.display()),
+            }
+        }
+        (messages, room_lines)
+    }
+
+    /// Last state of the messages of a room within the retention limit,
+    /// with the lines left in the file
+    fn load_room(&self, path: &Path, compact: bool) -> io::Result<(Vec<StoredMessage>, usize)> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let mut lines = 0;
+        let mut order = Vec::new();
+        let mut latest: HashMap<String, StoredMessage> = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            lines += 1;
+            match serde_json::from_str::<StoredMessage>(&line) {
+                Ok(stored) => {
+                    if !latest.contains_key(&stored.uuid) {
+                        order.push(stored.uuid.clone());
+                    }
+                    latest.insert(stored.uuid.clone(), stored);
+                }
+                // A crash may leave a truncated last line
+                Err(e) => eprintln!("Skipping history line {lines} of {}: {e}", path.display()),
+            }
+        }
+
+        let mut stored: Vec<StoredMessage> = order
+            .into_iter()
+            .filter_map(|uuid| latest.remove(&uuid))
+            .collect();
+        stored.sort_by_key(|message| message.status.tx);
+        if let Some(days) = self.policy.max_age_days {
+            let oldest = (Utc::now() - Duration::days(days.into())).timestamp_millis();
+            stored.retain(|message| message.status.tx >= oldest);
+        }
+        let excess = stored.len().saturating_sub(self.policy.max_messages);
+        stored.drain(..excess);
+
+        if compact && stored.len() < lines {
+            Self::rewrite(path, &stored)?;
+            lines = stored.len();
+        }
+        Ok((stored, lines))
+    }
+
+    fn rewrite(path: &Path, stored: &[StoredMessage]) -> io::Result<()> {
+        let mut content = String::new();
+        for message in stored {
+            content += &serde_json::to_string(message).map_err(io::Error::other)?;
+            content.push('\n');
+        }
+        // Write then rename so a crash never leaves a truncated file
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Append the current state of `messages`, a room file holding too many lines is
+    /// compacted to the retention limit
+    pub fn append(&mut self, messages: &[&ChatMessage]) {
+        let mut by_room: HashMap<&str, (String, usize)> = HashMap::new();
+        for message in messages {
+            match serde_json::to_string(&StoredMessage::from(*message)) {
+                Ok(line) => {
+                    let (content, lines) = by_room.entry(&message.room_uuid).or_default();
+                    content.push_str(&line);
+                    content.push('\n');
+                    *lines += 1;
+                }
+                Err(e) => eprintln!("Failed to serialize message {}: {e}", message.uuid),
+            }
+        }
+
+        for (room_uuid, (content, appended)) in by_room {
+            let path = self.room_path(room_uuid);
+            let result = fs::create_dir_all(&self.dir).and_then(|_| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?
+                    .write_all(content.as_bytes())
+            });
+            if let Err(e) = result {
+                eprintln!("Failed to append history {}: {e}", path.display());
+                continue;
+            }
+
+            let lines = self.lines.get(&path).copied().unwrap_or(0) + appended;
+            // Every change of state adds a line, compact once they are twice the messages kept
+            let lines = if lines > 2 * self.policy.max_messages.max(1) {
+                match self.load_room(&path, true) {
+                    Ok((_, lines)) => lines,
+                    Err(e) => {
+                        eprintln!("Failed to compact history {}: {e}", path.display());
+                        lines
+                    }
+                }
+            } else {
+                lines
+            };
+            self.lines.insert(path, lines);
+        }
+    }
+}
+
+pub fn start_history_worker(model: Arc<Mutex<ChatModel>>) {
+    TOKIO_RUNTIME.spawn(async move {
+        let mut interval = tokio::time::interval(HISTORY_TICK);
+        loop {
+            interval.tick().await;
+            let model = model.clone();
+            let flush = move || model.lock().unwrap().flush_history();
+            if let Err(e) = tokio::task::spawn_blocking(flush).await {
+                eprintln!("History flush failed: {e}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn room_path_stays_in_dir() {
+        let store = HistoryStore::new(Path::new("history"), HistoryPolicy::default());
+        for room_uuid in [
+            "0",
+            "1b4e28ba-2fa1-11d2-883f-0016d3cca427",
+            "../../.bashrc",
+            "a/b\\c%",
+        ] {
+            let path = store.room_path(room_uuid);
+            assert_eq!(path.parent(), Some(Path::new("history")));
+            let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap();
+            assert_eq!(HistoryStore::room_uuid(stem).as_deref(), Some(room_uuid));
+        }
+    }
+
+    #[test]
+    fn log_is_compacted_while_running() {
+        let dir = std::env::temp_dir().join(format!("dtchat-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let policy = HistoryPolicy {
+            max_messages: 3,
+            max_age_days: None,
+        };
+        let mut store = HistoryStore::new(&dir, policy);
+        let local_peer = Peer {
+            uuid: "local".to_string(),
+            name: "local".to_string(),
+            ..Peer::default()
+        };
+        for i in 0..20 {
+            let at = DateTime::from_timestamp(1_700_000_000 + i, 0).unwrap();
+            let message = ChatMessage {
+                uuid: format!("message-{i}"),
+                response: None,
+                sender: local_peer.clone(),
+                text: "hello".to_string(),
+                shipment_status: MessageStatus::Received(at, at),
+                endpoint: None,
+                attempts: 0,
+                history: Vec::new(),
+                room_uuid: "room".to_string(),
+                recipients: Vec::new(),
+                routing: None,
+                predicted_ack: None,
+            };
+            store.append(&[&message]);
+            let lines = fs::read_to_string(store.room_path("room"))
+                .unwrap()
+                .lines()
+                .count();
+            assert!(lines <= 6, "{lines} lines after {} messages", i + 1);
+        }
+
+        let uuids: Vec<String> = store
+            .read_only(&local_peer, &[])
+            .into_iter()
+            .map(|message| message.uuid)
+            .collect();
+        assert_eq!(uuids, ["message-17", "message-18", "message-19"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,117 @@
 use chrono::{DateTime, Utc};
 
 use super::config::Peer;
+use super::socket::Endpoint;
 
+/// Lifecycle of a message, every state starts with the creation or transmission time
 #[derive(Clone, Debug, PartialEq)]
 pub enum MessageStatus {
+    Draft(DateTime<Utc>),  // Message written, not handed to the outbox yet
+    Queued(DateTime<Utc>), // Message waiting in the outbox
     Sent(DateTime<Utc>, Option<DateTime<Utc>>), // Message sent, awaiting ACK
-    Received(DateTime<Utc>, DateTime<Utc>),     // Message received from peer
+    Delivered(DateTime<Utc>, DateTime<Utc>), // Message ACKed by the receiver
+    Read(DateTime<Utc>, DateTime<Utc>), // Message read by the receiver
+    Failed(DateTime<Utc>, DateTime<Utc>, String), // Last send failed, with the reason
+    Expired(DateTime<Utc>, DateTime<Utc>), // No ACK after the last allowed attempt
+    Received(DateTime<Utc>, DateTime<Utc>), // Message received from peer
+}
+
+impl MessageStatus {
+    /// Every `label()`, along the lifecycle
+    pub const LABELS: [&'static str; 8] = [
+        "Draft",
+        "Queued",
+        "Sent",
+        "Delivered",
+        "Read",
+        "Failed",
+        "Expired",
+        "Received",
+    ];
+
+    /// Creation or transmission time
+    pub fn tx(&self) -> DateTime<Utc> {
+        match self {
+            Self::Draft(tx)
+            | Self::Queued(tx)
+            | Self::Sent(tx, _)
+            | Self::Delivered(tx, _)
+            | Self::Read(tx, _)
+            | Self::Failed(tx, _, _)
+            | Self::Expired(tx, _)
+            | Self::Received(tx, _) => *tx,
+        }
+    }
+
+    /// Time the message reached the other end, if known
+    pub fn rx(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Delivered(_, rx) | Self::Read(_, rx) | Self::Received(_, rx) => Some(*rx),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Draft(_) => "Draft",
+            Self::Queued(_) => "Queued",
+            Self::Sent(..) => "Sent",
+            Self::Delivered(..) => "Delivered",
+            Self::Read(..) => "Read",
+            Self::Failed(..) => "Failed",
+            Self::Expired(..) => "Expired",
+            Self::Received(..) => "Received",
+        }
+    }
+
+    // Order of the states along the lifecycle, the least advanced recipient gives the
+    // state of a message sent to several peers
+    fn progress(&self) -> u8 {
+        match self {
+            Self::Draft(_) => 0,
+            Self::Queued(_) => 1,
+            Self::Failed(..) => 2,
+            Self::Sent(..) => 3,
+            Self::Expired(..) => 4,
+            Self::Delivered(..) => 5,
+            Self::Read(..) | Self::Received(..) => 6,
+        }
+    }
+
+    /// Whether a message in this state may move to `next`
+    pub fn allows(&self, next: &MessageStatus) -> bool {
+        use MessageStatus::*;
+        match (self, next) {
+            (Draft(_), Queued(_) | Sent(..) | Failed(..)) => true,
+            // A retransmission updates the PBAT, a late ACK still counts after a failure
+            (
+                Queued(_) | Sent(..) | Failed(..) | Expired(..),
+                Sent(..) | Delivered(..) | Read(..),
+            ) => true,
+            (Queued(_) | Sent(..), Failed(..) | Expired(..)) => true,
+            (Failed(..), Expired(..)) => true,
+            (Delivered(..), Read(..)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Delivery of a sent message to one of its recipients
+#[derive(Clone, Debug)]
+pub struct Recipient {
+    pub peer: Peer,
+    pub status: MessageStatus,
+    /// Endpoint that carried the last transmission
+    pub endpoint: Option<Endpoint>,
+    pub attempts: u32,
+    /// Latest predicted arrival, kept once the ACK arrives
+    pub pbat: Option<DateTime<Utc>>,
+    /// Time of the delivery ACK, kept once the read receipt arrives
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// Routing algorithm that produced `pbat`
+    pub routing: Option<String>,
+    /// Predicted arrival of the ACK back to the local peer, routed from `pbat`
+    pub predicted_ack: Option<DateTime<Utc>>,
 }
 
 #[derive(Clone, Debug)]
@@ -15,65 +121,388 @@ pub struct ChatMessage {
     pub sender: Peer,
     pub text: String,
     pub shipment_status: MessageStatus,
+    /// Endpoint that carried the message (used to send it, or received it)
+    pub endpoint: Option<Endpoint>,
+    /// Transmissions of the message by the local peer
+    pub attempts: u32,
+    /// Previous states, oldest first, with the time each one was left
+    pub history: Vec<(DateTime<Utc>, MessageStatus)>,
+    pub room_uuid: String,
+    /// Per-recipient delivery of a message sent by the local peer
+    pub recipients: Vec<Recipient>,
+    /// Routing algorithm that produced the latest PBAT, e.g. `CgrFirstEndingContactGraph/EVL`
+    pub routing: Option<String>,
+    /// Predicted arrival of the last ACK back to the local peer
+    pub predicted_ack: Option<DateTime<Utc>>,
 }
 
 impl ChatMessage {
     pub fn get_shipment_status_str(&self, sent_by_me: bool) -> String {
-        match &self.shipment_status {
+        let mut status = self.get_status_str(sent_by_me);
+        if self.recipients.len() > 1 {
+            status += &format!(
+                "[{}/{} delivered]",
+                self.delivered_count(),
+                self.recipients.len()
+            );
+        }
+        if self.attempts > 1 {
+            status += &format!("[attempt {}]", self.attempts);
+        }
+        status
+    }
+
+    fn get_status_str(&self, sent_by_me: bool) -> String {
+        let time = |t: &DateTime<Utc>| t.format("%H:%M:%S").to_string();
+        let state = match &self.shipment_status {
+            MessageStatus::Draft(created) => format!("{}->draft", time(created)),
+            MessageStatus::Queued(created) => format!("{}->queued", time(created)),
             MessageStatus::Sent(tx, pbat) => {
-                let pred_str = if let Some(pbat_time) = pbat {
-                    pbat_time.format("%H:%M:%S").to_string()
-                } else {
-                    "??".to_string()
-                };
-
-                format!(
-                    "[{}->{}][{}]",
-                    tx.format("%H:%M:%S"),
-                    pred_str,
-                    self.sender.name
-                )
+                let pred_str = pbat.as_ref().map_or("??".to_string(), time);
+                match &self.predicted_ack {
+                    Some(ack) => format!("{}->{}->ACK {}", time(tx), pred_str, time(ack)),
+                    None => format!("{}->{}", time(tx), pred_str),
+                }
             }
+            MessageStatus::Delivered(tx, ack) => format!("{}->{}✓", time(tx), time(ack)),
+            MessageStatus::Read(tx, read) => format!("{}->{}✓✓", time(tx), time(read)),
+            MessageStatus::Failed(tx, _, reason) => format!("{}->failed: {reason}", time(tx)),
+            MessageStatus::Expired(tx, at) => format!("{}->expired {}", time(tx), time(at)),
             MessageStatus::Received(tx, rx) => {
                 let acked = if sent_by_me { "✓" } else { "" };
-                format!(
-                    "[{}->{}{}][{}]",
-                    tx.format("%H:%M:%S"),
-                    rx.format("%H:%M:%S"),
-                    acked,
-                    self.sender.name
-                )
+                format!("{}->{}{}", time(tx), time(rx), acked)
             }
-        }
+        };
+        format!("[{state}][{}]", self.sender.name)
     }
+
     pub fn get_timestamps(&self) -> (f64, Option<f64>, Option<f64>) {
-        match self.shipment_status {
+        let ms = |t: DateTime<Utc>| t.timestamp_millis() as f64;
+        match &self.shipment_status {
             MessageStatus::Sent(tx, pbat_opt) => {
-                let pbat_val = pbat_opt.unwrap_or(tx);
-                (
-                    tx.timestamp_millis() as f64,
-                    Some(pbat_val.timestamp_millis() as f64),
-                    None,
-                )
+                let pbat_val = pbat_opt.unwrap_or(*tx);
+                (ms(*tx), Some(ms(pbat_val)), None)
             }
-            MessageStatus::Received(tx, rx) => (
-                tx.timestamp_millis() as f64,
-                None,
-                Some(rx.timestamp_millis() as f64),
-            ),
+            status => (ms(status.tx()), None, status.rx().map(ms)),
+        }
+    }
+
+    pub fn is_queued(&self) -> bool {
+        matches!(self.shipment_status, MessageStatus::Queued(_))
+    }
+
+    /// Move to `next` and record the current state in the history,
+    /// returns false if the lifecycle does not allow it
+    pub fn transition(&mut self, next: MessageStatus) -> bool {
+        if !self.shipment_status.allows(&next) {
+            return false;
+        }
+        let previous = std::mem::replace(&mut self.shipment_status, next);
+        self.history.push((Utc::now(), previous));
+        true
+    }
+
+    /// Track the delivery to `peer`, currently in `status`
+    pub fn add_recipient(&mut self, peer: &Peer, status: MessageStatus, attempts: u32) {
+        if self.recipient_mut(&peer.uuid).is_some() {
+            return;
+        }
+        self.recipients.push(Recipient {
+            peer: peer.clone(),
+            status,
+            endpoint: None,
+            attempts,
+            pbat: None,
+            delivered_at: None,
+            routing: None,
+            predicted_ack: None,
+        });
+        self.refresh_status();
+    }
+
+    pub fn recipient_mut(&mut self, peer_uuid: &str) -> Option<&mut Recipient> {
+        self.recipients
+            .iter_mut()
+            .find(|r| r.peer.uuid == peer_uuid)
+    }
+
+    /// Recipients that acknowledged the message
+    pub fn delivered_count(&self) -> usize {
+        self.recipients
+            .iter()
+            .filter(|r| r.status.rx().is_some())
+            .count()
+    }
+
+    /// Latest predicted arrival of the message, kept once the ACK arrives
+    pub fn predicted_arrival(&self) -> Option<DateTime<Utc>> {
+        std::iter::once(&self.shipment_status)
+            .chain(self.history.iter().rev().map(|(_, status)| status))
+            .find_map(|status| match status {
+                MessageStatus::Sent(_, pbat) => *pbat,
+                _ => None,
+            })
+    }
+
+    /// Time of the latest delivery ACK, kept once the read receipt arrives
+    pub fn delivered_at(&self) -> Option<DateTime<Utc>> {
+        std::iter::once(&self.shipment_status)
+            .chain(self.history.iter().rev().map(|(_, status)| status))
+            .find_map(|status| match status {
+                MessageStatus::Delivered(_, at) => Some(*at),
+                _ => None,
+            })
+    }
+
+    /// Time of the first transmission of the message, to `peer_uuid` or else to any recipient
+    pub fn transmission_time(&self, peer_uuid: &str) -> Option<DateTime<Utc>> {
+        let sent_tx = |status: &MessageStatus| match status {
+            MessageStatus::Sent(tx, _) | MessageStatus::Failed(tx, _, _) => Some(*tx),
+            _ => None,
+        };
+        self.recipients
+            .iter()
+            .find(|r| r.peer.uuid == peer_uuid)
+            .and_then(|r| sent_tx(&r.status))
+            .or_else(|| sent_tx(&self.shipment_status))
+    }
+
+    /// Creation or transmission time of the delivery to `peer_uuid`
+    pub fn tx_for(&self, peer_uuid: &str) -> DateTime<Utc> {
+        self.recipients
+            .iter()
+            .find(|r| r.peer.uuid == peer_uuid)
+            .map_or(&self.shipment_status, |r| &r.status)
+            .tx()
+    }
+
+    /// Move the delivery to `peer_uuid` to `next`, or the whole message when it has no
+    /// such recipient, returns false if the lifecycle does not allow it
+    pub fn transition_for(&mut self, peer_uuid: &str, next: MessageStatus) -> bool {
+        let Some(recipient) = self.recipient_mut(peer_uuid) else {
+            return self.transition(next);
+        };
+        if !recipient.status.allows(&next) {
+            return false;
+        }
+        match next {
+            MessageStatus::Sent(_, Some(pbat)) => recipient.pbat = Some(pbat),
+            MessageStatus::Delivered(_, at) => recipient.delivered_at = Some(at),
+            _ => {}
         }
+        recipient.status = next;
+        self.refresh_status();
+        true
     }
 
-    /// Update message status when ACK is received
-    pub fn update_with_ack(&mut self, _is_read: bool, ack_time: DateTime<Utc>) {
-        match self.shipment_status {
-            MessageStatus::Sent(sent_time, _pbat) => {
-                // For now, we only distinguish between sent and acknowledged
-                self.shipment_status = MessageStatus::Received(sent_time, ack_time);
+    /// Derive the state of the message from its recipients
+    fn refresh_status(&mut self) {
+        let Some(least) = self
+            .recipients
+            .iter()
+            .map(|r| &r.status)
+            .min_by_key(|s| s.progress())
+        else {
+            return;
+        };
+        let latest_rx = self.recipients.iter().filter_map(|r| r.status.rx()).max();
+        let next = match least {
+            MessageStatus::Sent(tx, _) => {
+                // Predicted arrival at the last recipient still waiting
+                let pbat = self
+                    .recipients
+                    .iter()
+                    .filter_map(|r| match r.status {
+                        MessageStatus::Sent(_, pbat) => pbat,
+                        _ => None,
+                    })
+                    .max();
+                MessageStatus::Sent(*tx, pbat)
             }
-            _ => {
-                // Message is already acknowledged or received, no update needed
+            MessageStatus::Delivered(tx, rx) => {
+                MessageStatus::Delivered(*tx, latest_rx.unwrap_or(*rx))
             }
+            MessageStatus::Read(tx, rx) => MessageStatus::Read(*tx, latest_rx.unwrap_or(*rx)),
+            status => status.clone(),
+        };
+
+        self.attempts = self
+            .recipients
+            .iter()
+            .map(|r| r.attempts)
+            .max()
+            .unwrap_or(0);
+        // Not checked against the lifecycle: the aggregate goes back, e.g. from Failed to
+        // Queued, when a failed recipient is retried
+        if next != self.shipment_status {
+            let previous = std::mem::replace(&mut self.shipment_status, next);
+            self.history.push((Utc::now(), previous));
+        }
+    }
+
+    /// Update message status when `ack_sender_uuid` acknowledges it
+    pub fn update_with_ack(
+        &mut self,
+        ack_sender_uuid: &str,
+        is_read: bool,
+        ack_time: DateTime<Utc>,
+    ) {
+        let tx = self.tx_for(ack_sender_uuid);
+        let next = if is_read {
+            MessageStatus::Read(tx, ack_time)
+        } else {
+            MessageStatus::Delivered(tx, ack_time)
+        };
+        // Message already acknowledged or received, no update needed
+        self.transition_for(ack_sender_uuid, next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn peer(uuid: &str) -> Peer {
+        Peer {
+            uuid: uuid.to_string(),
+            name: uuid.to_string(),
+            ..Peer::default()
+        }
+    }
+
+    fn message(status: MessageStatus) -> ChatMessage {
+        ChatMessage {
+            uuid: "message".to_string(),
+            response: None,
+            sender: peer("local"),
+            text: "hello".to_string(),
+            shipment_status: status,
+            endpoint: None,
+            attempts: 0,
+            history: Vec::new(),
+            room_uuid: "room".to_string(),
+            recipients: Vec::new(),
+            routing: None,
+            predicted_ack: None,
         }
     }
+
+    fn failed(tx: i64) -> MessageStatus {
+        MessageStatus::Failed(at(tx), at(tx), "closed".to_string())
+    }
+
+    #[test]
+    fn allowed_transitions() {
+        use MessageStatus::*;
+        let allowed = [
+            (Draft(at(0)), Queued(at(0))),
+            (Draft(at(0)), Sent(at(1), None)),
+            (Draft(at(0)), failed(1)),
+            (Queued(at(0)), Sent(at(1), Some(at(5)))),
+            (Queued(at(0)), failed(1)),
+            (Sent(at(1), None), Sent(at(2), Some(at(6)))),
+            (Sent(at(1), None), Delivered(at(1), at(3))),
+            (Sent(at(1), None), Read(at(1), at(3))),
+            (Sent(at(1), None), Expired(at(1), at(9))),
+            (failed(1), Sent(at(2), None)),
+            (failed(1), Delivered(at(1), at(3))),
+            (failed(1), Expired(at(1), at(9))),
+            (Expired(at(1), at(9)), Delivered(at(1), at(10))),
+            (Delivered(at(1), at(3)), Read(at(1), at(4))),
+        ];
+        for (from, to) in allowed {
+            assert!(from.allows(&to), "{from:?} -> {to:?}");
+        }
+    }
+
+    #[test]
+    fn rejected_transitions() {
+        use MessageStatus::*;
+        let rejected = [
+            (Draft(at(0)), Delivered(at(0), at(1))),
+            (Draft(at(0)), Expired(at(0), at(1))),
+            (Queued(at(0)), Draft(at(0))),
+            (Sent(at(1), None), Queued(at(1))),
+            (Delivered(at(1), at(3)), Sent(at(2), None)),
+            (Delivered(at(1), at(3)), failed(2)),
+            (Delivered(at(1), at(3)), Expired(at(1), at(9))),
+            (Read(at(1), at(4)), Delivered(at(1), at(3))),
+            (Expired(at(1), at(9)), failed(9)),
+            (Received(at(1), at(2)), Delivered(at(1), at(3))),
+        ];
+        for (from, to) in rejected {
+            assert!(!from.allows(&to), "{from:?} -> {to:?}");
+        }
+    }
+
+    #[test]
+    fn transition_records_history() {
+        let mut msg = message(MessageStatus::Draft(at(0)));
+        assert!(msg.transition(MessageStatus::Sent(at(1), Some(at(5)))));
+        assert!(!msg.transition(MessageStatus::Draft(at(2))));
+        assert_eq!(msg.shipment_status, MessageStatus::Sent(at(1), Some(at(5))));
+        assert_eq!(msg.history.len(), 1);
+        assert_eq!(msg.history[0].1, MessageStatus::Draft(at(0)));
+    }
+
+    #[test]
+    fn aggregate_follows_the_least_advanced_recipient() {
+        let mut msg = message(MessageStatus::Queued(at(0)));
+        msg.add_recipient(&peer("a"), MessageStatus::Queued(at(0)), 0);
+        msg.add_recipient(&peer("b"), MessageStatus::Queued(at(0)), 0);
+
+        assert!(msg.transition_for("a", MessageStatus::Sent(at(1), Some(at(5)))));
+        assert_eq!(msg.shipment_status, MessageStatus::Queued(at(0)));
+        assert!(msg.transition_for("b", MessageStatus::Sent(at(1), Some(at(8)))));
+        // Predicted arrival at the last recipient still waiting
+        assert_eq!(msg.shipment_status, MessageStatus::Sent(at(1), Some(at(8))));
+
+        assert!(msg.transition_for("b", MessageStatus::Delivered(at(1), at(7))));
+        assert_eq!(msg.shipment_status, MessageStatus::Sent(at(1), Some(at(5))));
+        assert!(msg.transition_for("a", MessageStatus::Delivered(at(1), at(4))));
+        // Latest ACK of the recipients
+        assert_eq!(msg.shipment_status, MessageStatus::Delivered(at(1), at(7)));
+        assert_eq!(msg.delivered_count(), 2);
+
+        assert!(!msg.transition_for("a", MessageStatus::Sent(at(2), None)));
+    }
+
+    #[test]
+    fn aggregate_goes_back_outside_of_the_lifecycle() {
+        let mut msg = message(MessageStatus::Queued(at(0)));
+        msg.add_recipient(&peer("a"), MessageStatus::Queued(at(0)), 1);
+        assert!(msg.transition_for("a", failed(1)));
+        assert_eq!(msg.shipment_status, failed(1));
+        assert!(msg.transition_for("a", MessageStatus::Delivered(at(1), at(3))));
+        assert_eq!(msg.shipment_status, MessageStatus::Delivered(at(1), at(3)));
+
+        // A recipient added later, e.g. to a file transfer, is queued again
+        msg.add_recipient(&peer("b"), MessageStatus::Queued(at(4)), 0);
+        assert!(!MessageStatus::Delivered(at(1), at(3)).allows(&MessageStatus::Queued(at(4))));
+        assert_eq!(msg.shipment_status, MessageStatus::Queued(at(4)));
+        assert_eq!(
+            msg.history.last().map(|(_, status)| status),
+            Some(&MessageStatus::Delivered(at(1), at(3)))
+        );
+    }
+
+    #[test]
+    fn latest_pbat_and_ack_after_retransmission() {
+        let mut msg = message(MessageStatus::Draft(at(0)));
+        assert!(msg.transition(MessageStatus::Sent(at(1), Some(at(5)))));
+        assert!(msg.transition(MessageStatus::Sent(at(10), Some(at(15)))));
+        assert!(msg.transition(MessageStatus::Delivered(at(10), at(14))));
+        assert!(msg.transition(MessageStatus::Read(at(10), at(20))));
+        assert_eq!(msg.predicted_arrival(), Some(at(15)));
+        assert_eq!(msg.delivered_at(), Some(at(14)));
+
+        // An ACK of the first transmission, older in the history
+        msg.history
+            .insert(1, (at(6), MessageStatus::Delivered(at(1), at(4))));
+        assert_eq!(msg.delivered_at(), Some(at(14)));
+    }
 }
@@ -1,7 +1,16 @@
 pub mod ack;
 pub mod colors;
 pub mod config;
+pub mod export;
+pub mod file_transfer;
+#[cfg(feature = "dev")]
+pub mod fixtures;
+pub mod history;
 pub mod message;
+pub mod outbox;
 pub mod prediction_config;
+pub mod presence;
 pub mod proto;
+pub mod rooms;
+pub mod search;
 pub mod socket;
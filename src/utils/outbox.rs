@@ -0,0 +1,477 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::app::{AppEvent, ChatModel};
+use crate::utils::ack::create_nack_message;
+use crate::utils::config::{Peer, Room};
+use crate::utils::message::ChatMessage;
+use crate::utils::presence::{create_presence_message, Presence};
+use crate::utils::proto::generate_uuid;
+use crate::utils::rooms::{create_room_control_message, RoomAction};
+use crate::utils::socket::{send_to_peer, Endpoint, GenericSocket, SendingSocket, TOKIO_RUNTIME};
+
+/// How often the outbox is checked for messages to release
+const OUTBOX_TICK: std::time::Duration = std::time::Duration::from_secs(1);
+/// Delay before the first retry, doubled after each failed attempt
+const RETRY_BASE_SECS: i64 = 2;
+const RETRY_MAX_SECS: i64 = 300;
+
+/// Control message carried by an outbox entry in place of a chat message
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum OutboxControl {
+    /// Change of `room` for one of its participants
+    Room { action: RoomAction, room: Room },
+    /// Request to send all the chunks of a file again, it arrived corrupted
+    Nack { transfer_uuid: String },
+    /// Local presence picked at `at`, in milliseconds since the UTC epoch
+    Presence { presence: Presence, at: i64 },
+}
+
+impl OutboxControl {
+    /// Serialized message for the receiver, `uuid` kept across the retries
+    pub fn encode(&self, uuid: &str, local_peer_uuid: &str) -> Vec<u8> {
+        use prost::Message;
+
+        match self {
+            Self::Room { action, room } => {
+                create_room_control_message(uuid, *action, room, local_peer_uuid).encode_to_vec()
+            }
+            Self::Nack { transfer_uuid } => {
+                create_nack_message(uuid, transfer_uuid, local_peer_uuid).encode_to_vec()
+            }
+            Self::Presence { presence, at } => {
+                create_presence_message(uuid, *presence, *at, local_peer_uuid).encode_to_vec()
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Room { action, room } => format!("{action:?} of room {}", room.name),
+            Self::Nack { transfer_uuid } => format!("NACK of file {transfer_uuid}"),
+            Self::Presence { presence, .. } => format!("presence {}", presence.label()),
+        }
+    }
+}
+
+/// A message waiting to be handed to the transport
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub message_uuid: String,
+    pub receiver_uuid: String,
+    pub text: String,
+    pub response: Option<String>,
+    /// Creation time, in milliseconds since the UTC epoch
+    pub created_at: i64,
+    /// Compute a PBAT when the message is released
+    pub pbat: bool,
+    /// Send over every valid endpoint of the receiver at once
+    pub redundant: bool,
+    /// Room the message was written in, selects the retransmission policy
+    #[serde(default)]
+    pub room_uuid: Option<String>,
+    /// Failed sends since the last transmission, drives the backoff
+    pub attempts: u32,
+    /// Transmissions handed to the transport so far, still without ACK
+    #[serde(default)]
+    pub transmissions: u32,
+    /// Earliest time of the next attempt, in milliseconds since the UTC epoch
+    pub next_attempt: i64,
+    /// Sent instead of `text`, without message in the rooms nor ACK
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control: Option<OutboxControl>,
+    /// Size of the chunks carrying `text` when a single bundle cannot, the entry then waits
+    /// for the file transfers to have every chunk acknowledged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment_size: Option<usize>,
+    // Handed to the transport by a flush, not persisted
+    #[serde(skip)]
+    in_flight: bool,
+}
+
+impl OutboxEntry {
+    pub fn new(message: &ChatMessage, receiver: &Peer, pbat: bool, redundant: bool) -> Self {
+        let now = Utc::now().timestamp_millis();
+        Self {
+            message_uuid: message.uuid.clone(),
+            receiver_uuid: receiver.uuid.clone(),
+            text: message.text.clone(),
+            response: message.response.clone(),
+            created_at: now,
+            pbat,
+            redundant,
+            room_uuid: Some(message.room_uuid.clone()),
+            attempts: 0,
+            transmissions: 0,
+            next_attempt: now,
+            control: None,
+            fragment_size: None,
+            in_flight: false,
+        }
+    }
+
+    /// Entry carrying `control` to `receiver`
+    pub fn control(receiver: &Peer, room_uuid: Option<String>, control: OutboxControl) -> Self {
+        let now = Utc::now().timestamp_millis();
+        Self {
+            message_uuid: generate_uuid(),
+            receiver_uuid: receiver.uuid.clone(),
+            text: String::new(),
+            response: None,
+            created_at: now,
+            pbat: false,
+            redundant: false,
+            room_uuid,
+            attempts: 0,
+            transmissions: 0,
+            next_attempt: now,
+            control: Some(control),
+            fragment_size: None,
+            in_flight: false,
+        }
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.created_at).unwrap_or_else(Utc::now)
+    }
+}
+
+/// Exponential backoff after `attempts` failed sends
+pub fn retry_delay(attempts: u32) -> Duration {
+    let factor = 1i64 << attempts.saturating_sub(1).min(16);
+    Duration::seconds((RETRY_BASE_SECS * factor).min(RETRY_MAX_SECS))
+}
+
+/// Queue of outgoing messages, persisted to disk on every change
+pub struct Outbox {
+    path: PathBuf,
+    entries: Vec<OutboxEntry>,
+}
+
+impl Outbox {
+    /// Load the outbox stored at `path`, starting empty if there is none
+    pub fn load(path: &Path) -> Self {
+        let entries = match fs::read_to_string(path) {
+            Ok(content) => serde_yaml::from_str(&content).unwrap_or_else(|e| {
+                eprintln!("Failed to parse outbox {}: {e}", path.display());
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        Self {
+            path: path.to_path_buf(),
+            entries,
+        }
+    }
+
+    fn save(&self) {
+        let path = &self.path;
+        let result = serde_yaml::to_string(&self.entries)
+            .map_err(std::io::Error::other)
+            .and_then(|content| {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                // Write then rename so a crash never leaves a truncated outbox
+                let tmp_path = path.with_extension("tmp");
+                fs::write(&tmp_path, content)?;
+                fs::rename(&tmp_path, path)
+            });
+
+        if let Err(e) = result {
+            eprintln!("Failed to save outbox {}: {e}", path.display());
+        }
+    }
+
+    pub fn entries(&self) -> &[OutboxEntry] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, entry: OutboxEntry) {
+        self.entries.push(entry);
+        self.save();
+    }
+
+    pub fn remove(&mut self, message_uuid: &str, receiver_uuid: &str) {
+        self.entries
+            .retain(|e| !(e.message_uuid == message_uuid && e.receiver_uuid == receiver_uuid));
+        self.save();
+    }
+
+    /// Drop the control entries to `receiver_uuid` that `outdated` selects
+    pub fn remove_controls(
+        &mut self,
+        receiver_uuid: &str,
+        outdated: impl Fn(&OutboxControl) -> bool,
+    ) {
+        self.entries.retain(|e| {
+            e.receiver_uuid != receiver_uuid || !e.control.as_ref().is_some_and(&outdated)
+        });
+        self.save();
+    }
+
+    /// Drop the entry of a message acknowledged by its receiver, returns whether there was one
+    pub fn acknowledge(&mut self, message_uuid: &str, receiver_uuid: &str) -> bool {
+        let len = self.entries.len();
+        self.entries
+            .retain(|e| !(e.message_uuid == message_uuid && e.receiver_uuid == receiver_uuid));
+        let removed = self.entries.len() != len;
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    fn entry_mut(&mut self, message_uuid: &str, receiver_uuid: &str) -> Option<&mut OutboxEntry> {
+        self.entries
+            .iter_mut()
+            .find(|e| e.message_uuid == message_uuid && e.receiver_uuid == receiver_uuid)
+    }
+
+    /// Mark the entries due at `now` as in flight and return them,
+    /// the ones sent in chunks are left to the file transfers
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<OutboxEntry> {
+        let now = now.timestamp_millis();
+        self.entries
+            .iter_mut()
+            .filter(|e| !e.in_flight && e.fragment_size.is_none() && e.next_attempt <= now)
+            .map(|e| {
+                e.in_flight = true;
+                e.clone()
+            })
+            .collect()
+    }
+
+    /// Put an entry back in the queue until `at`, counting a failed attempt if `failed`
+    pub fn postpone(
+        &mut self,
+        message_uuid: &str,
+        receiver_uuid: &str,
+        at: DateTime<Utc>,
+        failed: bool,
+    ) {
+        if let Some(entry) = self.entry_mut(message_uuid, receiver_uuid) {
+            entry.in_flight = false;
+            entry.next_attempt = at.timestamp_millis();
+            if failed {
+                entry.attempts += 1;
+            }
+        }
+        self.save();
+    }
+
+    /// Keep an entry sent in chunks of `fragment_size` until the whole transfer is acknowledged
+    pub fn fragment(&mut self, message_uuid: &str, receiver_uuid: &str, fragment_size: usize) {
+        if let Some(entry) = self.entry_mut(message_uuid, receiver_uuid) {
+            entry.in_flight = false;
+            entry.fragment_size = Some(fragment_size);
+        }
+        self.save();
+    }
+
+    /// Keep a transmitted entry until its ACK arrives, retransmitting it at `deadline`
+    pub fn await_ack(&mut self, message_uuid: &str, receiver_uuid: &str, deadline: DateTime<Utc>) {
+        if let Some(entry) = self.entry_mut(message_uuid, receiver_uuid) {
+            entry.in_flight = false;
+            entry.transmissions += 1;
+            entry.attempts = 0;
+            entry.next_attempt = deadline.timestamp_millis();
+        }
+        self.save();
+    }
+}
+
+/// Hand `message` to the transport, trying the endpoints of `receiver` in order
+/// until one succeeds, or all of them when `redundant` is set.
+/// Returns the endpoints that carried it.
+pub fn transmit(
+    message: &ChatMessage,
+    receiver: &Peer,
+    redundant: bool,
+) -> Result<Vec<Endpoint>, String> {
+    let endpoints = receiver.send_endpoints();
+    if endpoints.is_empty() {
+        return Err(format!("No valid endpoint for {}", receiver.name));
+    }
+
+    #[cfg(feature = "delayed_ack")]
+    {
+        use std::env;
+        // We delay the send to have a delayed ack, the message is still displayed instantly
+        let delay_ms = env::var("DTCHAT_ACK_DELAY_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(500); // Default to 500ms
+        println!("delayed_ack : waiting {delay_ms} seconds before send");
+        std::thread::sleep(std::time::Duration::from_millis(delay_ms));
+    }
+
+    let mut errors = Vec::new();
+    let mut carried_by = Vec::new();
+    for endpoint in endpoints {
+        println!("Sending to {} via {endpoint}", receiver.name);
+        let result =
+            GenericSocket::new(&endpoint).and_then(|mut socket| socket.send_message(message));
+
+        match result {
+            Ok(_) => {
+                carried_by.push(endpoint);
+                if !redundant {
+                    break;
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to send via {endpoint}: {e}");
+                errors.push(format!("{endpoint}: {e}"));
+            }
+        }
+    }
+
+    if carried_by.is_empty() {
+        Err(errors.join(", "))
+    } else {
+        Ok(carried_by)
+    }
+}
+
+/// Release every due outbox entry whose next hop is reachable
+pub fn flush_outbox(model: &Arc<Mutex<ChatModel>>) {
+    let now = Utc::now();
+    let due = model.lock().unwrap().outbox.take_due(now);
+
+    for entry in due {
+        // Give up once the last allowed transmission is still without ACK
+        if model.lock().unwrap().retransmissions_exhausted(&entry) {
+            continue;
+        }
+        if let Some(control) = &entry.control {
+            flush_control(model, &entry, control, now);
+            continue;
+        }
+
+        // Build the message as it will leave now, or leave it queued while the contact is closed
+        let prepared = model.lock().unwrap().prepare_outbox_release(&entry, now);
+        let (message, receiver) = match prepared {
+            Ok(prepared) => prepared,
+            Err(retry_at) => {
+                model.lock().unwrap().outbox.postpone(
+                    &entry.message_uuid,
+                    &entry.receiver_uuid,
+                    retry_at,
+                    false,
+                );
+                continue;
+            }
+        };
+
+        // A message too big for a single bundle to its receiver goes in chunks, like a file
+        if model
+            .lock()
+            .unwrap()
+            .fragment_outbox_entry(&entry, &message, &receiver)
+        {
+            continue;
+        }
+
+        let result = transmit(&message, &receiver, entry.redundant);
+
+        let mut model = model.lock().unwrap();
+        match result {
+            Ok(carried_by) => {
+                let paths = carried_by
+                    .iter()
+                    .map(|endpoint| endpoint.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                model.outbox_sent(&entry, message, carried_by);
+                model.notify_observers(AppEvent::Sent(format!("Message sent via {paths}.")));
+            }
+            Err(e) => {
+                model.outbox_failed(&entry, &message, &e);
+                let delay = retry_delay(entry.attempts + 1);
+                model.outbox.postpone(
+                    &entry.message_uuid,
+                    &entry.receiver_uuid,
+                    Utc::now() + delay,
+                    true,
+                );
+                model.notify_observers(AppEvent::Error(format!(
+                    "Socket error: {e}, retrying in {}s",
+                    delay.num_seconds()
+                )));
+            }
+        }
+    }
+}
+
+/// Send a control entry once the contact to the next hop is open, retrying on failure
+fn flush_control(
+    model: &Arc<Mutex<ChatModel>>,
+    entry: &OutboxEntry,
+    control: &OutboxControl,
+    now: DateTime<Utc>,
+) {
+    let prepared = model
+        .lock()
+        .unwrap()
+        .prepare_control_release(entry, control, now);
+    let (buf, receiver) = match prepared {
+        Ok(prepared) => prepared,
+        Err(retry_at) => {
+            model.lock().unwrap().outbox.postpone(
+                &entry.message_uuid,
+                &entry.receiver_uuid,
+                retry_at,
+                false,
+            );
+            return;
+        }
+    };
+
+    let sent = send_to_peer(&receiver, &buf);
+
+    let mut model = model.lock().unwrap();
+    if sent {
+        println!("Sent {} to {}", control.describe(), receiver.name);
+        model
+            .outbox
+            .remove(&entry.message_uuid, &entry.receiver_uuid);
+    } else {
+        let delay = retry_delay(entry.attempts + 1);
+        model.outbox.postpone(
+            &entry.message_uuid,
+            &entry.receiver_uuid,
+            Utc::now() + delay,
+            true,
+        );
+        model.notify_observers(AppEvent::Error(format!(
+            "Could not send {} to {}, retrying in {}s",
+            control.describe(),
+            receiver.name,
+            delay.num_seconds()
+        )));
+    }
+}
+
+/// Flush the outbox in the background, now
+pub fn flush_outbox_now(model: Arc<Mutex<ChatModel>>) {
+    TOKIO_RUNTIME.spawn_blocking(move || flush_outbox(&model));
+}
+
+/// Periodically flush the outbox for as long as the application runs
+pub fn start_outbox_worker(model: Arc<Mutex<ChatModel>>) {
+    TOKIO_RUNTIME.spawn(async move {
+        let mut interval = tokio::time::interval(OUTBOX_TICK);
+        loop {
+            interval.tick().await;
+            let model = model.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || flush_outbox(&model)).await {
+                eprintln!("Outbox flush failed: {e}");
+            }
+        }
+    });
+}
@@ -1,58 +1,467 @@
 use a_sabr::{
     bundle::Bundle,
-    contact_manager::legacy::evl::EVLManager,
+    contact::Contact,
+    contact_manager::legacy::{eto::ETOManager, evl::EVLManager, qd::QDManager},
+    contact_manager::ContactManager,
     contact_plan::from_ion_file::IONContactPlan,
     node_manager::none::NoManagement,
     routing::aliases::build_generic_router,
-    routing::Router,
-    types::{Date, NodeID},
+    routing::{Router, RoutingOutput},
+    types::{Date, NodeID, Volume},
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use std::collections::HashMap;
-use std::io;
-use std::sync::{Mutex, RwLock};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
 
-use crate::utils::socket::Endpoint;
+use crate::app::ChatModel;
+use crate::utils::config::{BundleOverhead, ContactManagerKind, Peer, RoutingConfig};
+use crate::utils::socket::{dtn_node_name, Endpoint, TOKIO_RUNTIME};
+
+/// Fragments are never made smaller, whatever the residual volume
+pub const MIN_FRAGMENT_SIZE: usize = 1024;
+/// How often the contact plan file is checked for changes
+const CONTACT_PLAN_TICK: std::time::Duration = std::time::Duration::from_secs(2);
+/// Contacts to the first hop filled in turn by the fragments of a transfer, at most
+const MAX_FRAGMENT_CONTACTS: usize = 64;
+/// Steps of the search for the residual volume of a contact
+const RESIDUAL_SEARCH_STEPS: usize = 32;
+
+/// Routers known to `build_generic_router`, which panics on any other name
+pub const ROUTER_NAMES: [&str; 9] = [
+    "SpsnMpt",
+    "SpsnNodeGraph",
+    "SpsnContactGraph",
+    "CgrFirstEndingMpt",
+    "CgrFirstDepletedMpt",
+    "CgrFirstEndingNodeGraph",
+    "CgrFirstDepletedNodeGraph",
+    "CgrFirstEndingContactGraph",
+    "CgrFirstDepletedContactGraph",
+];
+
+/// Index of each ION node name of the contact plan in the router
+type NodeIndices = HashMap<String, NodeID>;
+/// First contact start and last contact end, relative to the start of the contact plan
+type PlanSpan = (Date, Date);
+
+// Parse the contact plan for the contact manager `$manager` and build the router on it,
+// along with the time span of the contacts and the index of each node name
+macro_rules! build_router {
+    ($manager:ty, $router:expr, $contact_plan:expr) => {{
+        let (nodes, contacts) = IONContactPlan::parse::<NoManagement, $manager>($contact_plan)?;
+        let node_ids: NodeIndices = nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node.get_node_name().to_string(), index as NodeID))
+            .collect();
+        let span = contacts.iter().fold(None, |span, contact| {
+            let (start, end) = (contact.info.start, contact.info.end);
+            Some(match span {
+                Some((first, last)) => (start.min(first), end.max(last)),
+                None => (start, end),
+            })
+        });
+        let router = build_generic_router::<NoManagement, $manager>($router, nodes, contacts, None);
+        let router: Box<dyn Router<NoManagement, $manager> + Send + Sync> =
+            unsafe { std::mem::transmute(router) };
+        (router, span, node_ids)
+    }};
+}
+
+/// Router of the contact plan, the contact manager is a type parameter of A-SABR
+enum RoutingEngine {
+    Evl(Box<dyn Router<NoManagement, EVLManager> + Send + Sync>),
+    Qd(Box<dyn Router<NoManagement, QDManager> + Send + Sync>),
+    Eto(Box<dyn Router<NoManagement, ETOManager> + Send + Sync>),
+}
+
+impl RoutingEngine {
+    /// The router, with the first contact start and last contact end of the plan
+    /// (relative to its start), None when it has no contact, and the index of each node
+    fn build(
+        routing: &RoutingConfig,
+        contact_plan: &str,
+    ) -> io::Result<(Self, Option<PlanSpan>, NodeIndices)> {
+        if !ROUTER_NAMES.contains(&routing.router.as_str()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown A-SABR router '{}'", routing.router),
+            ));
+        }
+        let router = routing.router.as_str();
+        Ok(match routing.contact_manager {
+            ContactManagerKind::Evl => {
+                let (router, span, node_ids) = build_router!(EVLManager, router, contact_plan);
+                (Self::Evl(router), span, node_ids)
+            }
+            ContactManagerKind::Qd => {
+                let (router, span, node_ids) = build_router!(QDManager, router, contact_plan);
+                (Self::Qd(router), span, node_ids)
+            }
+            ContactManagerKind::Eto => {
+                let (router, span, node_ids) = build_router!(ETOManager, router, contact_plan);
+                (Self::Eto(router), span, node_ids)
+            }
+        })
+    }
+}
+
+/// End of the transmission of `size` bytes of `bundle` over `contact` from `at`,
+/// None if the contact cannot take them
+fn transmission_end<CM: ContactManager>(
+    contact: &Contact<NoManagement, CM>,
+    bundle: &Bundle,
+    at: Date,
+    size: Volume,
+) -> Option<Date> {
+    let probe = Bundle {
+        source: bundle.source,
+        destinations: bundle.destinations.clone(),
+        priority: bundle.priority,
+        size,
+        expiration: bundle.expiration,
+    };
+    let tx = contact.manager.dry_run_tx(&contact.info, at, &probe)?;
+    Some(tx.tx_end)
+}
+
+/// Largest volume `contact` still takes from `at`, up to `limit`, as its contact manager sees
+/// it: the bundles already booked on the contact are counted
+fn residual_volume<CM: ContactManager>(
+    contact: &Contact<NoManagement, CM>,
+    bundle: &Bundle,
+    at: Date,
+    limit: Volume,
+) -> Volume {
+    let fits = |size: Volume| transmission_end(contact, bundle, at, size).is_some();
+    if limit <= 0.0 || fits(limit) {
+        return limit.max(0.0);
+    }
+    let (mut low, mut high) = (0.0, limit);
+    for _ in 0..RESIDUAL_SEARCH_STEPS {
+        let mid = (low + high) / 2.0;
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+/// Largest fragment, carried in `envelope` bytes of fields, fitting in `residual` bytes of a
+/// contact, from `MIN_FRAGMENT_SIZE` up to `max_fragment`
+fn fragment_size(
+    residual: Volume,
+    max_fragment: usize,
+    envelope: usize,
+    overhead: &BundleOverhead,
+) -> usize {
+    let fitting = (overhead.payload_in(residual) as usize).saturating_sub(envelope);
+    fitting.clamp(MIN_FRAGMENT_SIZE, max_fragment.max(MIN_FRAGMENT_SIZE))
+}
+
+/// Origin of the `+` times of the contact plan
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EpochSource {
+    /// `contact_plan_epoch` of the configuration
+    Configured,
+    /// Earliest `@yyyy/mm/dd-hh:mm:ss` time of the contact plan
+    ContactPlan,
+    /// Launch of DTChat, which differs on every node
+    AppStart,
+}
+
+impl EpochSource {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Configured => "set in the configuration",
+            Self::ContactPlan => "first absolute time of the contact plan",
+            Self::AppStart => "launch of DTChat",
+        }
+    }
+}
+
+/// Parse an epoch as RFC 3339, `YYYY-MM-DD HH:MM:SS` in UTC, or ION `[@]yyyy/mm/dd-hh:mm:ss`
+pub fn parse_epoch(input: &str) -> Option<DateTime<Utc>> {
+    let input = input.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S") {
+        return Some(time.and_utc());
+    }
+    ion_absolute_time(input.trim_start_matches('@'))
+}
+
+// `yyyy/mm/dd-hh:mm:ss`, the absolute time format of the ION commands
+fn ion_absolute_time(input: &str) -> Option<DateTime<Utc>> {
+    let time = NaiveDateTime::parse_from_str(input, "%Y/%m/%d-%H:%M:%S").ok()?;
+    Some(time.and_utc())
+}
+
+// Time fields of the `a contact` and `a range` lines
+fn plan_time_fields(fields: &[&str]) -> bool {
+    matches!(fields, ["a", "contact" | "range", _, _, ..])
+}
+
+/// Earliest `@` time of `content`, None when every time is relative
+fn first_absolute_time(content: &str) -> Option<DateTime<Utc>> {
+    content
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .filter(|fields| plan_time_fields(fields))
+        .flat_map(|fields| {
+            [fields[2], fields[3]].map(|f| f.strip_prefix('@').and_then(ion_absolute_time))
+        })
+        .flatten()
+        .min()
+}
+
+/// `content` with the `@` times made relative to `epoch`, None when it has none.
+/// A-SABR only reads relative times; times before the epoch are clamped to it.
+fn relative_plan(content: &str, epoch: DateTime<Utc>) -> Option<String> {
+    let mut changed = false;
+    let lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let mut fields: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+            let refs: Vec<&str> = fields.iter().map(String::as_str).collect();
+            if !plan_time_fields(&refs) {
+                return line.to_string();
+            }
+            let mut line_changed = false;
+            for field in &mut fields[2..4] {
+                if let Some(time) = field.strip_prefix('@').and_then(ion_absolute_time) {
+                    *field = format!("+{}", (time - epoch).num_seconds().max(0));
+                    line_changed = true;
+                }
+            }
+            if line_changed {
+                changed = true;
+                fields.join(" ")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    changed.then(|| lines.join("\n") + "\n")
+}
+
+/// Private copy of a contact plan read by A-SABR, removed when dropped
+struct PlanCopy(PathBuf);
+
+impl PlanCopy {
+    /// Write `content` to a new file of `dir`, an existing file is never opened
+    fn write(dir: &Path, file_name: &str, content: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut copy = 0;
+        loop {
+            let path = dir.join(format!("{}-{copy}-{file_name}", std::process::id()));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let plan = Self(path);
+                    file.write_all(content.as_bytes())?;
+                    return Ok(plan);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => copy += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn path(&self) -> String {
+        self.0.to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for PlanCopy {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            eprintln!("Failed to remove {}: {e}", self.0.display());
+        }
+    }
+}
+
+/// Where the contact plan is read from, kept to reload it
+#[derive(Clone, Debug)]
+pub struct ContactPlanSource {
+    /// ION `.rc` file
+    pub path: String,
+    /// dtn-scheme EID (or its node part) -> ION node name of the contact plan
+    pub dtn_node_map: HashMap<String, String>,
+    pub routing: RoutingConfig,
+    /// `contact_plan_epoch` of the configuration, see `parse_epoch`
+    pub epoch: Option<String>,
+    /// Where the snapshots of the plan read by A-SABR are written
+    pub copy_dir: PathBuf,
+}
 
 pub struct PredictionConfig {
-    ion_to_node_id: RwLock<HashMap<String, NodeID>>,
-    router: Mutex<Box<dyn Router<NoManagement, EVLManager> + Send + Sync>>,
+    ion_to_node_id: RwLock<NodeIndices>,
+    router: Mutex<RoutingEngine>,
+    /// Algorithm of `router`
+    routing: RoutingConfig,
+    /// Snapshot of the contact plan read by A-SABR, with relative times only
+    copy: PlanCopy,
+    /// UTC timestamp of the `+0` of the contact plan
     cp_start_time: f64,
+    epoch_source: EpochSource,
+    // dtn-scheme EID (or its node part) -> ION node name of the contact plan
+    dtn_node_map: HashMap<String, String>,
+    span: Option<PlanSpan>,
+}
+
+/// Whether the contact plan lets the local node reach a peer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reachability {
+    /// The first contact of the route is open until then
+    LinkUp(DateTime<Utc>),
+    /// The first contact of the route opens then
+    NextContact(DateTime<Utc>),
+    /// No route in the contact plan
+    Unreachable,
+}
+
+impl Reachability {
+    pub fn describe(&self, now: DateTime<Utc>) -> String {
+        match self {
+            Self::LinkUp(until) => format!("link up until {}", until.format("%H:%M")),
+            Self::NextContact(at) => format!("next contact in {}", format_delay(*at - now)),
+            Self::Unreachable => "no contact planned".to_string(),
+        }
+    }
+}
+
+fn format_delay(delay: chrono::Duration) -> String {
+    match delay.num_minutes() {
+        minutes if minutes < 1 => format!("{} s", delay.num_seconds().max(0)),
+        minutes if minutes < 120 => format!("{minutes} min"),
+        minutes => format!("{} h {} min", minutes / 60, minutes % 60),
+    }
+}
+
+/// How to split a transfer towards one destination
+#[derive(Clone, Copy, Debug)]
+pub struct FragmentPlan {
+    /// Largest fragment fitting the residual volume of the first contact
+    pub size: usize,
+    /// Predicted arrival of the last fragment
+    pub completion: DateTime<Utc>,
 }
 
 impl PredictionConfig {
-    pub fn new(contact_plan: &str) -> io::Result<Self> {
+    /// The `+0` of the plan is the epoch of `source`, else the earliest absolute time of
+    /// the plan, else `fallback_epoch` (the launch of DTChat)
+    pub fn new(source: &ContactPlanSource, fallback_epoch: DateTime<Utc>) -> io::Result<Self> {
+        let contact_plan = source.path.as_str();
         println!("RAW contact plan : ");
         println!("{contact_plan}");
 
-        let (nodes, contacts) = IONContactPlan::parse::<NoManagement, EVLManager>(contact_plan)?;
+        let content = fs::read_to_string(contact_plan)?;
+        let configured = match &source.epoch {
+            Some(epoch) => Some(parse_epoch(epoch).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid contact plan epoch '{epoch}'"),
+                )
+            })?),
+            None => None,
+        };
+        let (epoch, epoch_source) = match (configured, first_absolute_time(&content)) {
+            (Some(epoch), _) => (epoch, EpochSource::Configured),
+            (None, Some(first)) => (first, EpochSource::ContactPlan),
+            (None, None) => {
+                eprintln!("No contact plan epoch configured, +0 is the launch of DTChat");
+                (fallback_epoch, EpochSource::AppStart)
+            }
+        };
 
-        let ion_to_node_id = Self::map_node_indices(contact_plan)?;
+        // A-SABR reads a private copy of the content read above, so that the epoch, the nodes
+        // and the router all come from the same version of a plan that may be edited meanwhile,
+        // with the absolute times made relative to the epoch
+        let file_name = Path::new(contact_plan)
+            .file_name()
+            .map_or("contact_plan".into(), |name| name.to_string_lossy());
+        let content = relative_plan(&content, epoch).unwrap_or(content);
+        let copy = PlanCopy::write(&source.copy_dir, &file_name, &content)?;
 
         // Generate the router
-        let router = build_generic_router::<NoManagement, EVLManager>(
-            "CgrFirstEndingContactGraph",
-            nodes,
-            contacts,
-            None,
-        );
+        let (router, span, ion_to_node_id) = RoutingEngine::build(&source.routing, &copy.path())?;
 
-        let router: Box<dyn Router<NoManagement, EVLManager> + Send + Sync> =
-            unsafe { std::mem::transmute(router) };
-
-        let cp_start_time = Utc::now().timestamp() as f64;
+        let cp_start_time = epoch.timestamp() as f64;
 
         Ok(PredictionConfig {
             ion_to_node_id: RwLock::new(ion_to_node_id),
             router: Mutex::new(router),
+            routing: source.routing.clone(),
+            copy,
             cp_start_time,
+            epoch_source,
+            dtn_node_map: source.dtn_node_map.clone(),
+            span,
         })
     }
 
+    /// UTC time of the `+0` of the contact plan, and where it comes from
+    pub fn epoch(&self) -> (DateTime<Utc>, EpochSource) {
+        (
+            PredictionConfig::f64_to_utc(self.cp_start_time),
+            self.epoch_source,
+        )
+    }
+
+    /// First contact start and last contact end of the plan
+    pub fn plan_span(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let (start, end) = self.span?;
+        Some((
+            PredictionConfig::f64_to_utc(start + self.cp_start_time),
+            PredictionConfig::f64_to_utc(end + self.cp_start_time),
+        ))
+    }
+
+    /// Warning when `now` falls outside the time span of the contact plan
+    pub fn time_warning(&self, now: DateTime<Utc>) -> Option<String> {
+        let format = |t: DateTime<Utc>| t.format("%Y-%m-%d %H:%M:%S UTC").to_string();
+        match self.plan_span() {
+            None => Some("The contact plan has no contact".to_string()),
+            Some((start, _)) if now < start => {
+                Some(format!("The contact plan starts at {}", format(start)))
+            }
+            Some((_, end)) if now > end => {
+                Some(format!("The contact plan ended at {}", format(end)))
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Algorithm producing the predictions
+    pub fn routing(&self) -> &RoutingConfig {
+        &self.routing
+    }
+
+    /// Rebuild the router with another algorithm, the current one is kept on error
+    pub fn set_routing(&mut self, routing: RoutingConfig) -> io::Result<()> {
+        let (router, _, _) = RoutingEngine::build(&routing, &self.copy.path())?;
+        *self.router.lock().unwrap() = router;
+        self.routing = routing;
+        Ok(())
+    }
+
     pub fn get_node_id(&self, ion_id: &str) -> Option<NodeID> {
         self.ion_to_node_id.read().unwrap().get(ion_id).copied()
     }
 
+    /// UTC time of a time of the contact plan
+    fn plan_time(&self, time: Date) -> DateTime<Utc> {
+        Self::f64_to_utc(time + self.cp_start_time)
+    }
+
     pub fn f64_to_utc(timestamp: f64) -> DateTime<Utc> {
         let secs = timestamp.trunc() as i64;
         let nsecs = ((timestamp.fract()) * 1_000_000_000.0).round() as u32;
@@ -60,7 +469,7 @@ impl PredictionConfig {
         DateTime::from_naive_utc_and_offset(naive.naive_utc(), Utc)
     }
 
-    pub fn extract_ion_node_from_endpoint(endpoint: &Endpoint) -> Option<String> {
+    pub fn extract_ion_node_from_endpoint(&self, endpoint: &Endpoint) -> Option<String> {
         match endpoint {
             Endpoint::Bp(bp_address) => {
                 // Handle ipn: format (e.g., "ipn:10.1" -> "10")
@@ -69,6 +478,17 @@ impl PredictionConfig {
                         return Some(after_ipn[..dot_pos].to_string());
                     }
                 }
+                // Handle dtn: format through the configured map, by full EID first then by node
+                // (e.g., "dtn://vm2.local/dtchat" -> map["vm2.local"])
+                if bp_address.starts_with("dtn:") {
+                    return self
+                        .dtn_node_map
+                        .get(bp_address.as_str())
+                        .or_else(|| {
+                            dtn_node_name(bp_address).and_then(|node| self.dtn_node_map.get(node))
+                        })
+                        .cloned();
+                }
                 if bp_address.chars().all(|c| c.is_ascii_digit()) {
                     return Some(bp_address.clone());
                 }
@@ -78,17 +498,162 @@ impl PredictionConfig {
         }
     }
 
-    pub fn map_node_indices(contact_plan: &str) -> io::Result<HashMap<String, NodeID>> {
-        let (nodes, _contacts) = IONContactPlan::parse::<NoManagement, EVLManager>(contact_plan)?;
-        let node_index_map: HashMap<String, NodeID> = nodes
+    /// ION node name of the first BP endpoint of `peer` that maps to the contact plan
+    pub fn ion_node_for_peer(&self, peer: &Peer) -> Option<String> {
+        peer.endpoints
             .iter()
-            .enumerate()
-            .map(|(index, node)| (node.get_node_name().to_string(), index as NodeID))
-            .collect();
-        Ok(node_index_map)
+            .find_map(|endpoint| self.extract_ion_node_from_endpoint(endpoint))
+    }
+
+    pub fn predict(
+        &self,
+        source_ion: &str,
+        dest_ion: &str,
+        message_size: f64,
+    ) -> io::Result<DateTime<Utc>> {
+        self.predict_at(source_ion, dest_ion, message_size, Utc::now())
+    }
+
+    /// Arrival of a bundle handed to the source at `send_time` rather than now,
+    /// e.g. the ACK of a message sent back from its predicted arrival
+    pub fn predict_at(
+        &self,
+        source_ion: &str,
+        dest_ion: &str,
+        message_size: f64,
+        send_time: DateTime<Utc>,
+    ) -> io::Result<DateTime<Utc>> {
+        Ok(self
+            .route_at(source_ion, dest_ion, message_size, send_time, 0.0)?
+            .arrival)
+    }
+
+    /// Whether the first contact of the route to `dest_ion` is open now, and if not when it opens
+    pub fn next_hop_open(
+        &self,
+        source_ion: &str,
+        dest_ion: &str,
+        message_size: f64,
+    ) -> io::Result<Result<(), DateTime<Utc>>> {
+        let route = self.route(source_ion, dest_ion, message_size)?;
+        if route.first_hop_start <= Utc::now() {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(route.first_hop_start))
+        }
+    }
+
+    /// Volume the first contact of the route to `dest_ion` still takes now for bundles of
+    /// at least `message_size`, up to `limit`; zero while that contact is not open yet
+    pub fn next_hop_volume(
+        &self,
+        source_ion: &str,
+        dest_ion: &str,
+        message_size: f64,
+        limit: Volume,
+    ) -> io::Result<Volume> {
+        let now = Utc::now();
+        let route = self.route_at(source_ion, dest_ion, message_size, now, limit)?;
+        Ok(if route.first_hop_start <= now {
+            route.residual
+        } else {
+            0.0
+        })
+    }
+
+    /// State of the first contact of the route to `dest_ion`
+    pub fn reachability(&self, source_ion: &str, dest_ion: &str) -> io::Result<Reachability> {
+        match self.route(source_ion, dest_ion, 0.0) {
+            Ok(route) if route.first_hop_start <= Utc::now() => {
+                Ok(Reachability::LinkUp(route.first_hop_end))
+            }
+            Ok(route) => Ok(Reachability::NextContact(route.first_hop_start)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Reachability::Unreachable),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Size the fragments of a `total_size` bytes transfer to the residual volume A-SABR keeps
+    /// for the first contact towards `dest_ion`, and predict when the last one arrives.
+    /// Later hops store and forward each fragment on their own.
+    pub fn plan_fragments(
+        &self,
+        source_ion: &str,
+        dest_ion: &str,
+        total_size: usize,
+        max_fragment: usize,
+        envelope: usize,
+        overhead: &BundleOverhead,
+    ) -> io::Result<FragmentPlan> {
+        // Bytes on the link for a fragment of `size` bytes, carried in `envelope` bytes of fields
+        let on_link = |size: usize| overhead.bundle_size(size + envelope);
+        let now = Utc::now();
+        // Route the smallest fragment, then size them to what its first contact still takes
+        let first = self.route_at(
+            source_ion,
+            dest_ion,
+            on_link(MIN_FRAGMENT_SIZE),
+            now,
+            on_link(max_fragment),
+        )?;
+        let size = fragment_size(first.residual, max_fragment, envelope, overhead);
+
+        // The contacts to the first hop take the fragments ahead of the last one in turn,
+        // each up to its residual volume
+        let fragments = total_size.div_ceil(size).max(1);
+        let mut ahead = (fragments - 1) as f64 * on_link(size);
+        let mut send_time = now;
+        for _ in 0..MAX_FRAGMENT_CONTACTS {
+            if ahead <= 0.0 {
+                break;
+            }
+            let route = self.route_at(source_ion, dest_ion, on_link(size), send_time, ahead)?;
+            match route.backlog_sent {
+                Some(sent) => {
+                    send_time = sent;
+                    ahead = 0.0;
+                }
+                None => {
+                    ahead -= route.residual;
+                    send_time = route
+                        .first_hop_end
+                        .max(send_time + chrono::Duration::seconds(1));
+                }
+            }
+        }
+        if ahead > 0.0 {
+            return Err(io::Error::other(format!(
+                "Not enough contact volume from ION {source_ion} for {total_size} bytes"
+            )));
+        }
+
+        // The last fragment then takes the path A-SABR plans for it
+        let last = self.route_at(source_ion, dest_ion, on_link(size), send_time, 0.0)?;
+        Ok(FragmentPlan {
+            size,
+            completion: last.arrival,
+        })
     }
 
-    pub fn predict(&self, source_ion: &str, dest_ion: &str, message_size: f64) -> io::Result<Date> {
+    fn route(
+        &self,
+        source_ion: &str,
+        dest_ion: &str,
+        message_size: f64,
+    ) -> io::Result<RoutePrediction> {
+        self.route_at(source_ion, dest_ion, message_size, Utc::now(), 0.0)
+    }
+
+    /// Route a bundle handed to the source at `send_time`, with the residual volume of the
+    /// first hop checked for `backlog` bytes
+    fn route_at(
+        &self,
+        source_ion: &str,
+        dest_ion: &str,
+        message_size: f64,
+        send_time: DateTime<Utc>,
+        backlog: Volume,
+    ) -> io::Result<RoutePrediction> {
         let source_node_id = self.get_node_id(source_ion).ok_or_else(|| {
             io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -113,14 +678,52 @@ impl PredictionConfig {
 
         let excluded_nodes = vec![];
 
-        let cp_send_time = Utc::now().timestamp() as f64 - self.cp_start_time;
+        let cp_send_time = send_time.timestamp() as f64 - self.cp_start_time;
 
         let mut router = self.router.lock().unwrap();
-        match router.route(bundle.source, &bundle, cp_send_time, &excluded_nodes) {
+        let (source, time) = (bundle.source, cp_send_time);
+        match &mut *router {
+            RoutingEngine::Evl(router) => self.route_prediction(
+                router.route(source, &bundle, time, &excluded_nodes),
+                source_ion,
+                dest_ion,
+                &bundle,
+                cp_send_time,
+                backlog,
+            ),
+            RoutingEngine::Qd(router) => self.route_prediction(
+                router.route(source, &bundle, time, &excluded_nodes),
+                source_ion,
+                dest_ion,
+                &bundle,
+                cp_send_time,
+                backlog,
+            ),
+            RoutingEngine::Eto(router) => self.route_prediction(
+                router.route(source, &bundle, time, &excluded_nodes),
+                source_ion,
+                dest_ion,
+                &bundle,
+                cp_send_time,
+                backlog,
+            ),
+        }
+    }
+
+    fn route_prediction<CM: ContactManager>(
+        &self,
+        output: Option<RoutingOutput<NoManagement, CM>>,
+        source_ion: &str,
+        dest_ion: &str,
+        bundle: &Bundle,
+        cp_send_time: Date,
+        backlog: Volume,
+    ) -> io::Result<RoutePrediction> {
+        match output {
             Some(routing_output) => {
                 println!("Route found from ION {source_ion} to ION {dest_ion}!");
                 // Only display the last element
-                if let Some((_contact_ptr, (_contact, route_stages))) =
+                if let Some((_contact_ptr, (first_hop, route_stages))) =
                     routing_output.first_hops.iter().last()
                 {
                     if let Some(last_stage) = route_stages.last() {
@@ -141,7 +744,20 @@ impl PredictionConfig {
                         println!("cp_send_time is {cp_send_time}");
                         println!("the delay in seconds is : {delay}");
 
-                        return Ok(delay + self.cp_start_time);
+                        let first_hop = first_hop.borrow();
+                        let info = &first_hop.info;
+                        let residual = residual_volume(&first_hop, bundle, cp_send_time, backlog);
+                        let backlog_sent = (residual >= backlog)
+                            .then(|| transmission_end(&first_hop, bundle, cp_send_time, backlog))
+                            .flatten()
+                            .map(|end| self.plan_time(end));
+                        return Ok(RoutePrediction {
+                            arrival: self.plan_time(delay),
+                            first_hop_start: self.plan_time(info.start),
+                            first_hop_end: self.plan_time(info.end),
+                            residual,
+                            backlog_sent,
+                        });
                     }
                 }
                 Err(io::Error::other(
@@ -158,3 +774,181 @@ impl PredictionConfig {
         }
     }
 }
+
+/// Outcome of a route computation, times in UTC
+struct RoutePrediction {
+    arrival: DateTime<Utc>,
+    first_hop_start: DateTime<Utc>,
+    first_hop_end: DateTime<Utc>,
+    /// Volume the first hop still takes from the send time, up to the backlog asked for
+    residual: Volume,
+    /// When the first hop has sent the backlog, None if it goes past the residual volume
+    backlog_sent: Option<DateTime<Utc>>,
+}
+
+/// Load the contact plan of `model` again and swap it in, router and node map together.
+/// The parsing runs without the model lock; on error the current plan is kept.
+pub fn reload_contact_plan(model: &Arc<Mutex<ChatModel>>) {
+    let Some((source, fallback_epoch)) = model.lock().unwrap().contact_plan_to_load() else {
+        return;
+    };
+    // A-SABR may panic on a malformed plan
+    let loaded = panic::catch_unwind(AssertUnwindSafe(|| {
+        PredictionConfig::new(&source, fallback_epoch)
+    }))
+    .unwrap_or_else(|_| Err(io::Error::other("the contact plan parser panicked")))
+    .map_err(|e| format!("{}: {e}", source.path));
+    model.lock().unwrap().apply_contact_plan(loaded);
+}
+
+fn modified_at(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reload the contact plan when its file changes
+pub fn start_contact_plan_watcher(model: Arc<Mutex<ChatModel>>) {
+    let Some(path) = model
+        .lock()
+        .unwrap()
+        .contact_plan
+        .as_ref()
+        .map(|s| s.path.clone())
+    else {
+        return;
+    };
+    TOKIO_RUNTIME.spawn(async move {
+        let mut interval = tokio::time::interval(CONTACT_PLAN_TICK);
+        let mut loaded = modified_at(&path);
+        let mut seen = loaded;
+        loop {
+            interval.tick().await;
+            let modified = modified_at(&path);
+            // Wait for the time to settle, so a file being written is not read half-way
+            if modified != loaded && modified == seen && modified.is_some() {
+                loaded = modified;
+                let model = model.clone();
+                let reload = move || reload_contact_plan(&model);
+                if let Err(e) = tokio::task::spawn_blocking(reload).await {
+                    eprintln!("Contact plan reload failed: {e}");
+                }
+            }
+            seen = modified;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(input: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn fragment_size_follows_the_residual_volume() {
+        let overhead = BundleOverhead::default();
+        let on_link = |size: usize| overhead.bundle_size(size + 100);
+        assert_eq!(
+            fragment_size(on_link(60_000), 60_000, 100, &overhead),
+            60_000
+        );
+        assert_eq!(
+            fragment_size(on_link(90_000), 60_000, 100, &overhead),
+            60_000
+        );
+        let size = fragment_size(on_link(20_000), 60_000, 100, &overhead);
+        assert!((19_999..=20_000).contains(&size), "{size}");
+        assert_eq!(
+            fragment_size(on_link(10), 60_000, 100, &overhead),
+            MIN_FRAGMENT_SIZE
+        );
+    }
+
+    #[test]
+    fn fragments_shrink_to_a_small_first_contact() {
+        let dir = std::env::temp_dir().join(format!("dtchat-fragments-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // 10 s at 2000 B/s hold 20 kB, less than a fragment of 60 kB
+        let plan = dir.join("plan.rc");
+        fs::write(
+            &plan,
+            "\
+a contact +0 +10 1 2 2000
+a range +0 +10 1 2 1
+a contact +60 +86400 1 2 100000
+a range +60 +86400 1 2 1
+",
+        )
+        .unwrap();
+        let source = ContactPlanSource {
+            path: plan.to_string_lossy().into_owned(),
+            dtn_node_map: HashMap::new(),
+            routing: RoutingConfig::default(),
+            epoch: None,
+            copy_dir: dir.clone(),
+        };
+        let config = PredictionConfig::new(&source, Utc::now()).unwrap();
+
+        let overhead = BundleOverhead::default();
+        let fragments = config.plan_fragments("1", "2", 200_000, 60_000, 100, &overhead);
+        drop(config);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let fragments = fragments.unwrap();
+        assert!(fragments.size < 20_000, "{}", fragments.size);
+        assert!(fragments.size >= MIN_FRAGMENT_SIZE);
+    }
+
+    #[test]
+    fn parse_epoch_formats() {
+        let epoch = utc("2025-10-01 12:00:00");
+        assert_eq!(parse_epoch("2025-10-01T12:00:00Z"), Some(epoch));
+        assert_eq!(parse_epoch("2025-10-01T14:00:00+02:00"), Some(epoch));
+        assert_eq!(parse_epoch(" 2025-10-01 12:00:00 "), Some(epoch));
+        assert_eq!(parse_epoch("2025/10/01-12:00:00"), Some(epoch));
+        assert_eq!(parse_epoch("@2025/10/01-12:00:00"), Some(epoch));
+        assert_eq!(parse_epoch("+60"), None);
+        assert_eq!(parse_epoch("2025-10-01"), None);
+    }
+
+    #[test]
+    fn first_absolute_time_of_contacts_and_ranges() {
+        let content = "\
+# a contact @2025/01/01-00:00:00 +10 1 2 100
+a contact +0 +3600 1 2 100
+a range @2025/10/01-12:10:00 @2025/10/01-13:00:00 1 2 1
+a contact @2025/10/01-12:00:00 +7200 2 1 100
+";
+        assert_eq!(
+            first_absolute_time(content),
+            Some(utc("2025-10-01 12:00:00"))
+        );
+        assert_eq!(first_absolute_time("a contact +0 +3600 1 2 100\n"), None);
+    }
+
+    #[test]
+    fn relative_plan_from_epoch() {
+        let epoch = utc("2025-10-01 12:00:00");
+        let content = "\
+# plan
+a contact @2025/10/01-12:00:00 @2025/10/01-13:00:00 1 2 100
+a range +10 @2025/10/01-12:01:00 1 2 1
+a contact @2025/10/01-11:00:00 +3600 2 1 100
+";
+        assert_eq!(
+            relative_plan(content, epoch).as_deref(),
+            Some(
+                "\
+# plan
+a contact +0 +3600 1 2 100
+a range +10 +60 1 2 1
+a contact +0 +3600 2 1 100
+"
+            )
+        );
+        assert_eq!(relative_plan("a contact +0 +3600 1 2 100\n", epoch), None);
+    }
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+use crate::utils::proto::dtchat_proto::{presence_update, PresenceUpdate};
+use crate::utils::proto::{dtchat_proto, Content};
+
+/// Availability announced by a peer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Presence {
+    #[default]
+    Unknown,
+    Online,
+    Away,
+    Busy,
+    Offline,
+}
+
+impl Presence {
+    /// Presences the local user can pick
+    pub const SELECTABLE: [Presence; 4] = [Self::Online, Self::Away, Self::Busy, Self::Offline];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Unknown => "Unknown",
+            Self::Online => "Online",
+            Self::Away => "Away",
+            Self::Busy => "Busy",
+            Self::Offline => "Offline",
+        }
+    }
+
+    pub fn color(&self) -> egui::Color32 {
+        match self {
+            Self::Online => egui::Color32::GREEN,
+            Self::Away => egui::Color32::YELLOW,
+            Self::Busy => egui::Color32::RED,
+            Self::Unknown | Self::Offline => egui::Color32::GRAY,
+        }
+    }
+}
+
+impl From<presence_update::Status> for Presence {
+    fn from(status: presence_update::Status) -> Self {
+        match status {
+            presence_update::Status::Unknown => Self::Unknown,
+            presence_update::Status::Online => Self::Online,
+            presence_update::Status::Away => Self::Away,
+            presence_update::Status::Busy => Self::Busy,
+            presence_update::Status::Offline => Self::Offline,
+        }
+    }
+}
+
+impl From<Presence> for presence_update::Status {
+    fn from(presence: Presence) -> Self {
+        match presence {
+            Presence::Unknown => Self::Unknown,
+            Presence::Online => Self::Online,
+            Presence::Away => Self::Away,
+            Presence::Busy => Self::Busy,
+            Presence::Offline => Self::Offline,
+        }
+    }
+}
+
+/// Announce of `presence`, picked at `at` (milliseconds since the UTC epoch) so that the
+/// receiver orders the announces however late they arrive
+pub fn create_presence_message(
+    uuid: &str,
+    presence: Presence,
+    at: i64,
+    local_peer_uuid: &str,
+) -> dtchat_proto::ProtoMessage {
+    dtchat_proto::ProtoMessage {
+        uuid: uuid.to_string(),
+        sender_uuid: local_peer_uuid.to_string(),
+        timestamp: at,
+        room_uuid: String::new(),
+        content: Some(Content::Presence(PresenceUpdate {
+            status: presence_update::Status::from(presence) as i32,
+        })),
+    }
+}
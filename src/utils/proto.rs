@@ -1,9 +1,14 @@
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use chrono::{DateTime, Utc};
+use std::io::{self, Error, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use uuid::Uuid;
 
-use super::config::Peer;
+use super::config::{Peer, Room};
+use super::file_transfer::{file_label, FileChunk};
 use super::message::{ChatMessage, MessageStatus};
+use super::presence::Presence;
+use super::rooms::RoomAction;
 
 pub mod dtchat_proto {
     include!(concat!(env!("OUT_DIR"), "/dtchat.rs"));
@@ -11,14 +16,131 @@ pub mod dtchat_proto {
 
 pub use dtchat_proto::proto_message::Content;
 
+/// Version of the wire framing, sent as the first byte of every frame.
+pub const FRAME_VERSION: u8 = 1;
+/// Frame header: version byte followed by the payload length as a big-endian u32.
+pub const FRAME_HEADER_LEN: usize = 5;
+/// Largest UDP payload over IPv4; a datagram carries exactly one frame.
+pub const MAX_DATAGRAM_SIZE: usize = 65507;
+pub const DEFAULT_MAX_FRAME_SIZE: usize = MAX_DATAGRAM_SIZE - FRAME_HEADER_LEN;
+
+static MAX_FRAME_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_FRAME_SIZE);
+
+/// Set the largest payload (in bytes) accepted by `encode_frame` and the listeners.
+/// Any message may go over UDP, so the frame is clamped to a datagram.
+pub fn set_max_frame_size(size: usize) {
+    if size > DEFAULT_MAX_FRAME_SIZE {
+        eprintln!(
+            "Maximum message size {size} does not fit in a UDP datagram, using {DEFAULT_MAX_FRAME_SIZE}"
+        );
+    }
+    MAX_FRAME_SIZE.store(size.min(DEFAULT_MAX_FRAME_SIZE), Ordering::Relaxed);
+}
+
+pub fn max_frame_size() -> usize {
+    MAX_FRAME_SIZE.load(Ordering::Relaxed)
+}
+
+/// Prefix `payload` with the frame header.
+pub fn encode_frame(payload: &[u8]) -> io::Result<Bytes> {
+    if payload.len() > max_frame_size() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Payload of {} bytes exceeds the maximum frame size ({} bytes)",
+                payload.len(),
+                max_frame_size()
+            ),
+        ));
+    }
+
+    let mut buf = BytesMut::with_capacity(FRAME_HEADER_LEN + payload.len());
+    buf.put_u8(FRAME_VERSION);
+    buf.put_u32(payload.len() as u32);
+    buf.put_slice(payload);
+    Ok(buf.freeze())
+}
+
+/// Validate a frame header and return the length of the payload that follows it.
+pub fn decode_frame_header(header: &[u8; FRAME_HEADER_LEN]) -> io::Result<usize> {
+    if header[0] != FRAME_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported frame version: {}", header[0]),
+        ));
+    }
+
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > max_frame_size() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Frame of {len} bytes exceeds the maximum frame size ({} bytes)",
+                max_frame_size()
+            ),
+        ));
+    }
+    Ok(len)
+}
+
+/// Extract the payload of a frame received as a single datagram (UDP or BP).
+pub fn decode_frame(datagram: &[u8]) -> io::Result<&[u8]> {
+    let header: &[u8; FRAME_HEADER_LEN] = datagram
+        .get(..FRAME_HEADER_LEN)
+        .and_then(|h| h.try_into().ok())
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Datagram shorter than header"))?;
+
+    let len = decode_frame_header(header)?;
+    let payload = &datagram[FRAME_HEADER_LEN..];
+    if payload.len() != len {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Frame announces {len} bytes but datagram carries {}",
+                payload.len()
+            ),
+        ));
+    }
+    Ok(payload)
+}
+
 #[derive(Debug)]
 pub enum DeserializedMessage {
-    ChatMessage(ChatMessage),
+    ChatMessage(Box<ChatMessage>),
     Ack {
         message_uuid: String,
+        /// UUID of the peer acknowledging the message
+        ack_sender: String,
         is_read: bool,
         ack_time: DateTime<Utc>,
     },
+    /// The sender of `message_uuid`, a file, must send all its chunks again
+    Nack {
+        message_uuid: String,
+        nack_sender: String,
+    },
+    AckBatch {
+        ack_sender: String,
+        /// (message UUID, read, ACK time) of every delivery in the batch
+        acks: Vec<(String, bool, DateTime<Utc>)>,
+    },
+    FileChunk {
+        /// Message standing for the file, with the UUID of the chunk
+        message: Box<ChatMessage>,
+        chunk: FileChunk,
+    },
+    Presence {
+        sender_uuid: String,
+        presence: Presence,
+        /// Time the peer announced it
+        at: DateTime<Utc>,
+    },
+    RoomControl {
+        sender_uuid: String,
+        action: RoomAction,
+        /// The room as seen by the sender, every participant included
+        room: Room,
+    },
 }
 
 pub fn serialize_message(message: &ChatMessage) -> Bytes {
@@ -49,6 +171,12 @@ pub fn generate_uuid() -> String {
     Uuid::new_v4().to_string()
 }
 
+/// Size of `message` once serialized, in bytes
+pub fn encoded_size(message: &ChatMessage) -> usize {
+    use prost::Message;
+    construct_proto_message(message).encoded_len()
+}
+
 fn construct_proto_message(message: &ChatMessage) -> dtchat_proto::ProtoMessage {
     let (tx_time, _, _) = message.get_timestamps();
 
@@ -64,7 +192,7 @@ fn construct_proto_message(message: &ChatMessage) -> dtchat_proto::ProtoMessage
         uuid: message.uuid.clone(),
         sender_uuid: message.sender.uuid.clone(),
         timestamp: tx_time as i64,
-        room_uuid: "default".to_string(),
+        room_uuid: message.room_uuid.clone(),
         content,
     }
 }
@@ -81,36 +209,183 @@ fn extract_message_from_proto(
 
     // Handle ACK messages separately
     if let Content::Delivery(delivery_status) = &content {
+        if !delivery_status.received {
+            return Some(DeserializedMessage::Nack {
+                message_uuid: delivery_status.message_uuid.clone(),
+                nack_sender: proto.sender_uuid.clone(),
+            });
+        }
         let ack_time = Utc.timestamp_millis_opt(proto.timestamp).single()?;
         return Some(DeserializedMessage::Ack {
             message_uuid: delivery_status.message_uuid.clone(),
+            ack_sender: proto.sender_uuid.clone(),
             is_read: delivery_status.read,
             ack_time,
         });
     }
 
+    if let Content::DeliveryBatch(batch) = &content {
+        let acks = batch
+            .deliveries
+            .iter()
+            .filter_map(|delivery| {
+                let ack_time = Utc.timestamp_millis_opt(delivery.timestamp).single()?;
+                Some((delivery.message_uuid.clone(), delivery.read, ack_time))
+            })
+            .collect();
+        return Some(DeserializedMessage::AckBatch {
+            ack_sender: proto.sender_uuid.clone(),
+            acks,
+        });
+    }
+
+    if let Content::Presence(update) = &content {
+        return Some(DeserializedMessage::Presence {
+            sender_uuid: proto.sender_uuid.clone(),
+            presence: update.status().into(),
+            at: Utc.timestamp_millis_opt(proto.timestamp).single()?,
+        });
+    }
+
+    if let Content::RoomControl(control) = &content {
+        return Some(DeserializedMessage::RoomControl {
+            sender_uuid: proto.sender_uuid.clone(),
+            action: control.action().into(),
+            room: Room {
+                uuid: proto.room_uuid.clone(),
+                name: control.room_name.clone(),
+                participants: control.participants.clone(),
+                retransmission: None,
+            },
+        });
+    }
+
     // Extract text based on the message type
     let (text, reply_to) = match &content {
         Content::Text(text_msg) => (text_msg.content.clone(), text_msg.reply_to_uuid.clone()),
-        Content::File(_) => (
-            "File transfer (not implemented for display)".to_string(),
+        Content::File(file) => (
+            file_label(&file.filename, &file.mime_type, file.file_size),
             None,
         ),
-        Content::Presence(_) => (
-            "Presence update (not implemented for display)".to_string(),
-            None,
-        ),
-        Content::Delivery(_) => unreachable!(), // Already handled above
+        // Already handled above
+        Content::Presence(_)
+        | Content::Delivery(_)
+        | Content::DeliveryBatch(_)
+        | Content::RoomControl(_) => unreachable!(),
     };
 
     let tx_time = Utc.timestamp_millis_opt(proto.timestamp).single()?;
     let rx_time = Utc::now();
 
-    Some(DeserializedMessage::ChatMessage(ChatMessage {
+    let message = ChatMessage {
         uuid: proto.uuid,
         response: reply_to,
         sender,
         text,
         shipment_status: MessageStatus::Received(tx_time, rx_time),
-    }))
+        endpoint: None,
+        attempts: 0,
+        history: Vec::new(),
+        room_uuid: proto.room_uuid,
+        recipients: Vec::new(),
+        routing: None,
+        predicted_ack: None,
+    };
+
+    match content {
+        Content::File(file) => Some(DeserializedMessage::FileChunk {
+            message: Box::new(message),
+            chunk: FileChunk {
+                transfer_uuid: file.transfer_uuid,
+                filename: file.filename,
+                mime_type: file.mime_type,
+                file_size: file.file_size,
+                file_hash: file.file_hash,
+                index: file.chunk_index,
+                total_chunks: file.total_chunks,
+                data: file.chunk_data,
+            },
+        }),
+        _ => Some(DeserializedMessage::ChatMessage(Box::new(message))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(version: u8, len: u32) -> [u8; FRAME_HEADER_LEN] {
+        let [a, b, c, d] = len.to_be_bytes();
+        [version, a, b, c, d]
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = encode_frame(b"hello").unwrap();
+        assert_eq!(frame.len(), FRAME_HEADER_LEN + 5);
+        assert_eq!(decode_frame(&frame).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn empty_payload() {
+        let frame = encode_frame(&[]).unwrap();
+        assert!(decode_frame(&frame).unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncated_header() {
+        for len in 0..FRAME_HEADER_LEN {
+            let err = decode_frame(&header(FRAME_VERSION, 0)[..len]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let frame = encode_frame(b"hello").unwrap();
+        let err = decode_frame(&frame[..frame.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut frame = encode_frame(b"hello").unwrap().to_vec();
+        frame.push(0);
+        assert!(decode_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn wrong_version() {
+        let err = decode_frame_header(&header(FRAME_VERSION + 1, 0)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut frame = encode_frame(b"hello").unwrap().to_vec();
+        frame[0] = 0;
+        assert!(decode_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn oversize_length() {
+        let max = max_frame_size() as u32;
+        assert_eq!(
+            decode_frame_header(&header(FRAME_VERSION, max)).unwrap(),
+            max as usize
+        );
+        let err = decode_frame_header(&header(FRAME_VERSION, max + 1)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(decode_frame_header(&header(FRAME_VERSION, u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn largest_frame_fits_in_a_datagram() {
+        let frame = encode_frame(&vec![0u8; max_frame_size()]).unwrap();
+        assert!(frame.len() <= MAX_DATAGRAM_SIZE);
+    }
+
+    #[test]
+    fn oversize_payload_not_encoded() {
+        let payload = vec![0u8; max_frame_size() + 1];
+        let err = encode_frame(&payload).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
 }
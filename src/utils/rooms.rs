@@ -0,0 +1,162 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::utils::config::Room;
+use crate::utils::proto::dtchat_proto::{room_control, RoomControl};
+use crate::utils::proto::{dtchat_proto, Content};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoomAction {
+    Create,
+    Invite,
+    Leave,
+    Rename,
+    /// Sent to the participants removed from the room
+    Remove,
+}
+
+impl From<room_control::Action> for RoomAction {
+    fn from(action: room_control::Action) -> Self {
+        match action {
+            room_control::Action::Create => Self::Create,
+            room_control::Action::Invite => Self::Invite,
+            room_control::Action::Leave => Self::Leave,
+            room_control::Action::Rename => Self::Rename,
+            room_control::Action::Remove => Self::Remove,
+        }
+    }
+}
+
+impl From<RoomAction> for room_control::Action {
+    fn from(action: RoomAction) -> Self {
+        match action {
+            RoomAction::Create => Self::Create,
+            RoomAction::Invite => Self::Invite,
+            RoomAction::Leave => Self::Leave,
+            RoomAction::Rename => Self::Rename,
+            RoomAction::Remove => Self::Remove,
+        }
+    }
+}
+
+/// Rooms changed at runtime, applied over the configured ones
+#[derive(Default, Serialize, Deserialize)]
+struct StoredRooms {
+    /// Rooms created, joined or changed at runtime
+    #[serde(default)]
+    rooms: Vec<Room>,
+    /// UUIDs of the rooms left
+    #[serde(default)]
+    left: Vec<String>,
+}
+
+/// Room list of the local peer, runtime changes persisted to disk
+pub struct RoomStore {
+    path: PathBuf,
+    configured: Vec<Room>,
+    stored: StoredRooms,
+}
+
+impl RoomStore {
+    /// Load the runtime changes stored at `path` over the `configured` rooms
+    pub fn load(path: &Path, configured: Vec<Room>) -> Self {
+        let stored = match fs::read_to_string(path) {
+            Ok(content) => serde_yaml::from_str(&content).unwrap_or_else(|e| {
+                eprintln!("Failed to parse rooms {}: {e}", path.display());
+                StoredRooms::default()
+            }),
+            Err(_) => StoredRooms::default(),
+        };
+
+        Self {
+            path: path.to_path_buf(),
+            configured,
+            stored,
+        }
+    }
+
+    /// Configured rooms with the runtime changes applied
+    pub fn rooms(&self) -> Vec<Room> {
+        let mut rooms: Vec<Room> = self
+            .configured
+            .iter()
+            .map(|room| {
+                self.stored
+                    .rooms
+                    .iter()
+                    .find(|stored| stored.uuid == room.uuid)
+                    .unwrap_or(room)
+                    .clone()
+            })
+            .collect();
+        for stored in &self.stored.rooms {
+            if !rooms.iter().any(|room| room.uuid == stored.uuid) {
+                rooms.push(stored.clone());
+            }
+        }
+        rooms.retain(|room| !self.stored.left.contains(&room.uuid));
+        rooms
+    }
+
+    fn save(&self) {
+        let path = &self.path;
+        let result = serde_yaml::to_string(&self.stored)
+            .map_err(std::io::Error::other)
+            .and_then(|content| {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                // Write then rename so a crash never leaves a truncated file
+                let tmp_path = path.with_extension("tmp");
+                fs::write(&tmp_path, content)?;
+                fs::rename(&tmp_path, path)
+            });
+
+        if let Err(e) = result {
+            eprintln!("Failed to save rooms {}: {e}", path.display());
+        }
+    }
+
+    /// Record a room created, joined or changed at runtime
+    pub fn save_room(&mut self, room: &Room) {
+        self.stored.left.retain(|uuid| *uuid != room.uuid);
+        match self.stored.rooms.iter_mut().find(|r| r.uuid == room.uuid) {
+            Some(stored) => *stored = room.clone(),
+            None => self.stored.rooms.push(room.clone()),
+        }
+        self.save();
+    }
+
+    pub fn leave_room(&mut self, room_uuid: &str) {
+        self.stored.rooms.retain(|r| r.uuid != room_uuid);
+        if !self.stored.left.iter().any(|uuid| uuid == room_uuid) {
+            self.stored.left.push(room_uuid.to_string());
+        }
+        self.save();
+    }
+}
+
+pub fn create_room_control_message(
+    uuid: &str,
+    action: RoomAction,
+    room: &Room,
+    local_peer_uuid: &str,
+) -> dtchat_proto::ProtoMessage {
+    // The receivers see the sender as a participant, and themselves among the others
+    let mut participants = vec![local_peer_uuid.to_string()];
+    participants.extend(room.participants.iter().cloned());
+
+    dtchat_proto::ProtoMessage {
+        uuid: uuid.to_string(),
+        sender_uuid: local_peer_uuid.to_string(),
+        timestamp: Utc::now().timestamp_millis(),
+        room_uuid: room.uuid.clone(),
+        content: Some(Content::RoomControl(RoomControl {
+            action: room_control::Action::from(action) as i32,
+            room_name: room.name.clone(),
+            participants,
+        })),
+    }
+}
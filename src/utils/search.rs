@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::utils::message::ChatMessage;
+
+/// Lowercase words of `text`, the units matched by the search
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Criteria of a search, every set criterion must match
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchQuery {
+    /// Words the message must contain, the last one may be incomplete
+    pub text: String,
+    pub sender_uuid: Option<String>,
+    pub room_uuid: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub status: Option<&'static str>,
+}
+
+impl SearchQuery {
+    pub fn is_empty(&self) -> bool {
+        *self == SearchQuery::default()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchHit {
+    pub uuid: String,
+    pub room_uuid: String,
+    pub sender_uuid: String,
+    pub tx: DateTime<Utc>,
+    pub text: String,
+}
+
+struct IndexedMessage {
+    room_uuid: String,
+    sender_uuid: String,
+    tx: DateTime<Utc>,
+    status: &'static str,
+    text: String,
+    words: HashSet<String>,
+}
+
+/// Word index of the messages, updated as they change
+#[derive(Default)]
+pub struct SearchIndex {
+    messages: HashMap<String, IndexedMessage>,
+    /// Message UUIDs per word, sorted to find the words starting with a prefix
+    words: BTreeMap<String, HashSet<String>>,
+    revision: u64,
+}
+
+impl SearchIndex {
+    /// Changes each time a message is indexed, to know when results are outdated
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn room_of(&self, uuid: &str) -> Option<&str> {
+        self.messages.get(uuid).map(|m| m.room_uuid.as_str())
+    }
+
+    /// Index `message`, or refresh it if already indexed
+    pub fn update(&mut self, message: &ChatMessage) {
+        self.revision += 1;
+        let words: HashSet<String> = tokenize(&message.text).collect();
+        if let Some(previous) = self.messages.get(&message.uuid) {
+            for word in previous.words.difference(&words) {
+                if let Some(uuids) = self.words.get_mut(word) {
+                    uuids.remove(&message.uuid);
+                    if uuids.is_empty() {
+                        self.words.remove(word);
+                    }
+                }
+            }
+        }
+        for word in &words {
+            self.words
+                .entry(word.clone())
+                .or_default()
+                .insert(message.uuid.clone());
+        }
+        self.messages.insert(
+            message.uuid.clone(),
+            IndexedMessage {
+                room_uuid: message.room_uuid.clone(),
+                sender_uuid: message.sender.uuid.clone(),
+                tx: message.shipment_status.tx(),
+                status: message.shipment_status.label(),
+                text: message.text.clone(),
+                words,
+            },
+        );
+    }
+
+    // Messages holding a word starting with `prefix`
+    fn with_prefix(&self, prefix: &str) -> HashSet<&str> {
+        self.words
+            .range(prefix.to_string()..)
+            .take_while(|(word, _)| word.starts_with(prefix))
+            .flat_map(|(_, uuids)| uuids.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Messages matching `query`, latest first, at most `limit` of them,
+    /// with the number of matches
+    pub fn search(&self, query: &SearchQuery, limit: usize) -> (Vec<SearchHit>, usize) {
+        let words: Vec<String> = tokenize(&query.text).collect();
+        // Only the messages holding every word are checked against the other criteria
+        let candidates: Vec<&str> = if words.is_empty() {
+            self.messages.keys().map(String::as_str).collect()
+        } else {
+            let mut sets: Vec<HashSet<&str>> =
+                words.iter().map(|word| self.with_prefix(word)).collect();
+            sets.sort_by_key(HashSet::len);
+            let (smallest, others) = sets.split_first().expect("at least one word");
+            smallest
+                .iter()
+                .filter(|uuid| others.iter().all(|set| set.contains(*uuid)))
+                .copied()
+                .collect()
+        };
+
+        let mut matches: Vec<(&str, &IndexedMessage)> = candidates
+            .into_iter()
+            .map(|uuid| (uuid, &self.messages[uuid]))
+            .filter(|(_, message)| {
+                query
+                    .sender_uuid
+                    .as_ref()
+                    .is_none_or(|sender| message.sender_uuid == *sender)
+                    && query
+                        .room_uuid
+                        .as_ref()
+                        .is_none_or(|room| message.room_uuid == *room)
+                    && query.from.is_none_or(|from| message.tx >= from)
+                    && query.to.is_none_or(|to| message.tx <= to)
+                    && query.status.is_none_or(|status| message.status == status)
+            })
+            .collect();
+        let total = matches.len();
+        matches.sort_by_key(|(_, message)| std::cmp::Reverse(message.tx));
+        let hits = matches
+            .into_iter()
+            .take(limit)
+            .map(|(uuid, message)| SearchHit {
+                uuid: uuid.to_string(),
+                room_uuid: message.room_uuid.clone(),
+                sender_uuid: message.sender_uuid.clone(),
+                tx: message.tx,
+                text: message.text.clone(),
+            })
+            .collect();
+        (hits, total)
+    }
+}
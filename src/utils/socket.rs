@@ -1,11 +1,19 @@
-use crate::utils::ack::{self};
-use crate::utils::config::Peer;
+use crate::utils::ack::{self, AckBatcher};
+use crate::utils::config::{Peer, Room};
+use crate::utils::file_transfer::FileChunk;
 use crate::utils::message::ChatMessage;
-use crate::utils::proto::{deserialize_message, serialize_message, DeserializedMessage};
+use crate::utils::presence::Presence;
+use crate::utils::proto::{
+    decode_frame, decode_frame_header, deserialize_message, encode_frame, max_frame_size,
+    serialize_message, DeserializedMessage, FRAME_HEADER_LEN,
+};
+use crate::utils::rooms::RoomAction;
+use chrono::{DateTime, Utc};
 use libc::{self, c_int};
 use once_cell::sync::Lazy;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, Error, ErrorKind, Read, Write};
 use std::mem::ManuallyDrop;
 use std::sync::{Arc, Mutex};
@@ -18,7 +26,7 @@ const AF_BP: c_int = 28;
 pub static TOKIO_RUNTIME: Lazy<Runtime> =
     Lazy::new(|| Runtime::new().expect("Failed to create Tokio runtime"));
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(tag = "type", content = "address")] // Internally tagged enum
 pub enum Endpoint {
     Udp(String),
@@ -26,7 +34,34 @@ pub enum Endpoint {
     Bp(String),
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum EndpointKind {
+    Udp,
+    Tcp,
+    Bp,
+}
+
+impl EndpointKind {
+    pub const ALL: [EndpointKind; 3] = [Self::Udp, Self::Tcp, Self::Bp];
+}
+
 impl Endpoint {
+    pub fn new(kind: EndpointKind, address: String) -> Self {
+        match kind {
+            EndpointKind::Udp => Endpoint::Udp(address),
+            EndpointKind::Tcp => Endpoint::Tcp(address),
+            EndpointKind::Bp => Endpoint::Bp(address),
+        }
+    }
+
+    pub fn kind(&self) -> EndpointKind {
+        match self {
+            Endpoint::Udp(_) => EndpointKind::Udp,
+            Endpoint::Tcp(_) => EndpointKind::Tcp,
+            Endpoint::Bp(_) => EndpointKind::Bp,
+        }
+    }
+
     /// Check if this endpoint is valid and can be used for socket operations
     pub fn is_valid(&self) -> bool {
         match self {
@@ -44,8 +79,18 @@ impl Endpoint {
     }
 }
 
+/// Maximum length of a `dtn:` EID in `sockaddr_bp`, including the trailing NUL.
+const BP_DTN_EID_MAX_LEN: usize = 64;
+
+/// Build the `struct sockaddr_bp` of the bp-socket kernel module (DTN-MTP/bp-socket):
+/// `sa_family_t bp_family`, the `bp_scheme` enum, then the `bp_addr` union of the scheme.
+/// The address length passed to the kernel covers the member of the scheme only, so an
+/// `ipn:` address keeps the 16 bytes it has always had whatever the size of the union.
+/// The `dtn` member, a NUL-terminated EID of at most 64 bytes, and `BP_SCHEME_DTN` must
+/// follow the `bp_scheme_t` enum and `bp_addr` union of the module headers.
 fn create_bp_sockaddr_with_string(eid_string: &str) -> io::Result<SockAddr> {
     const BP_SCHEME_IPN: u32 = 1;
+    const BP_SCHEME_DTN: u32 = 2;
 
     #[repr(C)]
     struct SockAddrBp {
@@ -57,7 +102,7 @@ fn create_bp_sockaddr_with_string(eid_string: &str) -> io::Result<SockAddr> {
     #[repr(C)]
     union BpAddr {
         ipn: ManuallyDrop<IpnAddr>,
-        // Extend with other schemes like DTN if needed
+        dtn: ManuallyDrop<DtnAddr>,
     }
 
     #[repr(C)]
@@ -66,6 +111,16 @@ fn create_bp_sockaddr_with_string(eid_string: &str) -> io::Result<SockAddr> {
         service_id: u32,
     }
 
+    #[repr(C)]
+    struct DtnAddr {
+        endpoint_id: [u8; BP_DTN_EID_MAX_LEN],
+    }
+
+    const IPN_ADDR_LEN: usize = mem::offset_of!(SockAddrBp, bp_addr) + mem::size_of::<IpnAddr>();
+    const DTN_ADDR_LEN: usize = mem::offset_of!(SockAddrBp, bp_addr) + mem::size_of::<DtnAddr>();
+    // Size of `sockaddr_bp` before the dtn scheme, still expected for ipn addresses
+    const _: () = assert!(IPN_ADDR_LEN == 16);
+
     if eid_string.is_empty() {
         return Err(Error::new(
             ErrorKind::InvalidInput,
@@ -74,7 +129,7 @@ fn create_bp_sockaddr_with_string(eid_string: &str) -> io::Result<SockAddr> {
     }
 
     // ---- Handle "ipn:" scheme ----
-    if let Some(eid_body) = eid_string.strip_prefix("ipn:") {
+    let (sockaddr_bp, addr_len) = if let Some(eid_body) = eid_string.strip_prefix("ipn:") {
         let parts: Vec<&str> = eid_body.split('.').collect();
         if parts.len() != 2 {
             return Err(Error::new(
@@ -100,31 +155,70 @@ fn create_bp_sockaddr_with_string(eid_string: &str) -> io::Result<SockAddr> {
                 }),
             },
         };
+        (sockaddr_bp, IPN_ADDR_LEN)
+    }
+    // ---- Handle "dtn:" scheme ----
+    else if eid_string.starts_with("dtn:") {
+        if dtn_node_name(eid_string).is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid DTN EID format: {eid_string}"),
+            ));
+        }
 
-        let mut sockaddr_storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
-        unsafe {
-            ptr::copy_nonoverlapping(
-                &sockaddr_bp as *const SockAddrBp as *const std::ffi::c_void,
-                &mut sockaddr_storage as *mut _ as *mut std::ffi::c_void,
-                mem::size_of::<SockAddrBp>(),
-            );
+        let bytes = eid_string.as_bytes();
+        // Keep room for the NUL terminator expected by the kernel module
+        if bytes.len() >= BP_DTN_EID_MAX_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "DTN EID longer than {} bytes: {eid_string}",
+                    BP_DTN_EID_MAX_LEN - 1
+                ),
+            ));
         }
 
-        let addr_len = mem::size_of::<SockAddrBp>() as libc::socklen_t;
-        let address = unsafe { SockAddr::new(sockaddr_storage, addr_len) };
-        Ok(address)
+        let mut endpoint_id = [0u8; BP_DTN_EID_MAX_LEN];
+        endpoint_id[..bytes.len()].copy_from_slice(bytes);
+
+        let sockaddr_bp = SockAddrBp {
+            bp_family: AF_BP as libc::sa_family_t,
+            bp_scheme: BP_SCHEME_DTN,
+            bp_addr: BpAddr {
+                dtn: ManuallyDrop::new(DtnAddr { endpoint_id }),
+            },
+        };
+        (sockaddr_bp, DTN_ADDR_LEN)
     }
-    // ---- Handle unsupported or unimplemented schemes ----
-    else if eid_string.starts_with("dtn:") {
-        Err(Error::new(
-            ErrorKind::Unsupported,
-            "DTN scheme not yet implemented",
-        ))
-    } else {
-        Err(Error::new(
+    // ---- Handle unsupported schemes ----
+    else {
+        return Err(Error::new(
             ErrorKind::InvalidInput,
             format!("Unsupported scheme in EID: {eid_string}"),
-        ))
+        ));
+    };
+
+    let mut sockaddr_storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    unsafe {
+        ptr::copy_nonoverlapping(
+            &sockaddr_bp as *const SockAddrBp as *const std::ffi::c_void,
+            &mut sockaddr_storage as *mut _ as *mut std::ffi::c_void,
+            addr_len,
+        );
+    }
+
+    let address = unsafe { SockAddr::new(sockaddr_storage, addr_len as libc::socklen_t) };
+    Ok(address)
+}
+
+/// Node part of a `dtn://node/service` EID (e.g. "vm2.local" for "dtn://vm2.local/dtchat")
+pub fn dtn_node_name(eid: &str) -> Option<&str> {
+    let body = eid.strip_prefix("dtn://")?;
+    let node = body.split('/').next()?;
+    if node.is_empty() {
+        None
+    } else {
+        Some(node)
     }
 }
 
@@ -184,14 +278,16 @@ impl GenericSocket {
         })
     }
 
+    /// Send `data` as a single frame (see `proto::encode_frame`).
     pub fn send(&mut self, data: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let frame = encode_frame(data)?;
         match self.eidpoint {
             Endpoint::Bp(_) | Endpoint::Udp(_) => {
-                self.socket.send_to(data, &self.sockaddr.clone())?;
+                self.socket.send_to(&frame, &self.sockaddr.clone())?;
             }
             Endpoint::Tcp(_) => {
                 self.socket.connect(&self.sockaddr.clone())?;
-                self.socket.write_all(data)?;
+                self.socket.write_all(&frame)?;
                 self.socket.flush()?;
                 self.socket.shutdown(std::net::Shutdown::Both)?;
             }
@@ -216,47 +312,33 @@ impl GenericSocket {
         match &self.eidpoint {
             Endpoint::Udp(addr) | Endpoint::Bp(addr) => {
                 let address = addr.clone();
+                let endpoint = self.eidpoint.clone();
 
                 TOKIO_RUNTIME.spawn_blocking({
                     let mut socket = self.socket.try_clone()?; // Clone the socket for the async thread
                     move || {
+                        // One datagram carries exactly one frame, so size the buffer for the largest one
+                        let mut buffer = vec![0u8; FRAME_HEADER_LEN + max_frame_size()];
                         loop {
-                            let mut buffer: [u8; 1024] = [0; 1024];
                             match socket.read(&mut buffer) {
                                 Ok(size) => {
                                     println!(
                                         "UDP/BP received {size} bytes on listening address {address}"
                                     );
-                                     for b in &buffer[0..size] {
-                                        print!("{b}");
-                                     };
+                                    let datagram = buffer[..size].to_vec();
                                     let new_controller_arc = Arc::clone(&controller_arc);
-                                    let address_clone = address.clone();
+                                    let endpoint_clone = endpoint.clone();
                                     TOKIO_RUNTIME.spawn(async move {
-                                        let controller = new_controller_arc.lock().unwrap();
-                                        let peers = controller.get_peers();
-                                        let endpoint_type = if address_clone.starts_with("ipn:") || address_clone.starts_with("dtn:") {
-                                            Endpoint::Bp(address_clone.clone())
-                                        } else {
-                                            Endpoint::Udp(address_clone.clone())
-                                        };
-                                        if let Some(deserialized) =
-                                            deserialize_message(&buffer[1..((buffer[0] as usize) + 1)], &peers)
-                                        {
-                                            match deserialized {
-                                            DeserializedMessage::ChatMessage(message) => {
-                                                println!("📨 Received message: '{}' from {}", message.text, message.sender.name);
-                                                controller.send_ack_if_needed_with_endpoint_info(&message, Some(&endpoint_type));
-                                                controller.notify_observers(message);
+                                        match decode_frame(&datagram) {
+                                            Ok(payload) => {
+                                                let mut controller = new_controller_arc.lock().unwrap();
+                                                controller.handle_payload(payload, Some(&endpoint_clone));
                                             }
-                                            DeserializedMessage::Ack { message_uuid, is_read, ack_time } => {
-                                                println!("✅ Received ACK for message {} (read: {}) at {}",
-                                                    message_uuid, is_read, ack_time.format("%H:%M:%S"));
-                                                controller.handle_ack_received(&message_uuid, is_read, ack_time);
+                                            Err(e) => {
+                                                eprintln!("Dropping malformed frame on {endpoint_clone}: {e}");
                                             }
                                         }
-                                    }
-                                });
+                                    });
                                 }
                                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                                     thread::sleep(std::time::Duration::from_millis(10));
@@ -281,8 +363,8 @@ impl GenericSocket {
                                 println!("TCP received data on listening address {address}");
                                 let new_controller_arc = Arc::clone(&controller_arc);
 
-                                TOKIO_RUNTIME.spawn(async move {
-                                    handle_tcp_connection(stream.into(), new_controller_arc).await;
+                                TOKIO_RUNTIME.spawn_blocking(move || {
+                                    handle_tcp_connection(stream.into(), new_controller_arc);
                                 });
                             }
                             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -302,52 +384,50 @@ impl GenericSocket {
     }
 }
 
-async fn handle_tcp_connection(
+/// Read frames from an accepted TCP stream until the peer closes it.
+fn handle_tcp_connection(
     mut stream: std::net::TcpStream,
     controller_arc: Arc<Mutex<DefaultSocketController>>,
 ) {
-    let mut buffer = [0; 1024];
-    match stream.read(&mut buffer) {
-        Ok(_size) => {
-            let controller = controller_arc.lock().unwrap();
-            let peers = controller.get_peers();
-
-            // Get the peer address to determine the endpoint
-            let peer_addr = stream.peer_addr().ok();
-            let tcp_endpoint = peer_addr.map(|addr| Endpoint::Tcp(addr.to_string()));
-
-            if let Some(deserialized) =
-                deserialize_message(&buffer[1..((buffer[0] as usize) + 1)], &peers)
-            {
-                match deserialized {
-                    DeserializedMessage::ChatMessage(message) => {
-                        println!(
-                            "📨 TCP Received message: '{}' from {}",
-                            message.text, message.sender.name
-                        );
-                        controller
-                            .send_ack_if_needed_with_endpoint_info(&message, tcp_endpoint.as_ref());
-                        controller.notify_observers(message);
-                    }
-                    DeserializedMessage::Ack {
-                        message_uuid,
-                        is_read,
-                        ack_time,
-                    } => {
-                        println!(
-                            "✅ TCP Received ACK for message {} (read: {}) at {}",
-                            message_uuid,
-                            is_read,
-                            ack_time.format("%H:%M:%S")
-                        );
-                        controller.handle_ack_received(&message_uuid, is_read, ack_time);
-                    }
-                }
+    // The listening socket is non-blocking, which accepted streams may inherit
+    if let Err(e) = stream.set_nonblocking(false) {
+        eprintln!("TCP Error: {e}");
+        return;
+    }
+
+    // Get the peer address to determine the endpoint
+    let tcp_endpoint = stream
+        .peer_addr()
+        .ok()
+        .map(|addr| Endpoint::Tcp(addr.to_string()));
+
+    loop {
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        match stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                eprintln!("TCP Read Error: {e}");
+                break;
             }
         }
-        Err(e) => {
+
+        let len = match decode_frame_header(&header) {
+            Ok(len) => len,
+            Err(e) => {
+                eprintln!("Dropping TCP connection, malformed frame: {e}");
+                break;
+            }
+        };
+
+        let mut payload = vec![0u8; len];
+        if let Err(e) = stream.read_exact(&mut payload) {
             eprintln!("TCP Read Error: {e}");
+            break;
         }
+
+        let mut controller = controller_arc.lock().unwrap();
+        controller.handle_payload(&payload, tcp_endpoint.as_ref());
     }
 }
 
@@ -366,11 +446,88 @@ pub trait SocketObserver: Send + Sync {
     fn on_ack_received(
         &self,
         message_uuid: &str,
+        ack_sender_uuid: &str,
         is_read: bool,
         ack_time: chrono::DateTime<chrono::Utc>,
     ) {
         // Default implementation does nothing
-        let _ = (message_uuid, is_read, ack_time);
+        let _ = (message_uuid, ack_sender_uuid, is_read, ack_time);
+    }
+    /// Called for every copy of a file chunk received, returns false if it must not be ACKed
+    fn on_file_chunk(&self, message: ChatMessage, chunk: FileChunk) -> bool {
+        // Default implementation does nothing
+        let _ = (message, chunk);
+        true
+    }
+    /// Called when a peer could not put the file `message_uuid` back together
+    fn on_nack(&self, message_uuid: &str, nack_sender_uuid: &str) {
+        // Default implementation does nothing
+        let _ = (message_uuid, nack_sender_uuid);
+    }
+    /// Called when a peer announces its presence
+    fn on_presence(&self, sender_uuid: &str, presence: Presence, at: DateTime<Utc>) {
+        // Default implementation does nothing
+        let _ = (sender_uuid, presence, at);
+    }
+    /// Called when a peer creates, joins, leaves or renames a room
+    fn on_room_control(&self, sender_uuid: &str, action: RoomAction, room: Room) {
+        // Default implementation does nothing
+        let _ = (sender_uuid, action, room);
+    }
+    /// Called for every copy of a message received, with all the arrivals recorded so far
+    fn on_path_arrival(&self, message_uuid: &str, arrivals: &[PathArrival]) {
+        // Default implementation does nothing
+        let _ = (message_uuid, arrivals);
+    }
+}
+
+/// One copy of a message received on a given path
+#[derive(Clone, Debug)]
+pub struct PathArrival {
+    pub endpoint: Option<Endpoint>,
+    pub rx_time: DateTime<Utc>,
+}
+
+const DEDUP_CACHE_CAPACITY: usize = 4096;
+
+/// Remembers recently received message UUIDs and the path of each copy, oldest evicted first
+#[derive(Default)]
+pub struct DedupCache {
+    arrivals: HashMap<String, Vec<PathArrival>>,
+    order: VecDeque<String>,
+}
+
+impl DedupCache {
+    /// Record a copy of `uuid`, returns true if it is the first one
+    pub fn record(&mut self, uuid: &str, arrival: PathArrival) -> bool {
+        if let Some(arrivals) = self.arrivals.get_mut(uuid) {
+            arrivals.push(arrival);
+            return false;
+        }
+        self.insert(uuid, vec![arrival]);
+        true
+    }
+
+    /// Replace the copies recorded for `uuid`
+    pub fn set(&mut self, uuid: &str, arrivals: Vec<PathArrival>) {
+        match self.arrivals.get_mut(uuid) {
+            Some(recorded) => *recorded = arrivals,
+            None => self.insert(uuid, arrivals),
+        }
+    }
+
+    fn insert(&mut self, uuid: &str, arrivals: Vec<PathArrival>) {
+        if self.order.len() >= DEDUP_CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.arrivals.remove(&oldest);
+            }
+        }
+        self.order.push_back(uuid.to_string());
+        self.arrivals.insert(uuid.to_string(), arrivals);
+    }
+
+    pub fn arrivals(&self, uuid: &str) -> &[PathArrival] {
+        self.arrivals.get(uuid).map(Vec::as_slice).unwrap_or(&[])
     }
 }
 
@@ -384,10 +541,96 @@ impl SocketController for DefaultSocketController {
     }
 }
 
+/// Endpoint of `sender_peer` to ACK a message received on `received_on_endpoint`
+pub fn choose_ack_endpoint(
+    sender_peer: &Peer,
+    received_on_endpoint: Option<&Endpoint>,
+) -> Endpoint {
+    // If we know which endpoint the message was received on, try to find a compatible one
+    if let Some(received_endpoint) = received_on_endpoint {
+        // For BP messages, prefer BP endpoints for ACK
+        if matches!(received_endpoint, Endpoint::Bp(_)) {
+            if let Some(bp_endpoint) = sender_peer
+                .endpoints
+                .iter()
+                .find(|ep| matches!(ep, Endpoint::Bp(_)))
+            {
+                return bp_endpoint.clone();
+            }
+        }
+
+        // For TCP/UDP, try to use the same protocol if available
+        match received_endpoint {
+            Endpoint::Tcp(_) => {
+                if let Some(tcp_endpoint) = sender_peer
+                    .endpoints
+                    .iter()
+                    .find(|ep| matches!(ep, Endpoint::Tcp(_)))
+                {
+                    return tcp_endpoint.clone();
+                }
+            }
+            Endpoint::Udp(_) => {
+                if let Some(udp_endpoint) = sender_peer
+                    .endpoints
+                    .iter()
+                    .find(|ep| matches!(ep, Endpoint::Udp(_)))
+                {
+                    return udp_endpoint.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Fallback: prioritize BP > TCP > UDP for ACK reliability
+    for endpoint in &sender_peer.endpoints {
+        match endpoint {
+            Endpoint::Bp(_) if endpoint.is_valid() => return endpoint.clone(),
+            _ => {}
+        }
+    }
+    for endpoint in &sender_peer.endpoints {
+        match endpoint {
+            Endpoint::Tcp(_) if endpoint.is_valid() => return endpoint.clone(),
+            _ => {}
+        }
+    }
+    for endpoint in &sender_peer.endpoints {
+        match endpoint {
+            Endpoint::Udp(_) if endpoint.is_valid() => return endpoint.clone(),
+            _ => {}
+        }
+    }
+
+    // Ultimate fallback: first valid endpoint
+    sender_peer
+        .endpoints
+        .iter()
+        .find(|ep| ep.is_valid())
+        .unwrap_or(&sender_peer.endpoints[0])
+        .clone()
+}
+
+/// Send `data` to `peer` through its endpoints in priority order, until one succeeds
+pub fn send_to_peer(peer: &Peer, data: &[u8]) -> bool {
+    peer.send_endpoints().into_iter().any(|endpoint| {
+        match GenericSocket::new(&endpoint).and_then(|mut socket| socket.send(data)) {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("Failed to send to {} via {endpoint}: {e}", peer.name);
+                false
+            }
+        }
+    })
+}
+
 pub struct DefaultSocketController {
     observers: Vec<Arc<dyn SocketObserver + Send + Sync>>,
     local_peer: Option<Peer>,
     peers: Vec<Peer>,
+    received: DedupCache,
+    ack_batcher: Option<Arc<Mutex<AckBatcher>>>,
 }
 
 impl DefaultSocketController {
@@ -396,6 +639,8 @@ impl DefaultSocketController {
             observers: Vec::new(),
             local_peer: None,
             peers: Vec::new(),
+            received: DedupCache::default(),
+            ack_batcher: None,
         }
     }
 
@@ -403,14 +648,15 @@ impl DefaultSocketController {
         self.peers = peers;
     }
 
-    pub fn get_peers(&self) -> Vec<Peer> {
-        self.peers.clone()
-    }
-
     pub fn set_local_peer(&mut self, peer: Peer) {
         self.local_peer = Some(peer);
     }
 
+    /// Aggregate the delivery ACKs in `batcher` instead of sending them one by one
+    pub fn set_ack_batcher(&mut self, batcher: Arc<Mutex<AckBatcher>>) {
+        self.ack_batcher = Some(batcher);
+    }
+
     pub fn send_ack_if_needed_with_endpoint_info(
         &self,
         message: &ChatMessage,
@@ -436,7 +682,20 @@ impl DefaultSocketController {
                     sender_peer.name, sender_peer.uuid
                 );
 
-                let target_endpoint = self.choose_ack_endpoint(sender_peer, received_on_endpoint);
+                if let Some(batcher) = &self.ack_batcher {
+                    let batch = batcher.lock().unwrap().push(
+                        &sender_peer.uuid,
+                        received_on_endpoint,
+                        &message.uuid,
+                        false,
+                    );
+                    if let Some(batch) = batch {
+                        ack::send_ack_batch(batch, sender_peer, &local_peer.uuid);
+                    }
+                    return;
+                }
+
+                let target_endpoint = choose_ack_endpoint(sender_peer, received_on_endpoint);
                 println!(
                     "🎯 Sending ACK to {} via {}",
                     sender_peer.name, target_endpoint
@@ -471,75 +730,128 @@ impl DefaultSocketController {
         }
     }
 
-    fn choose_ack_endpoint(
-        &self,
-        sender_peer: &Peer,
-        received_on_endpoint: Option<&Endpoint>,
-    ) -> Endpoint {
-        // If we know which endpoint the message was received on, try to find a compatible one
-        if let Some(received_endpoint) = received_on_endpoint {
-            // For BP messages, prefer BP endpoints for ACK
-            if matches!(received_endpoint, Endpoint::Bp(_)) {
-                if let Some(bp_endpoint) = sender_peer
-                    .endpoints
-                    .iter()
-                    .find(|ep| matches!(ep, Endpoint::Bp(_)))
-                {
-                    return bp_endpoint.clone();
+    /// Decode one frame payload and dispatch it to the observers.
+    pub fn handle_payload(&mut self, payload: &[u8], received_on_endpoint: Option<&Endpoint>) {
+        let Some(deserialized) = deserialize_message(payload, &self.peers) else {
+            eprintln!("Failed to decode {} byte payload", payload.len());
+            return;
+        };
+
+        match deserialized {
+            DeserializedMessage::ChatMessage(message) => {
+                let mut message = *message;
+                message.endpoint = received_on_endpoint.cloned();
+
+                let arrival = PathArrival {
+                    endpoint: received_on_endpoint.cloned(),
+                    rx_time: Utc::now(),
+                };
+                let first_copy = self.received.record(&message.uuid, arrival);
+
+                if first_copy {
+                    println!(
+                        "📨 Received message: '{}' from {}",
+                        message.text, message.sender.name
+                    );
+                    self.send_ack_if_needed_with_endpoint_info(&message, received_on_endpoint);
+                    self.notify_observers(message.clone());
+                } else {
+                    println!(
+                        "♻️ Dropping duplicate of message {} received via {}",
+                        message.uuid,
+                        received_on_endpoint
+                            .map_or("unknown endpoint".to_string(), |e| e.to_string())
+                    );
+                    // The copy may be a retransmission because our first ACK got lost
+                    self.send_ack_if_needed_with_endpoint_info(&message, received_on_endpoint);
                 }
-            }
 
-            // For TCP/UDP, try to use the same protocol if available
-            match received_endpoint {
-                Endpoint::Tcp(_) => {
-                    if let Some(tcp_endpoint) = sender_peer
-                        .endpoints
-                        .iter()
-                        .find(|ep| matches!(ep, Endpoint::Tcp(_)))
-                    {
-                        return tcp_endpoint.clone();
-                    }
+                let arrivals = self.received.arrivals(&message.uuid);
+                for observer in &self.observers {
+                    observer.on_path_arrival(&message.uuid, arrivals);
                 }
-                Endpoint::Udp(_) => {
-                    if let Some(udp_endpoint) = sender_peer
-                        .endpoints
-                        .iter()
-                        .find(|ep| matches!(ep, Endpoint::Udp(_)))
-                    {
-                        return udp_endpoint.clone();
-                    }
+            }
+            DeserializedMessage::FileChunk { message, chunk } => {
+                let mut message = *message;
+                message.endpoint = received_on_endpoint.cloned();
+
+                let arrival = PathArrival {
+                    endpoint: received_on_endpoint.cloned(),
+                    rx_time: Utc::now(),
+                };
+                if self.received.record(&message.uuid, arrival) {
+                    println!(
+                        "📦 Received chunk {}/{} of {} from {}",
+                        chunk.index + 1,
+                        chunk.total_chunks,
+                        chunk.filename,
+                        message.sender.name
+                    );
+                }
+                // Every copy stored is ACKed, the sender resends the chunks left without ACK.
+                // Copies go through too: the chunks of a file sent again after a NACK keep
+                // their UUIDs.
+                let mut stored = true;
+                for observer in &self.observers {
+                    stored &= observer.on_file_chunk(message.clone(), chunk.clone());
+                }
+                if stored {
+                    self.send_ack_if_needed_with_endpoint_info(&message, received_on_endpoint);
                 }
-                _ => {}
             }
-        }
-
-        // Fallback: prioritize BP > TCP > UDP for ACK reliability
-        for endpoint in &sender_peer.endpoints {
-            match endpoint {
-                Endpoint::Bp(_) if endpoint.is_valid() => return endpoint.clone(),
-                _ => {}
+            DeserializedMessage::Ack {
+                message_uuid,
+                ack_sender,
+                is_read,
+                ack_time,
+            } => {
+                println!(
+                    "✅ Received ACK for message {} (read: {}) at {}",
+                    message_uuid,
+                    is_read,
+                    ack_time.format("%H:%M:%S")
+                );
+                self.handle_ack_received(&message_uuid, &ack_sender, is_read, ack_time);
             }
-        }
-        for endpoint in &sender_peer.endpoints {
-            match endpoint {
-                Endpoint::Tcp(_) if endpoint.is_valid() => return endpoint.clone(),
-                _ => {}
+            DeserializedMessage::Nack {
+                message_uuid,
+                nack_sender,
+            } => {
+                println!("❎ Received NACK for file {message_uuid} from {nack_sender}");
+                for observer in &self.observers {
+                    observer.on_nack(&message_uuid, &nack_sender);
+                }
             }
-        }
-        for endpoint in &sender_peer.endpoints {
-            match endpoint {
-                Endpoint::Udp(_) if endpoint.is_valid() => return endpoint.clone(),
-                _ => {}
+            DeserializedMessage::AckBatch { ack_sender, acks } => {
+                println!("✅ Received ACK batch of {} deliveries", acks.len());
+                for (message_uuid, is_read, ack_time) in acks {
+                    self.handle_ack_received(&message_uuid, &ack_sender, is_read, ack_time);
+                }
+            }
+            DeserializedMessage::Presence {
+                sender_uuid,
+                presence,
+                at,
+            } => {
+                println!("👤 {sender_uuid} is now {}", presence.label());
+                for observer in &self.observers {
+                    observer.on_presence(&sender_uuid, presence, at);
+                }
+            }
+            DeserializedMessage::RoomControl {
+                sender_uuid,
+                action,
+                room,
+            } => {
+                println!(
+                    "🏠 Received {action:?} of room {} from {sender_uuid}",
+                    room.name
+                );
+                for observer in &self.observers {
+                    observer.on_room_control(&sender_uuid, action, room.clone());
+                }
             }
         }
-
-        // Ultimate fallback: first valid endpoint
-        sender_peer
-            .endpoints
-            .iter()
-            .find(|ep| ep.is_valid())
-            .unwrap_or(&sender_peer.endpoints[0])
-            .clone()
     }
 
     fn notify_observers(&self, message: ChatMessage) {
@@ -554,13 +866,14 @@ impl DefaultSocketController {
     pub fn handle_ack_received(
         &self,
         message_uuid: &str,
+        ack_sender_uuid: &str,
         is_read: bool,
         ack_time: chrono::DateTime<chrono::Utc>,
     ) {
-        println!("🔄 Processing ACK for message {message_uuid}");
+        println!("🔄 Processing ACK for message {message_uuid} from {ack_sender_uuid}");
         // Notify observers about the ACK so they can update message status
         for observer in &self.observers {
-            observer.on_ack_received(message_uuid, is_read, ack_time);
+            observer.on_ack_received(message_uuid, ack_sender_uuid, is_read, ack_time);
         }
     }
 
@@ -614,9 +927,6 @@ impl SendingSocket for GenericSocket {
         let serialized = serialize_message(message);
         self.send(&serialized)?;
         println!("serialized: {} bytes", serialized.len());
-        for b in &serialized {
-            print!("{b}");
-        }
         Ok(serialized.len())
     }
 }